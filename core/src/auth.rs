@@ -0,0 +1,780 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::lru::Lru;
+
+// SHA-256 is implemented here rather than with mbedTLS, so that the frames
+// can be sealed and opened, and this module tested, on the host too. It only
+// hashes a few short inputs per frame, hence speed does not matter, and it is
+// checked against the test vectors of FIPS 180-4 and RFC 4231.
+
+/// Initial hash values of SHA-256.
+const H0: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+/// Round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a_2f98,
+    0x7137_4491,
+    0xb5c0_fbcf,
+    0xe9b5_dba5,
+    0x3956_c25b,
+    0x59f1_11f1,
+    0x923f_82a4,
+    0xab1c_5ed5,
+    0xd807_aa98,
+    0x1283_5b01,
+    0x2431_85be,
+    0x550c_7dc3,
+    0x72be_5d74,
+    0x80de_b1fe,
+    0x9bdc_06a7,
+    0xc19b_f174,
+    0xe49b_69c1,
+    0xefbe_4786,
+    0x0fc1_9dc6,
+    0x240c_a1cc,
+    0x2de9_2c6f,
+    0x4a74_84aa,
+    0x5cb0_a9dc,
+    0x76f9_88da,
+    0x983e_5152,
+    0xa831_c66d,
+    0xb003_27c8,
+    0xbf59_7fc7,
+    0xc6e0_0bf3,
+    0xd5a7_9147,
+    0x06ca_6351,
+    0x1429_2967,
+    0x27b7_0a85,
+    0x2e1b_2138,
+    0x4d2c_6dfc,
+    0x5338_0d13,
+    0x650a_7354,
+    0x766a_0abb,
+    0x81c2_c92e,
+    0x9272_2c85,
+    0xa2bf_e8a1,
+    0xa81a_664b,
+    0xc24b_8b70,
+    0xc76c_51a3,
+    0xd192_e819,
+    0xd699_0624,
+    0xf40e_3585,
+    0x106a_a070,
+    0x19a4_c116,
+    0x1e37_6c08,
+    0x2748_774c,
+    0x34b0_bcb5,
+    0x391c_0cb3,
+    0x4ed8_aa4a,
+    0x5b9c_ca4f,
+    0x682e_6ff3,
+    0x748f_82ee,
+    0x78a5_636f,
+    0x84c8_7814,
+    0x8cc7_0208,
+    0x90be_fffa,
+    0xa450_6ceb,
+    0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+/// Size of a SHA-256 block, in bytes.
+const BLOCK: usize = 64;
+
+// The inputs of the HMACs start with a marker of their use, so that the
+// inputs of different uses never collide.
+
+/// Marks the tags of clear frames.
+const CLEAR: u8 = 0;
+/// Marks the tags of encrypted frames.
+const SECRET: u8 = 1;
+/// Marks the keystreams.
+const KEYSTREAM: u8 = 2;
+/// Marks the ephemeral identifiers.
+const EPHEMERAL: u8 = 3;
+/// Marks the nonces.
+const NONCE: u8 = 4;
+
+/// Size of a truncated tag, in bytes.
+pub const TAG_LEN: usize = 4;
+
+/// Size of a sealed frame without its payload: the counter and the tag.
+pub const OVERHEAD: usize = 4 + TAG_LEN;
+
+/// Size of the nonce of an encrypted frame, in bytes. The keystream is also
+/// seeded with the tag, so that frames only share a keystream if both their
+/// nonces and their tags collide.
+pub const NONCE_LEN: usize = 4;
+
+/// Maximum size of the payload of an encrypted frame, as long as a keystream
+/// block without the counter.
+pub const MAX_SECRET: usize = 32 - 4;
+
+/// Processes a block of SHA-256.
+///
+/// # Arguments
+/// * `state` - The hash state to update.
+/// * `block` - The block.
+// The names follow FIPS 180-4.
+#[allow(clippy::many_single_char_names)]
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0; 64];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7)
+            ^ w[i - 15].rotate_right(18)
+            ^ (w[i - 15] >> 3);
+        let s1 =
+            w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// Computes the SHA-256 digest of a message given in parts.
+///
+/// # Arguments
+/// * `parts` - The parts of the message, concatenated.
+///
+/// # Returns
+/// The digest.
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut state = H0;
+    let mut buf = [0; BLOCK];
+    let mut len = 0;
+    let mut total: u64 = 0;
+
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        buf[len] = *byte;
+        len += 1;
+        total += 8;
+        if len == BLOCK {
+            compress(&mut state, &buf);
+            len = 0;
+        }
+    }
+
+    // Pads with a one bit, zeros and the length in bits.
+    buf[len] = 0x80;
+    buf[len + 1..].fill(0);
+    if len + 1 > BLOCK - 8 {
+        compress(&mut state, &buf);
+        buf.fill(0);
+    }
+    buf[BLOCK - 8..].copy_from_slice(&total.to_be_bytes());
+    compress(&mut state, &buf);
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Computes the HMAC-SHA256 of a message given in parts.
+///
+/// # Arguments
+/// * `key` - The key, at most a block long.
+/// * `parts` - The parts of the message, concatenated.
+///
+/// # Returns
+/// The authentication code.
+#[must_use]
+pub fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut ipad = [0x36; BLOCK];
+    let mut opad = [0x5c; BLOCK];
+    for (i, byte) in key.iter().take(BLOCK).enumerate() {
+        ipad[i] ^= byte;
+        opad[i] ^= byte;
+    }
+
+    let mut inner = vec![&ipad[..]];
+    inner.extend_from_slice(parts);
+    let inner = sha256(&inner);
+
+    sha256(&[&opad, &inner])
+}
+
+/// Represents the secret shared by the devices of a group.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Key([u8; Key::LEN]);
+
+impl Key {
+    /// Size of a key, in bytes.
+    pub const LEN: usize = 16;
+
+    /// Returns the bytes of the key.
+    ///
+    /// # Returns
+    /// The bytes of the key.
+    #[must_use]
+    pub fn bytes(&self) -> [u8; Self::LEN] {
+        self.0
+    }
+
+    /// Computes the truncated tag of a frame.
+    ///
+    /// # Arguments
+    /// * `marker` - Whether the frame is `CLEAR` or `SECRET`.
+    /// * `context` - Data the frame is bound to without carrying it, e.g. the
+    ///   name of the group.
+    /// * `body` - The frame without its tag.
+    ///
+    /// # Returns
+    /// The first `TAG_LEN` bytes of the HMAC of the marker, the context and the
+    /// body.
+    fn tag(&self, marker: u8, context: &[u8], body: &[u8]) -> [u8; TAG_LEN] {
+        let len = u8::try_from(context.len()).unwrap_or(u8::MAX);
+        let mac = hmac(&self.0, &[&[marker, len], context, body]);
+
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&mac[..TAG_LEN]);
+        tag
+    }
+
+    /// Checks the tag of a frame.
+    ///
+    /// # Arguments
+    /// * `marker` - Whether the frame is `CLEAR` or `SECRET`.
+    /// * `context` - Data the frame is bound to without carrying it.
+    /// * `body` - The frame without its tag.
+    /// * `tag` - The tag of the frame.
+    ///
+    /// # Returns
+    /// `true` if the tag is valid.
+    fn verify(&self, marker: u8, context: &[u8], body: &[u8], tag: &[u8]) -> bool {
+        // Compares in constant time so that the tag cannot be guessed byte by
+        // byte.
+        let diff = self
+            .tag(marker, context, body)
+            .iter()
+            .zip(tag)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+
+        diff == 0
+    }
+
+    /// Encrypts or decrypts data in place.
+    ///
+    /// The keystream is the HMAC of the nonce and of the tag of the plaintext,
+    /// as in SIV (RFC 5297). Together they act as an 8-byte nonce: two frames
+    /// with colliding nonces still get distinct keystreams, unless their tags
+    /// collide too.
+    ///
+    /// # Arguments
+    /// * `nonce` - The nonce, which should not repeat for the key.
+    /// * `tag` - The tag of the plaintext.
+    /// * `data` - The data, at most `MAX_SECRET` + 4 bytes.
+    fn apply_keystream(&self, nonce: &[u8], tag: &[u8], data: &mut [u8]) {
+        let stream = hmac(&self.0, &[&[KEYSTREAM], nonce, tag]);
+        for (byte, key) in data.iter_mut().zip(stream) {
+            *byte ^= key;
+        }
+    }
+
+    /// Seals a payload into an authenticated frame.
+    ///
+    /// # Arguments
+    /// * `context` - Data the frame is bound to without carrying it.
+    /// * `counter` - The counter of the frame, which must never decrease.
+    /// * `payload` - The payload, sent in clear.
+    ///
+    /// # Returns
+    /// The payload followed by the counter and the tag.
+    #[must_use]
+    pub fn seal(&self, context: &[u8], counter: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + OVERHEAD);
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&counter.to_le_bytes());
+        let tag = self.tag(CLEAR, context, &frame);
+        frame.extend_from_slice(&tag);
+
+        frame
+    }
+
+    /// Opens an authenticated frame.
+    ///
+    /// # Arguments
+    /// * `context` - Data the frame is bound to without carrying it.
+    /// * `frame` - The frame.
+    ///
+    /// # Returns
+    /// The counter and the payload, `None` if the frame is truncated or was not
+    /// sealed with this key and context.
+    #[must_use]
+    pub fn open<'a>(
+        &self,
+        context: &[u8],
+        frame: &'a [u8],
+    ) -> Option<(u32, &'a [u8])> {
+        let (body, tag) = frame.split_at(frame.len().checked_sub(TAG_LEN)?);
+        let (payload, counter) = body.split_at(body.len().checked_sub(4)?);
+        if !self.verify(CLEAR, context, body, tag) {
+            return None;
+        }
+
+        Some((
+            u32::from_le_bytes([counter[0], counter[1], counter[2], counter[3]]),
+            payload,
+        ))
+    }
+
+    /// Seals a payload into an authenticated and encrypted frame, which
+    /// reveals neither the payload nor the counter.
+    ///
+    /// # Arguments
+    /// * `context` - Data the frame is bound to without carrying it.
+    /// * `nonce` - The nonce, which should not repeat for the key, see `nonce`.
+    /// * `counter` - The counter of the frame, which must never decrease.
+    /// * `payload` - The payload, at most `MAX_SECRET` bytes.
+    ///
+    /// # Returns
+    /// The nonce, followed by the encrypted payload and counter, then the tag.
+    ///
+    /// # Errors
+    /// Returns an error if the payload is too long.
+    pub fn seal_secret(
+        &self,
+        context: &[u8],
+        nonce: [u8; NONCE_LEN],
+        counter: u32,
+        payload: &[u8],
+    ) -> Result<Vec<u8>> {
+        if payload.len() > MAX_SECRET {
+            return Err(anyhow!(
+                "Payload too long: {} bytes, expected at most {}",
+                payload.len(),
+                MAX_SECRET
+            ));
+        }
+
+        let mut frame = Vec::with_capacity(NONCE_LEN + payload.len() + OVERHEAD);
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&counter.to_le_bytes());
+        let tag = self.tag(SECRET, context, &frame);
+        self.apply_keystream(&nonce, &tag, &mut frame[NONCE_LEN..]);
+        frame.extend_from_slice(&tag);
+
+        Ok(frame)
+    }
+
+    /// Opens an authenticated and encrypted frame.
+    ///
+    /// # Arguments
+    /// * `context` - Data the frame is bound to without carrying it.
+    /// * `frame` - The frame.
+    ///
+    /// # Returns
+    /// The counter and the payload, `None` if the frame is malformed or was
+    /// not sealed with this key and context.
+    #[must_use]
+    pub fn open_secret(
+        &self,
+        context: &[u8],
+        frame: &[u8],
+    ) -> Option<(u32, Vec<u8>)> {
+        let (body, tag) = frame.split_at(frame.len().checked_sub(TAG_LEN)?);
+        if body.len() < NONCE_LEN + 4 || body.len() > NONCE_LEN + MAX_SECRET + 4 {
+            return None;
+        }
+
+        // The tag authenticates the plaintext, which has to be decrypted
+        // first.
+        let mut plain = body.to_vec();
+        self.apply_keystream(&body[..NONCE_LEN], tag, &mut plain[NONCE_LEN..]);
+        if !self.verify(SECRET, context, &plain, tag) {
+            return None;
+        }
+        let counter = plain.split_off(plain.len() - 4);
+        plain.drain(..NONCE_LEN);
+
+        Some((
+            u32::from_le_bytes([counter[0], counter[1], counter[2], counter[3]]),
+            plain,
+        ))
+    }
+
+    /// Derives the nonce of an encrypted frame from a counter of the sender.
+    ///
+    /// The nonces look random to outsiders, so that they do not reveal the
+    /// counter. Being truncated, they may collide, which `seal_secret` makes
+    /// harmless.
+    ///
+    /// # Arguments
+    /// * `id` - The permanent identifier of the sender, so that the senders
+    ///   sharing the key do not derive the same nonces.
+    /// * `counter` - A counter of the sender, which must never repeat.
+    ///
+    /// # Returns
+    /// The first `NONCE_LEN` bytes of the HMAC of the counter and the
+    /// identifier.
+    #[must_use]
+    pub fn nonce(&self, id: &[u8], counter: u32) -> [u8; NONCE_LEN] {
+        let mac = hmac(&self.0, &[&[NONCE], &counter.to_le_bytes(), id]);
+
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&mac[..NONCE_LEN]);
+        nonce
+    }
+
+    /// Derives an ephemeral identifier of a device, which only the group can
+    /// relate to the device.
+    ///
+    /// # Arguments
+    /// * `id` - The permanent identifier of the device.
+    /// * `slot` - The index of the time slot, which must never repeat.
+    ///
+    /// # Returns
+    /// The first 6 bytes of the HMAC of the slot and the identifier,
+    /// with the 2 most significant bits cleared as in a BLE non-resolvable
+    /// private address.
+    #[must_use]
+    pub fn ephemeral(&self, id: &[u8], slot: u32) -> [u8; 6] {
+        let mac = hmac(&self.0, &[&[EPHEMERAL], &slot.to_le_bytes(), id]);
+
+        let mut ephemeral = [0; 6];
+        ephemeral.copy_from_slice(&mac[..6]);
+        ephemeral[0] &= 0x3f;
+        ephemeral
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// Parses a key from its hexadecimal notation, 32 digits.
+    ///
+    /// # Errors
+    /// Returns an error if the notation is invalid.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != Self::LEN * 2 || !s.is_ascii() {
+            return Err(anyhow!(
+                "Invalid key: expected {} hexadecimal digits",
+                Self::LEN * 2
+            ));
+        }
+
+        let mut bytes = [0; Self::LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)?;
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+impl From<[u8; Key::LEN]> for Key {
+    /// Converts bytes into a key.
+    fn from(bytes: [u8; Key::LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for Key {
+    /// Formats the key without revealing it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+/// Represents the newest counter accepted from a sender.
+///
+/// # Fields
+/// * `counter` - The counter.
+/// * `since` - When the counter was first accepted.
+#[derive(Clone, Copy)]
+struct Newest {
+    counter: u32,
+    since: Instant,
+}
+
+/// Represents the replay protection of the frames of several senders.
+///
+/// Senders advertise the same frame repeatedly and advance its counter
+/// periodically. A frame is accepted if its counter is newer than the newest
+/// counter of its sender, or equal to it for a limited time. Hence, a recorded
+/// frame is rejected once its sender has moved on, and becomes useless after a
+/// while if its sender went away. The first frame of an unknown sender is
+/// accepted though, as there is no shared clock to tell how old it is.
+///
+/// # Type Parameters
+/// * `T` - Type of the identifier of a sender.
+pub struct Replay<T> {
+    fresh: Duration,
+    senders: Lru<T, Newest>,
+}
+
+impl<T: PartialEq> Replay<T> {
+    /// Creates a new `Replay` instance.
+    ///
+    /// # Arguments
+    /// * `fresh` - Duration a counter is accepted for, which must exceed the
+    ///   period the senders advance their counter at.
+    /// * `capacity` - Maximum number of senders remembered.
+    #[must_use]
+    pub fn new(fresh: Duration, capacity: usize) -> Self {
+        Self {
+            fresh,
+            senders: Lru::new(capacity),
+        }
+    }
+
+    /// Checks a counter from a sender and records it if accepted.
+    ///
+    /// # Arguments
+    /// * `sender` - The identifier of the sender.
+    /// * `counter` - The authenticated counter of the frame.
+    /// * `now` - The time the frame was received at.
+    ///
+    /// # Returns
+    /// `true` if the frame is accepted, `false` if it is a replay.
+    pub fn check(&mut self, sender: T, counter: u32, now: Instant) -> bool {
+        let newest = match self.senders.get(&sender) {
+            Some(newest) if counter == newest.counter => {
+                if now.saturating_duration_since(newest.since) > self.fresh {
+                    return false;
+                }
+                *newest
+            }
+            Some(newest) if counter < newest.counter => return false,
+            _ => Newest {
+                counter,
+                since: now,
+            },
+        };
+        self.senders.insert(sender, newest);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; Key::LEN] = [7; Key::LEN];
+
+    /// Decodes hexadecimal digits.
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hashes_fips_vectors() {
+        assert_eq!(
+            sha256(&[]).to_vec(),
+            hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(
+            sha256(&[b"ab", b"c"]).to_vec(),
+            hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        // The padding does not fit in the last block.
+        assert_eq!(
+            sha256(&[b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"])
+                .to_vec(),
+            hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    #[test]
+    fn authenticates_rfc_4231_vectors() {
+        let cases: [(Vec<u8>, Vec<u8>, &str); 4] = [
+            (
+                vec![0x0b; 20],
+                b"Hi There".to_vec(),
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?".to_vec(),
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                vec![0xaa; 20],
+                vec![0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                (1..=25).collect(),
+                vec![0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+        ];
+        for (key, data, mac) in cases {
+            assert_eq!(hmac(&key, &[&data]).to_vec(), hex(mac));
+        }
+
+        // Test case 5, truncated to 128 bits.
+        let mac = hmac(&[0x0c; 20], &[b"Test With ", b"Truncation"]);
+        assert_eq!(mac[..16].to_vec(), hex("a3b6167473100ee06e0c796c2955552b"));
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let key = Key::from(KEY);
+        let frame = key.seal(b"group", 42, b"payload");
+
+        assert_eq!(frame.len(), b"payload".len() + OVERHEAD);
+        assert_eq!(key.open(b"group", &frame), Some((42, &b"payload"[..])));
+        assert_eq!(key.open(b"other", &frame), None);
+        assert_eq!(Key::from([8; Key::LEN]).open(b"group", &frame), None);
+
+        let mut tampered = frame.clone();
+        tampered[0] ^= 1;
+        assert_eq!(key.open(b"group", &tampered), None);
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let key = Key::from(KEY);
+        let frame = key.seal(b"group", 42, b"");
+
+        assert_eq!(key.open(b"group", &frame), Some((42, &b""[..])));
+        for len in 0..frame.len() {
+            assert_eq!(key.open(b"group", &frame[..len]), None);
+        }
+    }
+
+    #[test]
+    fn seals_and_opens_secrets() {
+        let key = Key::from(KEY);
+        let frame = key
+            .seal_secret(b"group", key.nonce(b"sender", 1), 42, b"payload")
+            .unwrap();
+
+        assert!(!frame.windows(7).any(|window| window == b"payload"));
+        assert_eq!(
+            key.open_secret(b"group", &frame),
+            Some((42, b"payload".to_vec()))
+        );
+        assert_eq!(key.open_secret(b"other", &frame), None);
+        for len in 0..frame.len() {
+            assert_eq!(key.open_secret(b"group", &frame[..len]), None);
+        }
+
+        assert!(key
+            .seal_secret(b"group", key.nonce(b"sender", 2), 42, &[0; MAX_SECRET + 1])
+            .is_err());
+    }
+
+    #[test]
+    fn separates_keystreams_of_colliding_nonces() {
+        let key = Key::from(KEY);
+        let nonce = key.nonce(b"sender", 1);
+        let first = key.seal_secret(b"group", nonce, 1, b"payload").unwrap();
+        let second = key.seal_secret(b"group", nonce, 2, b"payload").unwrap();
+
+        // With a shared keystream, the equal payloads would encrypt equally.
+        let secret = NONCE_LEN..NONCE_LEN + 7;
+        assert_ne!(first[secret.clone()], second[secret]);
+        assert_eq!(
+            key.open_secret(b"group", &second),
+            Some((2, b"payload".to_vec()))
+        );
+
+        let mut tampered = second.clone();
+        tampered[0] ^= 1;
+        assert_eq!(key.open_secret(b"group", &tampered), None);
+        let mut tampered = second;
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(key.open_secret(b"group", &tampered), None);
+    }
+
+    #[test]
+    fn derives_distinct_nonces() {
+        let key = Key::from(KEY);
+        let nonce = key.nonce(b"sender", 1);
+
+        assert_eq!(key.nonce(b"sender", 1), nonce);
+        assert_ne!(key.nonce(b"sender", 2), nonce);
+        assert_ne!(key.nonce(b"other", 1), nonce);
+        assert_ne!(Key::from([8; Key::LEN]).nonce(b"sender", 1), nonce);
+    }
+
+    #[test]
+    fn parses_keys() {
+        let key: Key = "07070707070707070707070707070707".parse().unwrap();
+
+        assert_eq!(key.bytes(), KEY);
+        assert!("0707".parse::<Key>().is_err());
+        assert!("zz070707070707070707070707070707".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn rejects_replays() {
+        let fresh = Duration::from_secs(10);
+        let mut replay = Replay::new(fresh, 4);
+        let now = Instant::now();
+
+        assert!(replay.check(1, 5, now));
+        assert!(replay.check(1, 5, now + fresh));
+        assert!(!replay.check(1, 5, now + fresh * 2));
+        assert!(!replay.check(1, 4, now));
+        assert!(replay.check(1, 6, now + fresh * 2));
+        assert!(!replay.check(1, 5, now + fresh * 2));
+    }
+
+    #[test]
+    fn evicts_least_recent_sender() {
+        let mut replay = Replay::new(Duration::from_secs(10), 2);
+        let now = Instant::now();
+
+        assert!(replay.check(1, 5, now));
+        assert!(replay.check(2, 5, now));
+        // Sender 1 is refreshed, so sender 2 is evicted by sender 3.
+        assert!(replay.check(1, 6, now));
+        assert!(replay.check(3, 5, now));
+        assert!(!replay.check(1, 5, now));
+        assert!(replay.check(2, 4, now));
+    }
+}
@@ -0,0 +1,531 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Company identifier of Apple, which iBeacon frames are sent as.
+pub const APPLE: u16 = 0x004C;
+
+/// UUID of the Eddystone service, which Eddystone frames are service data of.
+pub const EDDYSTONE: u16 = 0xFEAA;
+
+/// Typical path loss at 1 m, in dB, from which the power measured by the
+/// receivers is estimated.
+const PATH_LOSS: i8 = 41;
+
+/// Maximum length of an encoded Eddystone URL, after its scheme.
+const MAX_URL: usize = 17;
+
+/// Prefixes of the Eddystone URLs, by code.
+const SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Expansions of the Eddystone URLs, by code.
+const EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org",
+    ".edu", ".net", ".info", ".biz", ".gov",
+];
+
+/// Represents a 128-bit UUID, identifying the beacons of a deployment.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Uuid([u8; 16]);
+
+impl Uuid {
+    /// Returns the bytes of the UUID.
+    ///
+    /// # Returns
+    /// The bytes, most significant first.
+    #[must_use]
+    pub fn bytes(self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Returns the Eddystone namespace of the UUID, by elision.
+    ///
+    /// # Returns
+    /// The first 4 and the last 6 bytes of the UUID.
+    #[must_use]
+    pub fn namespace(self) -> [u8; 10] {
+        let mut namespace = [0; 10];
+        namespace[..4].copy_from_slice(&self.0[..4]);
+        namespace[4..].copy_from_slice(&self.0[10..]);
+        namespace
+    }
+}
+
+impl From<[u8; 16]> for Uuid {
+    /// Converts bytes, most significant first, into a UUID.
+    fn from(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = anyhow::Error;
+
+    /// Parses a UUID from its hexadecimal notation, 32 digits, with or without
+    /// dashes, e.g. `e2c56db5-dffb-48d2-b060-d0f5a71096e0`.
+    ///
+    /// # Errors
+    /// Returns an error if the notation is invalid.
+    fn from_str(s: &str) -> Result<Self> {
+        let digits = s.replace('-', "");
+        if digits.len() != 32 || !digits.is_ascii() {
+            return Err(anyhow!("Invalid UUID: {}", s));
+        }
+
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)?;
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    /// Formats the UUID in its canonical notation, with dashes.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents a standard beacon frame the device can take turns with.
+///
+/// # Variants
+/// * `IBeacon` - An iBeacon frame, carrying the UUID, major and minor.
+/// * `Uid` - An Eddystone-UID frame, carrying the namespace of the UUID and
+///   the address of the device as instance.
+/// * `Url` - An Eddystone-URL frame, carrying the URL.
+/// * `Tlm` - An Eddystone-TLM frame, carrying the battery voltage, the number
+///   of advertisements and the uptime.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Frame {
+    IBeacon,
+    Uid,
+    Url,
+    Tlm,
+}
+
+impl Frame {
+    /// Names of the frames, as used by `FromStr` and `Display`.
+    pub const NAMES: [&'static str; 4] = ["ibeacon", "uid", "url", "tlm"];
+}
+
+impl FromStr for Frame {
+    type Err = anyhow::Error;
+
+    /// Parses a frame from its name, e.g. `ibeacon`.
+    ///
+    /// # Errors
+    /// Returns an error if no frame has this name.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ibeacon" => Ok(Self::IBeacon),
+            "uid" => Ok(Self::Uid),
+            "url" => Ok(Self::Url),
+            "tlm" => Ok(Self::Tlm),
+            _ => Err(anyhow!(
+                "Unknown beacon frame: {}, expected one of {:?}",
+                s,
+                Self::NAMES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    /// Formats the frame as its name.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::IBeacon => Self::NAMES[0],
+            Self::Uid => Self::NAMES[1],
+            Self::Url => Self::NAMES[2],
+            Self::Tlm => Self::NAMES[3],
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Represents the beacon parameters.
+///
+/// # Fields
+/// * `frames` - The frames the device takes turns with, none by default.
+/// * `uuid` - The UUID of the deployment, which the iBeacon and Eddystone-UID
+///   frames require, and which third-party beacons are recognized by.
+/// * `major` - The major number of the iBeacon frames.
+/// * `minor` - The minor number of the iBeacon frames.
+/// * `url` - The URL of the Eddystone-URL frames.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BeaconConfig {
+    pub frames: Vec<Frame>,
+    pub uuid: Option<Uuid>,
+    pub major: u16,
+    pub minor: u16,
+    pub url: String,
+}
+
+impl BeaconConfig {
+    /// Checks that the parameters are supported.
+    ///
+    /// # Errors
+    /// Returns an error describing the first invalid parameter.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(frame) = self
+            .frames
+            .iter()
+            .enumerate()
+            .find(|(i, frame)| self.frames[..*i].contains(frame))
+            .map(|(_, frame)| frame)
+        {
+            return Err(anyhow!("Invalid beacons: {} repeated", frame));
+        }
+        if let Some(frame) = self
+            .frames
+            .iter()
+            .find(|frame| matches!(frame, Frame::IBeacon | Frame::Uid))
+            .filter(|_| self.uuid.is_none())
+        {
+            return Err(anyhow!(
+                "Invalid beacons: {} requires a beacon_uuid",
+                frame
+            ));
+        }
+        if self.frames.contains(&Frame::Url) {
+            encode_url(&self.url)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encodes a URL as in the Eddystone-URL frames.
+///
+/// # Arguments
+/// * `url` - The URL, starting with `http://` or `https://`.
+///
+/// # Returns
+/// The code of the scheme, followed by the rest of the URL with the common
+/// suffixes replaced by their codes.
+///
+/// # Errors
+/// Returns an error if the scheme is not supported, if the URL has characters
+/// other than printable ASCII or if it is too long once encoded.
+pub fn encode_url(url: &str) -> Result<Vec<u8>> {
+    let (scheme, mut rest) = SCHEMES
+        .iter()
+        .zip(0..)
+        .find_map(|(prefix, code)| url.strip_prefix(prefix).map(|rest| (code, rest)))
+        .ok_or_else(|| {
+            anyhow!("Invalid beacon_url: {}, expected http(s)://", url)
+        })?;
+
+    let mut encoded = vec![scheme];
+    while let Some(c) = rest.chars().next() {
+        if let Some((expansion, code)) = EXPANSIONS
+            .iter()
+            .zip(0..)
+            .find(|(expansion, _)| rest.starts_with(*expansion))
+        {
+            encoded.push(code);
+            rest = &rest[expansion.len()..];
+        } else if c.is_ascii_graphic() {
+            encoded.push(c as u8);
+            rest = &rest[1..];
+        } else {
+            return Err(anyhow!("Invalid beacon_url: {}, unexpected {:?}", url, c));
+        }
+    }
+    if encoded.len() > 1 + MAX_URL {
+        return Err(anyhow!(
+            "Invalid beacon_url: {}, {} bytes once encoded, expected at most {}",
+            url,
+            encoded.len() - 1,
+            MAX_URL
+        ));
+    }
+
+    Ok(encoded)
+}
+
+/// Returns the power the receivers measure at 1 m, as iBeacon frames report.
+///
+/// # Arguments
+/// * `tx_power` - The transmit power, in dBm.
+///
+/// # Returns
+/// The estimated power at 1 m, in dBm.
+#[must_use]
+pub fn measured_power(tx_power: i8) -> i8 {
+    tx_power.saturating_sub(PATH_LOSS)
+}
+
+/// Encodes an iBeacon frame.
+///
+/// # Arguments
+/// * `uuid` - The UUID of the deployment.
+/// * `major` - The major number.
+/// * `minor` - The minor number.
+/// * `tx_power` - The transmit power, in dBm.
+///
+/// # Returns
+/// The manufacturer data: the company identifier, the iBeacon type and
+/// length, the UUID, the major and minor numbers and the measured power.
+#[must_use]
+pub fn ibeacon(uuid: Uuid, major: u16, minor: u16, tx_power: i8) -> Vec<u8> {
+    let mut data = APPLE.to_le_bytes().to_vec();
+    data.extend_from_slice(&[0x02, 0x15]);
+    data.extend_from_slice(&uuid.bytes());
+    data.extend_from_slice(&major.to_be_bytes());
+    data.extend_from_slice(&minor.to_be_bytes());
+    data.extend_from_slice(&measured_power(tx_power).to_be_bytes());
+    data
+}
+
+/// Encodes an Eddystone-UID frame.
+///
+/// # Arguments
+/// * `namespace` - The namespace of the deployment.
+/// * `instance` - The instance of the beacon within the namespace.
+/// * `tx_power` - The transmit power, in dBm, which the frame reports as the
+///   power at 0 m.
+///
+/// # Returns
+/// The service data of the Eddystone service.
+#[must_use]
+pub fn uid(namespace: [u8; 10], instance: [u8; 6], tx_power: i8) -> Vec<u8> {
+    let mut data = vec![0x00];
+    data.extend_from_slice(&tx_power.to_be_bytes());
+    data.extend_from_slice(&namespace);
+    data.extend_from_slice(&instance);
+    // Reserved for future use.
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+/// Encodes an Eddystone-URL frame.
+///
+/// # Arguments
+/// * `url` - The URL.
+/// * `tx_power` - The transmit power, in dBm, which the frame reports as the
+///   power at 0 m.
+///
+/// # Returns
+/// The service data of the Eddystone service.
+///
+/// # Errors
+/// Returns an error if the URL cannot be encoded.
+pub fn url(url: &str, tx_power: i8) -> Result<Vec<u8>> {
+    let mut data = vec![0x10];
+    data.extend_from_slice(&tx_power.to_be_bytes());
+    data.extend(encode_url(url)?);
+    Ok(data)
+}
+
+/// Encodes an unencrypted Eddystone-TLM frame.
+///
+/// # Arguments
+/// * `millivolts` - The battery voltage, `None` if not battery powered.
+/// * `count` - The number of advertisements sent since the device started.
+/// * `uptime` - The time since the device started.
+///
+/// # Returns
+/// The service data of the Eddystone service. The temperature is reported as
+/// not supported.
+#[must_use]
+pub fn tlm(millivolts: Option<u16>, count: u32, uptime: Duration) -> Vec<u8> {
+    let tenths = u32::try_from(uptime.as_millis() / 100).unwrap_or(u32::MAX);
+
+    let mut data = vec![0x20, 0x00];
+    data.extend_from_slice(&millivolts.unwrap_or(0).to_be_bytes());
+    data.extend_from_slice(&0x8000_u16.to_be_bytes());
+    data.extend_from_slice(&count.to_be_bytes());
+    data.extend_from_slice(&tenths.to_be_bytes());
+    data
+}
+
+/// Represents a beacon heard from a device, identifying its deployment.
+///
+/// # Variants
+/// * `IBeacon` - An iBeacon, with its UUID, major and minor numbers.
+/// * `Uid` - An Eddystone-UID beacon, with its namespace and instance.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Beacon {
+    IBeacon {
+        uuid: Uuid,
+        major: u16,
+        minor: u16,
+    },
+    Uid {
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+}
+
+impl Beacon {
+    /// Parses an iBeacon from manufacturer data.
+    ///
+    /// # Arguments
+    /// * `company` - The company identifier.
+    /// * `payload` - The manufacturer data after the company identifier.
+    ///
+    /// # Returns
+    /// The beacon, `None` if the data is not an iBeacon frame.
+    #[must_use]
+    pub fn from_manufacturer(company: u16, payload: &[u8]) -> Option<Self> {
+        let [0x02, 0x15, rest @ ..] = payload else {
+            return None;
+        };
+        if company != APPLE || rest.len() != 21 {
+            return None;
+        }
+
+        Some(Self::IBeacon {
+            uuid: Uuid(rest[..16].try_into().ok()?),
+            major: u16::from_be_bytes([rest[16], rest[17]]),
+            minor: u16::from_be_bytes([rest[18], rest[19]]),
+        })
+    }
+
+    /// Parses an Eddystone-UID beacon from the service data of the Eddystone
+    /// service.
+    ///
+    /// # Arguments
+    /// * `data` - The service data.
+    ///
+    /// # Returns
+    /// The beacon, `None` if the data is not an Eddystone-UID frame.
+    #[must_use]
+    pub fn from_eddystone(data: &[u8]) -> Option<Self> {
+        let [0x00, _, rest @ ..] = data else {
+            return None;
+        };
+        // The reserved bytes are optional.
+        if rest.len() != 16 && rest.len() != 18 {
+            return None;
+        }
+
+        Some(Self::Uid {
+            namespace: rest[..10].try_into().ok()?,
+            instance: rest[10..16].try_into().ok()?,
+        })
+    }
+
+    /// Returns whether the beacon belongs to a deployment.
+    ///
+    /// # Arguments
+    /// * `uuid` - The UUID of the deployment.
+    ///
+    /// # Returns
+    /// `true` if the beacon has the UUID, or its namespace if an Eddystone
+    /// beacon.
+    #[must_use]
+    pub fn belongs(&self, uuid: Uuid) -> bool {
+        match self {
+            Self::IBeacon { uuid: other, .. } => *other == uuid,
+            Self::Uid { namespace, .. } => *namespace == uuid.namespace(),
+        }
+    }
+
+    /// Returns whether the beacon is a frame sent by a device of the project,
+    /// rather than a third-party beacon.
+    ///
+    /// The devices send their address as the instance of their Eddystone-UID
+    /// frames, and the configured major in their iBeacon frames. Hence, the
+    /// third-party iBeacons of a deployment must have another major.
+    ///
+    /// # Arguments
+    /// * `devices` - The addresses of the devices of the project, e.g. the
+    ///   sender of the beacon and the known peers.
+    /// * `major` - The major number of the iBeacon frames of the devices, if
+    ///   they send any.
+    ///
+    /// # Returns
+    /// `true` if the instance of the beacon is one of the devices, or if its
+    /// major is the one of the devices.
+    #[must_use]
+    pub fn is_own(&self, devices: &[[u8; 6]], major: Option<u16>) -> bool {
+        match self {
+            Self::IBeacon { major: other, .. } => Some(*other) == major,
+            Self::Uid { instance, .. } => devices.contains(instance),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "e2c56db5-dffb-48d2-b060-d0f5a71096e0";
+
+    #[test]
+    fn parses_uuids() {
+        let uuid: Uuid = UUID.parse().unwrap();
+
+        assert_eq!(uuid.to_string(), UUID);
+        assert_eq!(
+            "E2C56DB5DFFB48D2B060D0F5A71096E0".parse::<Uuid>().unwrap(),
+            uuid
+        );
+        assert!("e2c5".parse::<Uuid>().is_err());
+        assert_eq!(
+            uuid.namespace(),
+            [0xe2, 0xc5, 0x6d, 0xb5, 0xd0, 0xf5, 0xa7, 0x10, 0x96, 0xe0]
+        );
+    }
+
+    #[test]
+    fn recognizes_own_frames() {
+        let uuid: Uuid = UUID.parse().unwrap();
+        let other = Uuid::from([0; 16]);
+
+        let ibeacon = ibeacon(uuid, 1, 2, 3);
+        let beacon = Beacon::from_manufacturer(APPLE, &ibeacon[2..]).unwrap();
+        assert!(beacon.belongs(uuid));
+        assert!(!beacon.belongs(other));
+        assert!(beacon.is_own(&[], Some(1)));
+        assert!(!beacon.is_own(&[], Some(7)));
+        assert!(!beacon.is_own(&[], None));
+        assert_eq!(Beacon::from_manufacturer(0xFFFF, &ibeacon[2..]), None);
+
+        let beacon =
+            Beacon::from_eddystone(&uid(uuid.namespace(), [1; 6], 3)).unwrap();
+        assert!(beacon.belongs(uuid));
+        assert!(beacon.is_own(&[[2; 6], [1; 6]], Some(1)));
+        assert!(!beacon.is_own(&[[2; 6]], Some(1)));
+    }
+
+    #[test]
+    fn encodes_urls() {
+        assert_eq!(
+            encode_url("https://www.example.com/").unwrap(),
+            [1, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0]
+        );
+        assert_eq!(encode_url("http://goo.gl/S6zT6P").unwrap()[0], 2);
+        assert!(encode_url("ftp://example.com").is_err());
+        assert!(encode_url("https://a b").is_err());
+        assert!(encode_url("https://abcdefghijklmnopqrstuvwxyz.com").is_err());
+    }
+
+    #[test]
+    fn validates_frames() {
+        let mut config = BeaconConfig {
+            frames: vec![Frame::Uid, Frame::Tlm],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.uuid = Some(UUID.parse().unwrap());
+        config.validate().unwrap();
+        config.frames.push(Frame::Tlm);
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid beacons: tlm repeated"
+        );
+    }
+}
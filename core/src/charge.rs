@@ -0,0 +1,207 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+/// Discharge curve of a single cell lithium polymer pack under light load, as
+/// `(millivolts, percent)` points by decreasing voltage.
+const CURVE: [(u32, u8); 13] = [
+    (4200, 100),
+    (4150, 95),
+    (4110, 90),
+    (4020, 80),
+    (3950, 70),
+    (3910, 60),
+    (3870, 50),
+    (3840, 40),
+    (3800, 30),
+    (3760, 20),
+    (3700, 10),
+    (3600, 5),
+    (3300, 0),
+];
+
+/// Estimates the state of charge of the pack from its voltage.
+///
+/// # Arguments
+/// * `millivolts` - The voltage of the pack.
+///
+/// # Returns
+/// The state of charge in percent, interpolated linearly along `CURVE`.
+#[must_use]
+pub fn state_of_charge(millivolts: u32) -> u8 {
+    let Some(upper) = CURVE.iter().position(|&(mv, _)| millivolts >= mv) else {
+        return 0;
+    };
+    if upper == 0 {
+        return 100;
+    }
+
+    let (high_mv, high_percent) = CURVE[upper - 1];
+    let (low_mv, low_percent) = CURVE[upper];
+    let percent = u32::from(low_percent)
+        + (millivolts - low_mv) * u32::from(high_percent - low_percent)
+            / (high_mv - low_mv);
+
+    u8::try_from(percent).unwrap_or(100)
+}
+
+/// Represents the charge level of the pack.
+///
+/// # Variants
+/// * `Normal` - The pack has enough charge.
+/// * `Low` - The pack should be charged soon.
+/// * `Critical` - The pack is about to be exhausted.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Eq,
+    IntoPrimitive,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum Level {
+    Normal,
+    Low,
+    Critical,
+}
+
+impl Level {
+    /// State of charge at or below which the pack is low, in percent.
+    const LOW: u8 = 20;
+    /// State of charge at or below which the pack is critical, in percent.
+    const CRITICAL: u8 = 5;
+    /// Margin above a threshold to reach before leaving its level, in percent.
+    const HYSTERESIS: u8 = 3;
+
+    /// Classifies a state of charge, given the current level.
+    ///
+    /// A level is left for a better one only once the state of charge is
+    /// `HYSTERESIS` above its threshold, so that noise does not make it flap.
+    ///
+    /// # Arguments
+    /// * `percent` - The state of charge.
+    ///
+    /// # Returns
+    /// The new level.
+    #[must_use]
+    pub fn classify(self, percent: u8) -> Self {
+        let level = if percent <= Self::CRITICAL {
+            Self::Critical
+        } else if percent <= Self::LOW {
+            Self::Low
+        } else {
+            Self::Normal
+        };
+        let recovered = match self {
+            Self::Normal => true,
+            Self::Low => percent > Self::LOW + Self::HYSTERESIS,
+            Self::Critical => percent > Self::CRITICAL + Self::HYSTERESIS,
+        };
+
+        if level > self || recovered {
+            level
+        } else {
+            self
+        }
+    }
+}
+
+/// Represents an exponential moving average of the voltage.
+#[derive(Default)]
+pub struct Filter {
+    scaled: Option<u32>,
+}
+
+impl Filter {
+    /// Weight of the past samples, as a power of two: each sample counts for
+    /// 1/8 of the average.
+    const SHIFT: u32 = 3;
+
+    /// Adds a sample to the average.
+    ///
+    /// # Arguments
+    /// * `sample` - The voltage sampled, in millivolts.
+    ///
+    /// # Returns
+    /// The filtered voltage, in millivolts.
+    pub fn update(&mut self, sample: u32) -> u32 {
+        let scaled = self.scaled.map_or(sample << Self::SHIFT, |scaled| {
+            scaled - (scaled >> Self::SHIFT) + sample
+        });
+        self.scaled = Some(scaled);
+
+        scaled >> Self::SHIFT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_along_curve() {
+        for (millivolts, percent) in CURVE {
+            assert_eq!(state_of_charge(millivolts), percent, "{millivolts} mV");
+        }
+        assert_eq!(state_of_charge(3890), 55);
+        // Rounded down between 5 % and 10 %.
+        assert_eq!(state_of_charge(3650), 7);
+        assert_eq!(state_of_charge(4199), 99);
+    }
+
+    #[test]
+    fn clamps_at_curve_ends() {
+        assert_eq!(state_of_charge(4500), 100);
+        assert_eq!(state_of_charge(3299), 0);
+        assert_eq!(state_of_charge(0), 0);
+    }
+
+    #[test]
+    fn decreases_with_voltage() {
+        let percents: Vec<u8> = (3200..=4300).rev().map(state_of_charge).collect();
+
+        assert!(percents.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn drains_without_hysteresis() {
+        assert_eq!(Level::Normal.classify(21), Level::Normal);
+        assert_eq!(Level::Normal.classify(20), Level::Low);
+        assert_eq!(Level::Normal.classify(5), Level::Critical);
+        assert_eq!(Level::Low.classify(5), Level::Critical);
+    }
+
+    #[test]
+    fn recovers_with_hysteresis() {
+        assert_eq!(Level::Low.classify(23), Level::Low);
+        assert_eq!(Level::Low.classify(24), Level::Normal);
+        assert_eq!(Level::Critical.classify(8), Level::Critical);
+        assert_eq!(Level::Critical.classify(9), Level::Low);
+        assert_eq!(Level::Critical.classify(100), Level::Normal);
+    }
+
+    #[test]
+    fn starts_at_first_sample() {
+        let mut filter = Filter::default();
+
+        assert_eq!(filter.update(3900), 3900);
+        // Each sample counts for an eighth.
+        assert_eq!(filter.update(3100), 3800);
+    }
+
+    #[test]
+    fn converges_exactly() {
+        let mut filter = Filter::default();
+        filter.update(4200);
+
+        let filtered: Vec<u32> = (0..64).map(|_| filter.update(3700)).collect();
+        assert!(filtered.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(filtered.last(), Some(&3700));
+
+        let filtered: Vec<u32> = (0..64).map(|_| filter.update(3900)).collect();
+        assert!(filtered.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(filtered.last(), Some(&3900));
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr};
+
+/// Represents an RGB color value.
+///
+/// # Fields
+/// * `r` - Red component of the color.
+/// * `g` - Green component of the color.
+/// * `b` - Blue component of the color.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Rgb {
+    /// Creates a new `Rgb` instance.
+    ///
+    /// # Arguments
+    /// * `r` - Red component of the color.
+    /// * `g` - Green component of the color.
+    /// * `b` - Blue component of the color.
+    ///
+    /// # Returns
+    /// A new `Rgb` instance.
+    #[must_use]
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = anyhow::Error;
+
+    /// Parses a color from its hexadecimal notation, e.g. `#00ff00` or `00ff00`.
+    ///
+    /// # Errors
+    /// Returns an error if the notation is invalid.
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid color: {}", s));
+        }
+
+        let [_, r, g, b] = u32::from_str_radix(hex, 16)?.to_be_bytes();
+        Ok(Self { r, g, b })
+    }
+}
+
+impl fmt::Display for Rgb {
+    /// Formats the color in hexadecimal notation, e.g. `#00ff00`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl From<&Rgb> for u32 {
+    /// Converts an `Rgb` instance to a `u32` color value.
+    /// e.g. rgb: (1,2,4)
+    /// G        R        B
+    /// 7      0 7      0 7      0
+    /// 00000010 00000001 00000100
+    ///
+    /// # Returns
+    /// A `u32` representation of the RGB color.
+    fn from(rgb: &Rgb) -> Self {
+        (u32::from(rgb.g) << 16) | (u32::from(rgb.r) << 8) | u32::from(rgb.b)
+    }
+}
+
+/// Brightness level for predefined colors.
+const BRIGHTNESS: u8 = 25;
+
+/// Predefined black color.
+pub const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+
+/// Predefined green color.
+pub const GREEN: Rgb = Rgb {
+    r: 0,
+    g: BRIGHTNESS,
+    b: 0,
+};
+
+/// Predefined red color.
+pub const RED: Rgb = Rgb {
+    r: BRIGHTNESS,
+    g: 0,
+    b: 0,
+};
+
+/// Predefined amber color.
+pub const AMBER: Rgb = Rgb {
+    r: BRIGHTNESS,
+    g: BRIGHTNESS / 2,
+    b: 0,
+};
+
+/// Predefined blue color.
+pub const BLUE: Rgb = Rgb {
+    r: 0,
+    g: 0,
+    b: BRIGHTNESS,
+};
+
+/// Predefined cyan color.
+pub const CYAN: Rgb = Rgb {
+    r: 0,
+    g: BRIGHTNESS,
+    b: BRIGHTNESS,
+};
+
+/// Predefined magenta color.
+pub const MAGENTA: Rgb = Rgb {
+    r: BRIGHTNESS,
+    g: 0,
+    b: BRIGHTNESS,
+};
+
+/// Predefined white color.
+pub const WHITE: Rgb = Rgb {
+    r: BRIGHTNESS,
+    g: BRIGHTNESS,
+    b: BRIGHTNESS,
+};
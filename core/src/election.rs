@@ -0,0 +1,192 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::lru::Lru;
+
+/// Represents the part a device plays in the election of the coordinator of
+/// its group.
+///
+/// # Variants
+/// * `Candidate` - The device heard no device with a lower identifier, and
+///   waits to be sure before leading.
+/// * `Leader` - The device coordinates the group.
+/// * `Follower` - The device heard a device with a lower identifier, which
+///   leads or will.
+#[derive(Clone, Copy, Debug, Eq, IntoPrimitive, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Role {
+    Candidate,
+    Leader,
+    Follower,
+}
+
+impl fmt::Display for Role {
+    /// Formats the role as a string.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Candidate => write!(f, "Candidate"),
+            Self::Leader => write!(f, "Leader"),
+            Self::Follower => write!(f, "Follower"),
+        }
+    }
+}
+
+/// Represents the election of the coordinator of a group, as in the bully
+/// algorithm: the device with the lowest identifier leads.
+///
+/// The advertisements of the devices are their heartbeats. A device that
+/// hears a lower identifier follows at once, even if it was leading. A device
+/// that hears none for `timeout`, e.g. because the leader disappeared, stands
+/// as a candidate for another `timeout`, long enough to hear any lower
+/// identifier, before leading.
+///
+/// # Type Parameters
+/// * `T` - Type of the identifier of a device.
+pub struct Election<T> {
+    own: T,
+    timeout: Duration,
+    standing: Option<Instant>,
+    peers: Lru<T, Instant>,
+}
+
+impl<T: Copy + Ord> Election<T> {
+    /// Creates a new `Election` instance, standing as a candidate.
+    ///
+    /// # Arguments
+    /// * `own` - The identifier of the device.
+    /// * `timeout` - Duration after which a silent peer is deemed gone, and
+    ///   a candidate leads.
+    /// * `capacity` - Maximum number of peers remembered.
+    /// * `now` - The current time.
+    #[must_use]
+    pub fn new(own: T, timeout: Duration, capacity: usize, now: Instant) -> Self {
+        Self {
+            own,
+            timeout,
+            standing: Some(now),
+            peers: Lru::new(capacity),
+        }
+    }
+
+    /// Records the heartbeat of a peer.
+    ///
+    /// # Arguments
+    /// * `peer` - The identifier of the peer.
+    /// * `now` - The time the heartbeat was heard at.
+    pub fn heartbeat(&mut self, peer: T, now: Instant) {
+        if peer != self.own {
+            self.peers.insert(peer, now);
+        }
+    }
+
+    /// Returns the leader, forgetting the peers gone silent.
+    ///
+    /// # Arguments
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// The lowest identifier heard, if lower than the one of the device,
+    /// `None` otherwise.
+    pub fn leader(&mut self, now: Instant) -> Option<T> {
+        let timeout = self.timeout;
+        self.peers
+            .retain(|_, heard| now.saturating_duration_since(*heard) < timeout);
+
+        self.peers
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| *id < self.own)
+            .min()
+    }
+
+    /// Returns the role of the device.
+    ///
+    /// # Arguments
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// `Follower` if a lower identifier was heard, otherwise `Candidate` until
+    /// the device stood for `timeout`, then `Leader`.
+    pub fn role(&mut self, now: Instant) -> Role {
+        if self.leader(now).is_some() {
+            self.standing = None;
+            return Role::Follower;
+        }
+
+        let standing = *self.standing.get_or_insert(now);
+        if now.saturating_duration_since(standing) < self.timeout {
+            Role::Candidate
+        } else {
+            Role::Leader
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    const SECOND: Duration = Duration::from_secs(1);
+    /// A second before `TIMEOUT`.
+    const ALMOST: Duration = Duration::from_secs(9);
+
+    #[test]
+    fn leads_after_standing() {
+        let start = Instant::now();
+        let mut election = Election::new(5, TIMEOUT, 4, start);
+
+        assert_eq!(election.role(start), Role::Candidate);
+        // Higher identifiers and the own one do not count.
+        election.heartbeat(7, start + SECOND);
+        election.heartbeat(5, start + SECOND);
+        assert_eq!(election.role(start + ALMOST), Role::Candidate);
+        assert_eq!(election.role(start + TIMEOUT), Role::Leader);
+        assert_eq!(election.leader(start + TIMEOUT), None);
+    }
+
+    #[test]
+    fn follows_lower_identifier() {
+        let start = Instant::now();
+        let mut election = Election::new(5, TIMEOUT, 4, start);
+        assert_eq!(election.role(start + TIMEOUT), Role::Leader);
+
+        // A leader steps down as soon as it hears a lower identifier.
+        election.heartbeat(3, start + TIMEOUT);
+        assert_eq!(election.role(start + TIMEOUT), Role::Follower);
+        election.heartbeat(1, start + TIMEOUT);
+        assert_eq!(election.leader(start + TIMEOUT), Some(1));
+    }
+
+    #[test]
+    fn stands_again_after_silence() {
+        let start = Instant::now();
+        let mut election = Election::new(5, TIMEOUT, 4, start);
+        election.heartbeat(3, start);
+        assert_eq!(election.role(start), Role::Follower);
+
+        assert_eq!(election.role(start + ALMOST), Role::Follower);
+        // The leader went silent: the device stands for another timeout.
+        assert_eq!(election.role(start + TIMEOUT), Role::Candidate);
+        assert_eq!(election.role(start + TIMEOUT + ALMOST), Role::Candidate);
+        assert_eq!(election.role(start + TIMEOUT * 2), Role::Leader);
+    }
+
+    #[test]
+    fn forgets_least_recent_peer() {
+        let start = Instant::now();
+        let mut election = Election::new(5, TIMEOUT, 2, start);
+
+        election.heartbeat(1, start);
+        election.heartbeat(8, start);
+        election.heartbeat(1, start + SECOND);
+        election.heartbeat(9, start + SECOND);
+        // Peer 8 was forgotten first, as peer 1 was heard again.
+        assert_eq!(election.leader(start + SECOND), Some(1));
+        election.heartbeat(7, start + SECOND);
+        assert_eq!(election.leader(start + SECOND), None);
+    }
+}
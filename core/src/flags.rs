@@ -0,0 +1,529 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FusedIterator,
+    marker::PhantomData,
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Sub, SubAssign},
+};
+
+/// A trait representing a fieldless enum usable as a flag in a `FlagSet`.
+///
+/// Implementations are generated by the `flags!` macro, which assigns each
+/// variant its declaration index so that variant `i` maps to bit `1 << i`.
+///
+/// # Type Parameters
+/// * `N` - Number of variants of the enum.
+pub trait Flag<const N: usize>: Copy + Into<u32> {
+    /// All the variants of the enum, in declaration order.
+    const ALL: [Self; N];
+
+    /// Returns the bit representing the flag.
+    ///
+    /// # Returns
+    /// A `u32` with only the flag's bit set.
+    fn bit(self) -> u32 {
+        1 << self.into()
+    }
+}
+
+/// Declares a fieldless enum implementing `Flag`.
+///
+/// The enum must be `Copy` and convertible into a `u32` (e.g. by deriving
+/// `IntoPrimitive` with `#[repr(u32)]`). Variants are given their declaration
+/// index as discriminant, and the macro also provides a `COUNT` constant and a
+/// `|` operator building a `FlagSet` out of two flags.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __flags {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant),+
+        }
+
+        impl $name {
+            /// Number of variants of the enum.
+            pub const COUNT: usize = [$(stringify!($variant)),+].len();
+        }
+
+        const _: () = assert!(
+            $name::COUNT <= u32::BITS as usize,
+            "Too many flags to fit in a u32"
+        );
+
+        impl $crate::flags::Flag<{ $name::COUNT }> for $name {
+            const ALL: [Self; $name::COUNT] = [$(Self::$variant),+];
+        }
+
+        impl ::std::ops::BitOr for $name {
+            type Output = $crate::flags::FlagSet<Self, { $name::COUNT }>;
+
+            /// Builds a set containing both flags.
+            fn bitor(self, rhs: Self) -> Self::Output {
+                Self::Output::from(self) | rhs
+            }
+        }
+    };
+}
+
+// Exported at the root of the crate, as every exported macro, but used as
+// `flags::flags`.
+pub use __flags as flags;
+
+/// Represents an allocation-free set of flags backed by a single `u32`.
+///
+/// # Type Parameters
+/// * `T` - Type of the flags.
+/// * `N` - Number of variants of `T`.
+pub struct FlagSet<T, const N: usize> {
+    bits: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T: Flag<N>, const N: usize> FlagSet<T, N> {
+    /// Mask of the bits that map to a variant of `T`.
+    const MASK: u32 = if N == u32::BITS as usize {
+        u32::MAX
+    } else {
+        (1 << N) - 1
+    };
+
+    /// Creates an empty set.
+    ///
+    /// # Returns
+    /// A set containing no flag.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self {
+            bits: 0,
+            marker: PhantomData,
+        }
+    }
+
+    /// Creates a set containing every flag.
+    ///
+    /// # Returns
+    /// A set containing all the variants of `T`.
+    #[must_use]
+    pub const fn all() -> Self {
+        Self::from_bits(u32::MAX)
+    }
+
+    /// Creates a set from its raw bits, ignoring bits that map to no flag.
+    ///
+    /// # Arguments
+    /// * `bits` - The raw bits of the set.
+    ///
+    /// # Returns
+    /// A set containing the flags whose bit is set.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Self {
+            bits: bits & Self::MASK,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the raw bits of the set.
+    ///
+    /// # Returns
+    /// A `u32` with the bit of every flag of the set set.
+    #[must_use]
+    pub const fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Checks whether the set is empty.
+    ///
+    /// # Returns
+    /// `true` if the set contains no flag, `false` otherwise.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Returns the number of flags in the set.
+    ///
+    /// # Returns
+    /// The number of flags in the set.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    /// Checks whether the set contains a flag.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag to look for.
+    ///
+    /// # Returns
+    /// `true` if the flag is in the set, `false` otherwise.
+    #[must_use]
+    pub fn contains(&self, flag: T) -> bool {
+        self.bits & flag.bit() != 0
+    }
+
+    /// Checks whether every flag of `other` is in the set.
+    ///
+    /// # Arguments
+    /// * `other` - The set to compare to.
+    ///
+    /// # Returns
+    /// `true` if `other` is a subset of the set, `false` otherwise.
+    #[must_use]
+    pub const fn is_superset(&self, other: Self) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    /// Checks whether the set and `other` have at least one flag in common.
+    ///
+    /// # Arguments
+    /// * `other` - The set to compare to.
+    ///
+    /// # Returns
+    /// `true` if the sets intersect, `false` otherwise.
+    #[must_use]
+    pub const fn intersects(&self, other: Self) -> bool {
+        self.bits & other.bits != 0
+    }
+
+    /// Adds a flag to the set.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag to add.
+    pub fn insert(&mut self, flag: T) {
+        self.bits |= flag.bit();
+    }
+
+    /// Removes a flag from the set.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag to remove.
+    pub fn remove(&mut self, flag: T) {
+        self.bits &= !flag.bit();
+    }
+
+    /// Returns the flags that are in the set or in `other`.
+    ///
+    /// # Arguments
+    /// * `other` - The other set.
+    ///
+    /// # Returns
+    /// The union of both sets.
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self::from_bits(self.bits | other.bits)
+    }
+
+    /// Returns the flags that are both in the set and in `other`.
+    ///
+    /// # Arguments
+    /// * `other` - The other set.
+    ///
+    /// # Returns
+    /// The intersection of both sets.
+    #[must_use]
+    pub const fn intersection(self, other: Self) -> Self {
+        Self::from_bits(self.bits & other.bits)
+    }
+
+    /// Returns the flags that are in the set but not in `other`.
+    ///
+    /// # Arguments
+    /// * `other` - The other set.
+    ///
+    /// # Returns
+    /// The difference of both sets.
+    #[must_use]
+    pub const fn difference(self, other: Self) -> Self {
+        Self::from_bits(self.bits & !other.bits)
+    }
+
+    /// Returns the flags that are not in the set.
+    ///
+    /// # Returns
+    /// The complement of the set.
+    #[must_use]
+    pub const fn complement(self) -> Self {
+        Self::from_bits(!self.bits)
+    }
+
+    /// Returns an iterator over the flags of the set, in declaration order.
+    ///
+    /// # Returns
+    /// An iterator over the flags of the set.
+    #[must_use]
+    pub fn iter(&self) -> Iter<T, N> {
+        Iter { remaining: *self }
+    }
+}
+
+impl<T, const N: usize> Clone for FlagSet<T, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, const N: usize> Copy for FlagSet<T, N> {}
+
+impl<T, const N: usize> PartialEq for FlagSet<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<T, const N: usize> Eq for FlagSet<T, N> {}
+
+impl<T, const N: usize> Hash for FlagSet<T, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits.hash(state);
+    }
+}
+
+impl<T: Flag<N>, const N: usize> Default for FlagSet<T, N> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<T: Flag<N> + fmt::Debug, const N: usize> fmt::Debug for FlagSet<T, N> {
+    /// Formats the set as the list of its flags.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Flag<N>, const N: usize> From<T> for FlagSet<T, N> {
+    /// Converts a flag into a set containing only that flag.
+    fn from(flag: T) -> Self {
+        Self::from_bits(flag.bit())
+    }
+}
+
+impl<T: Flag<N>, const N: usize> FromIterator<T> for FlagSet<T, N> {
+    /// Collects flags into a set.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: Flag<N>, const N: usize> Extend<T> for FlagSet<T, N> {
+    /// Adds flags to the set.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for flag in iter {
+            self.insert(flag);
+        }
+    }
+}
+
+impl<T: Flag<N>, const N: usize> BitOr for FlagSet<T, N> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl<T: Flag<N>, const N: usize> BitOr<T> for FlagSet<T, N> {
+    type Output = Self;
+
+    fn bitor(self, rhs: T) -> Self {
+        self.union(Self::from(rhs))
+    }
+}
+
+impl<T: Flag<N>, const N: usize> BitOrAssign for FlagSet<T, N> {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs);
+    }
+}
+
+impl<T: Flag<N>, const N: usize> BitAnd for FlagSet<T, N> {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        self.intersection(rhs)
+    }
+}
+
+impl<T: Flag<N>, const N: usize> BitAndAssign for FlagSet<T, N> {
+    fn bitand_assign(&mut self, rhs: Self) {
+        *self = self.intersection(rhs);
+    }
+}
+
+impl<T: Flag<N>, const N: usize> Sub for FlagSet<T, N> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.difference(rhs)
+    }
+}
+
+impl<T: Flag<N>, const N: usize> SubAssign for FlagSet<T, N> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = self.difference(rhs);
+    }
+}
+
+impl<T: Flag<N>, const N: usize> Not for FlagSet<T, N> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        self.complement()
+    }
+}
+
+impl<T: Flag<N>, const N: usize> IntoIterator for FlagSet<T, N> {
+    type Item = T;
+    type IntoIter = Iter<T, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Flag<N>, const N: usize> IntoIterator for &FlagSet<T, N> {
+    type Item = T;
+    type IntoIter = Iter<T, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Represents an iterator over the flags of a `FlagSet`.
+///
+/// # Type Parameters
+/// * `T` - Type of the flags.
+/// * `N` - Number of variants of `T`.
+pub struct Iter<T, const N: usize> {
+    remaining: FlagSet<T, N>,
+}
+
+impl<T: Flag<N>, const N: usize> Iterator for Iter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let flag = T::ALL[self.remaining.bits().trailing_zeros() as usize];
+        self.remaining.remove(flag);
+
+        Some(flag)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.remaining.len();
+        (len, Some(len))
+    }
+}
+
+impl<T: Flag<N>, const N: usize> ExactSizeIterator for Iter<T, N> {}
+
+impl<T: Flag<N>, const N: usize> FusedIterator for Iter<T, N> {}
+
+#[cfg(test)]
+mod tests {
+    use num_enum::IntoPrimitive;
+
+    use super::*;
+
+    flags! {
+        #[derive(Clone, Copy, Debug, Eq, IntoPrimitive, PartialEq)]
+        #[repr(u32)]
+        enum Color {
+            Red,
+            Green,
+            Blue,
+        }
+    }
+
+    type Colors = FlagSet<Color, { Color::COUNT }>;
+
+    #[test]
+    fn declares_flags() {
+        assert_eq!(Color::COUNT, 3);
+        assert_eq!(Color::ALL, [Color::Red, Color::Green, Color::Blue]);
+        assert_eq!(Color::Blue.bit(), 0b100);
+        assert_eq!((Color::Red | Color::Blue).bits(), 0b101);
+    }
+
+    #[test]
+    fn inserts_and_removes() {
+        let mut colors = Colors::empty();
+        assert!(colors.is_empty());
+
+        colors.insert(Color::Green);
+        colors.insert(Color::Green);
+        assert!(colors.contains(Color::Green));
+        assert!(!colors.contains(Color::Red));
+        assert_eq!(colors.len(), 1);
+
+        colors.remove(Color::Green);
+        colors.remove(Color::Blue);
+        assert!(colors.is_empty());
+    }
+
+    #[test]
+    fn combines_sets() {
+        let warm = Color::Red | Color::Green;
+        let cold = Color::Green | Color::Blue;
+
+        assert_eq!(warm | cold, Colors::all());
+        assert_eq!(warm.union(cold), warm | Color::Blue);
+        assert_eq!(warm & cold, Colors::from(Color::Green));
+        assert_eq!(warm - cold, Colors::from(Color::Red));
+        assert_eq!(!warm, Colors::from(Color::Blue));
+        assert!(warm.intersects(cold));
+        assert!(!warm.intersects(!warm));
+        assert!(Colors::all().is_superset(warm));
+        assert!(!warm.is_superset(cold));
+
+        let mut colors = warm;
+        colors |= cold;
+        colors &= !Colors::from(Color::Green);
+        colors -= Colors::from(Color::Blue);
+        assert_eq!(colors, Colors::from(Color::Red));
+    }
+
+    #[test]
+    fn ignores_unknown_bits() {
+        assert_eq!(Colors::from_bits(u32::MAX), Colors::all());
+        assert_eq!(Colors::all().bits(), 0b111);
+        assert_eq!(Colors::all().len(), 3);
+        assert_eq!(Colors::default(), Colors::empty());
+    }
+
+    #[test]
+    fn iterates_in_declaration_order() {
+        let colors: Colors =
+            [Color::Blue, Color::Red, Color::Blue].into_iter().collect();
+
+        let mut iter = colors.iter();
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.next(), Some(Color::Red));
+        assert_eq!(iter.next(), Some(Color::Blue));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+
+        let mut all = Colors::empty();
+        all.extend(Color::ALL.into_iter().rev());
+        assert_eq!(all.into_iter().collect::<Vec<_>>(), Color::ALL);
+    }
+
+    #[test]
+    fn formats_as_set() {
+        assert_eq!(format!("{:?}", Colors::empty()), "{}");
+        assert_eq!(format!("{:?}", Color::Blue | Color::Red), "{Red, Blue}");
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::color::{Rgb, BLUE, CYAN, MAGENTA, WHITE};
+
+/// Represents a group of devices that react to each other, so that several
+/// demos can run side by side. Devices ignore the devices of other groups.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Group(u8);
+
+impl Group {
+    /// Number of groups.
+    pub const COUNT: u8 = 4;
+    /// Colors of the groups, shown when the group changes.
+    const COLORS: [Rgb; Self::COUNT as usize] = [WHITE, CYAN, MAGENTA, BLUE];
+
+    /// Returns the identifier of the group.
+    ///
+    /// # Returns
+    /// The identifier, below `COUNT`.
+    #[must_use]
+    pub fn id(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the group after this one, cycling back to the first.
+    ///
+    /// # Returns
+    /// The next group.
+    #[must_use]
+    pub fn next(self) -> Self {
+        Self((self.0 + 1) % Self::COUNT)
+    }
+
+    /// Returns the color of the group.
+    ///
+    /// # Returns
+    /// The color shown on the LED for the group.
+    #[must_use]
+    pub fn color(self) -> Rgb {
+        Self::COLORS[usize::from(self.0)]
+    }
+}
+
+impl TryFrom<u8> for Group {
+    type Error = anyhow::Error;
+
+    /// Converts an identifier into a group.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such group.
+    fn try_from(id: u8) -> Result<Self> {
+        if id >= Self::COUNT {
+            return Err(anyhow!(
+                "Invalid group: {}, expected 0 to {}",
+                id,
+                Self::COUNT - 1
+            ));
+        }
+
+        Ok(Self(id))
+    }
+}
+
+impl FromStr for Group {
+    type Err = anyhow::Error;
+
+    /// Parses a group from its identifier, e.g. `2`.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such group.
+    fn from_str(s: &str) -> Result<Self> {
+        Self::try_from(s.parse::<u8>()?)
+    }
+}
+
+impl fmt::Display for Group {
+    /// Formats the group as its identifier.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Represents the group the device currently belongs to, shared between the
+/// state machine, which changes it, and the scanner.
+pub struct Membership {
+    group: AtomicU8,
+}
+
+impl Membership {
+    /// Creates a new `Membership` instance.
+    ///
+    /// # Arguments
+    /// * `group` - The initial group.
+    ///
+    /// # Errors
+    /// Returns an error if the membership cannot be initialized.
+    pub fn new(group: Group) -> Result<Self> {
+        Ok(Self {
+            group: AtomicU8::new(group.id()),
+        })
+    }
+
+    /// Returns the current group.
+    ///
+    /// # Returns
+    /// The group the device belongs to.
+    #[must_use]
+    pub fn get(&self) -> Group {
+        Group::try_from(self.group.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+
+    /// Changes the current group.
+    ///
+    /// # Arguments
+    /// * `group` - The new group.
+    pub fn set(&self, group: Group) {
+        self.group.store(group.id(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_groups() {
+        let groups: Vec<u8> =
+            std::iter::successors(Some(Group::default()), |group| {
+                Some(group.next())
+            })
+            .take(6)
+            .map(Group::id)
+            .collect();
+
+        assert_eq!(groups, [0, 1, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn converts_identifiers() {
+        for id in 0..Group::COUNT {
+            assert_eq!(Group::try_from(id).unwrap().id(), id);
+        }
+        assert_eq!(
+            Group::try_from(Group::COUNT).unwrap_err().to_string(),
+            "Invalid group: 4, expected 0 to 3"
+        );
+    }
+
+    #[test]
+    fn parses_identifiers() {
+        assert_eq!("3".parse::<Group>().unwrap(), Group::try_from(3).unwrap());
+        assert_eq!("2".parse::<Group>().unwrap().to_string(), "2");
+        assert!("4".parse::<Group>().is_err());
+        assert!("-1".parse::<Group>().is_err());
+        assert!("blue".parse::<Group>().is_err());
+        assert!("".parse::<Group>().is_err());
+    }
+
+    #[test]
+    fn maps_colors() {
+        let colors: Vec<Rgb> = (0..Group::COUNT)
+            .map(|id| Group::try_from(id).unwrap().color())
+            .collect();
+
+        assert_eq!(colors, [WHITE, CYAN, MAGENTA, BLUE]);
+    }
+
+    #[test]
+    fn shares_membership() {
+        let membership = Membership::new(Group::default()).unwrap();
+        membership.set(Group::default().next());
+
+        assert_eq!(membership.get().id(), 1);
+    }
+}
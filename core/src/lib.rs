@@ -0,0 +1,34 @@
+/// The hardware independent logic of the project, built and tested on the
+/// host.
+///
+/// The firmware re-exports these modules.
+///
+/// # Modules
+/// * `auth` - Authentication of the BLE advertisements.
+/// * `beacon` - Standard iBeacon and Eddystone frames.
+/// * `charge` - State of charge and charge levels of the battery.
+/// * `color` - RGB color utilities.
+/// * `election` - Election of a coordinator among the devices.
+/// * `flags` - Allocation-free sets of enum flags.
+/// * `group` - Groups of devices that react to each other.
+/// * `lru` - Bounded maps forgetting their least recently used entry.
+/// * `mesh` - Flooding of commands across the devices out of range.
+/// * `schedule` - Adaptive scheduling of the BLE scans.
+/// * `shell` - Command parsing and line editing.
+/// * `sniff` - Streaming of the advertisements heard, as JSON lines or pcap.
+/// * `sync` - Synchronisation of the blinking across devices.
+/// * `timestamp` - Points in time across restarts.
+pub mod auth;
+pub mod beacon;
+pub mod charge;
+pub mod color;
+pub mod election;
+pub mod flags;
+pub mod group;
+pub mod lru;
+pub mod mesh;
+pub mod schedule;
+pub mod shell;
+pub mod sniff;
+pub mod sync;
+pub mod timestamp;
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+/// Represents a bounded map that forgets its least recently used entry when
+/// full, e.g. the peers heard by a device.
+///
+/// An entry is used when it is inserted or replaced, not when it is read, so
+/// that the entries that stop being updated are the first to go. The maps are
+/// small, hence a linear search in a `VecDeque` rather than hashing.
+///
+/// # Type Parameters
+/// * `K` - Type of the keys.
+/// * `V` - Type of the values.
+pub struct Lru<K, V> {
+    capacity: usize,
+    entries: VecDeque<(K, V)>,
+}
+
+impl<K: PartialEq, V> Lru<K, V> {
+    /// Creates a new `Lru` instance.
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of entries.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of entries.
+    ///
+    /// # Returns
+    /// At most the capacity.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the map has no entries.
+    ///
+    /// # Returns
+    /// `true` if the map is empty, `false` otherwise.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the value of a key, without using its entry.
+    ///
+    /// # Arguments
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    /// The value, `None` if the key is unknown.
+    #[must_use]
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Inserts or replaces the value of a key, as the most recently used
+    /// entry.
+    ///
+    /// # Arguments
+    /// * `key` - The key.
+    /// * `value` - The value.
+    pub fn insert(&mut self, key: K, value: V) {
+        if let Some(i) = self.entries.iter().position(|(k, _)| *k == key) {
+            self.entries.remove(i);
+        } else if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        if self.capacity > 0 {
+            self.entries.push_back((key, value));
+        }
+    }
+
+    /// Keeps only the entries matching a predicate, in the same order.
+    ///
+    /// # Arguments
+    /// * `f` - The predicate, given the key and the value of each entry.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.entries.retain(|(key, value)| f(key, value));
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the entries, least recently used first.
+    ///
+    /// # Returns
+    /// An iterator over the keys and the values.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(lru: &Lru<u8, u32>) -> Vec<u8> {
+        lru.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn forgets_least_recently_used() {
+        let mut lru = Lru::new(3);
+        for key in 1..=4 {
+            lru.insert(key, u32::from(key) * 10);
+        }
+
+        assert_eq!(lru.len(), 3);
+        assert_eq!(keys(&lru), [2, 3, 4]);
+        assert_eq!(lru.get(&1), None);
+        assert_eq!(lru.get(&4), Some(&40));
+    }
+
+    #[test]
+    fn uses_entries_on_insert_only() {
+        let mut lru = Lru::new(3);
+        for key in 1..=3 {
+            lru.insert(key, 0);
+        }
+
+        // Reading does not save the first entry, replacing it does.
+        assert_eq!(lru.get(&1), Some(&0));
+        lru.insert(2, 20);
+        lru.insert(4, 0);
+        assert_eq!(keys(&lru), [3, 2, 4]);
+        assert_eq!(lru.get(&2), Some(&20));
+
+        lru.insert(3, 30);
+        lru.insert(5, 0);
+        assert_eq!(keys(&lru), [4, 3, 5]);
+    }
+
+    #[test]
+    fn retains_in_order() {
+        let mut lru = Lru::new(4);
+        for key in 1..=4 {
+            lru.insert(key, u32::from(key));
+        }
+
+        lru.retain(|key, value| key % 2 == 0 && *value > 2);
+        assert_eq!(keys(&lru), [4]);
+
+        lru.clear();
+        assert!(lru.is_empty());
+    }
+
+    #[test]
+    fn stores_nothing_without_capacity() {
+        let mut lru = Lru::new(0);
+        lru.insert(1, 1);
+
+        assert!(lru.is_empty());
+        assert_eq!(lru.get(&1), None);
+    }
+}
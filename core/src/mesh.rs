@@ -0,0 +1,645 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    auth::{Key, NONCE_LEN},
+    group::Group,
+    lru::Lru,
+};
+
+/// Maximum number of hops a message can be relayed over, which bounds the
+/// traffic caused by a forged time to live.
+pub const MAX_TTL: u8 = 7;
+
+/// Suffix of the name the authenticated messages are bound to, so that they
+/// cannot pass for the state of a device.
+const CONTEXT: &[u8] = b"/mesh";
+
+/// Length of the payload of a message: the command, the group and the origin.
+const PAYLOAD_LEN: usize = 8;
+
+/// Represents the part a device plays in the mesh.
+///
+/// # Variants
+/// * `Off` - The device neither sends nor obeys messages.
+/// * `Node` - The device sends messages when its button switches the system,
+///   and obeys the messages of its group.
+/// * `Relay` - The device also forwards the messages it receives, to the
+///   devices out of range of their origin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    Off,
+    Node,
+    Relay,
+}
+
+impl Role {
+    /// Names of the roles, as used by `FromStr` and `Display`.
+    pub const NAMES: [&'static str; 3] = ["off", "node", "relay"];
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    /// Parses a role from its name, e.g. `relay`.
+    ///
+    /// # Errors
+    /// Returns an error if no role has this name.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "node" => Ok(Self::Node),
+            "relay" => Ok(Self::Relay),
+            _ => Err(anyhow!(
+                "Unknown mesh role: {}, expected one of {:?}",
+                s,
+                Self::NAMES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    /// Formats the role as its name.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => Self::NAMES[0],
+            Self::Node => Self::NAMES[1],
+            Self::Relay => Self::NAMES[2],
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Represents the mesh parameters.
+///
+/// # Fields
+/// * `role` - The part the device plays in the mesh.
+/// * `ttl` - Number of times the messages sent by the device are relayed at
+///   most, up to `MAX_TTL`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MeshConfig {
+    pub role: Role,
+    pub ttl: u8,
+}
+
+impl Default for MeshConfig {
+    /// Returns the default mesh parameters: disabled, with three hops.
+    fn default() -> Self {
+        Self {
+            role: Role::Off,
+            ttl: 3,
+        }
+    }
+}
+
+impl MeshConfig {
+    /// Checks that the parameters are supported.
+    ///
+    /// # Errors
+    /// Returns an error describing the first invalid parameter.
+    pub fn validate(&self) -> Result<()> {
+        if self.ttl > MAX_TTL {
+            return Err(anyhow!(
+                "Invalid mesh_ttl: {}, expected 0 to {}",
+                self.ttl,
+                MAX_TTL
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents what a message asks the devices of a group to do.
+///
+/// # Variants
+/// * `Off` - Switch the system off.
+/// * `On` - Switch the system on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    Off,
+    On,
+}
+
+/// Represents a message flooded through the mesh.
+///
+/// Messages are identified by their origin and sequence number, so that each
+/// device handles and forwards a message once, however many relays it hears
+/// it from.
+///
+/// # Fields
+/// * `origin` - The address of the device that sent the message first.
+/// * `seq` - The sequence number of the message, increasing for its origin.
+/// * `ttl` - Number of times the message can still be relayed.
+/// * `group` - The group the message is meant for.
+/// * `command` - What the message asks for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Message {
+    pub origin: [u8; 6],
+    pub seq: u32,
+    pub ttl: u8,
+    pub group: Group,
+    pub command: Command,
+}
+
+impl Message {
+    /// Encodes the message, authenticated if there is a key.
+    ///
+    /// The time to live comes first and is not authenticated, as the relays
+    /// decrement it. Forging it only lets a message travel up to `MAX_TTL`
+    /// hops.
+    ///
+    /// # Arguments
+    /// * `key` - The key shared by the devices, if any.
+    /// * `nonce` - A nonce to encrypt the message with, which requires a key,
+    ///   so that private devices cannot be tracked by their messages.
+    /// * `name` - The name of the devices, which the message is bound to.
+    ///
+    /// # Returns
+    /// The time to live, then the command, the group and the origin, followed
+    /// by the sequence number and, if authenticated, the tag.
+    ///
+    /// # Errors
+    /// Returns an error if a nonce is given without a key.
+    pub fn encode(
+        &self,
+        key: Option<&Key>,
+        nonce: Option<[u8; NONCE_LEN]>,
+        name: &str,
+    ) -> Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(PAYLOAD_LEN);
+        payload.push(match self.command {
+            Command::Off => 0,
+            Command::On => 1,
+        });
+        payload.push(self.group.id());
+        payload.extend_from_slice(&self.origin);
+
+        let mut frame = vec![self.ttl];
+        match (key, nonce) {
+            (Some(key), Some(nonce)) => {
+                frame.extend(key.seal_secret(
+                    &context(name),
+                    nonce,
+                    self.seq,
+                    &payload,
+                )?);
+            }
+            (Some(key), None) => {
+                frame.extend(key.seal(&context(name), self.seq, &payload));
+            }
+            (None, Some(_)) => return Err(anyhow!("Encryption requires a key")),
+            (None, None) => {
+                frame.extend(payload);
+                frame.extend_from_slice(&self.seq.to_le_bytes());
+            }
+        }
+
+        Ok(frame)
+    }
+
+    /// Decodes a message, in clear or encrypted.
+    ///
+    /// # Arguments
+    /// * `key` - The key shared by the devices, if any.
+    /// * `name` - The name of the devices, which the message is bound to.
+    /// * `frame` - The encoded message.
+    ///
+    /// # Returns
+    /// The message, `None` if the frame is not a message or, with a key, was
+    /// forged.
+    #[must_use]
+    pub fn decode(key: Option<&Key>, name: &str, frame: &[u8]) -> Option<Self> {
+        let (&ttl, body) = frame.split_first()?;
+        let (seq, payload) = if let Some(key) = key {
+            key.open(&context(name), body)
+                .map(|(seq, payload)| (seq, payload.to_vec()))
+                .or_else(|| key.open_secret(&context(name), body))?
+        } else {
+            let (payload, seq) = body.split_at(body.len().checked_sub(4)?);
+            (u32::from_le_bytes(seq.try_into().ok()?), payload.to_vec())
+        };
+        let [command, group, origin @ ..] = payload.as_slice() else {
+            return None;
+        };
+
+        Some(Self {
+            origin: origin.try_into().ok()?,
+            seq,
+            ttl: (ttl <= MAX_TTL).then_some(ttl)?,
+            group: Group::try_from(*group).ok()?,
+            command: match command {
+                0 => Command::Off,
+                1 => Command::On,
+                _ => return None,
+            },
+        })
+    }
+}
+
+/// Returns the context the authenticated messages are bound to.
+///
+/// # Arguments
+/// * `name` - The name of the devices.
+///
+/// # Returns
+/// The name followed by `CONTEXT`.
+fn context(name: &str) -> Vec<u8> {
+    [name.as_bytes(), CONTEXT].concat()
+}
+
+/// Represents what a device does with a message it received.
+///
+/// # Fields
+/// * `command` - The command to obey.
+/// * `forward` - The message to relay, with its time to live decremented, if
+///   the device relays it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Receipt {
+    pub command: Command,
+    pub forward: Option<Message>,
+}
+
+/// Represents the flooding of the messages through a device.
+///
+/// A message is only handled if its sequence number is newer than the newest
+/// one of its origin, so that the copies heard from several relays, and the
+/// copies relayed back, are dropped. The origins that did not send for the
+/// longest time are forgotten first, as for the replay protection of the
+/// advertisements.
+///
+/// The newest sequence numbers are saved and restored across restarts, so that
+/// recorded messages are not obeyed again after a restart. An origin is only
+/// forgotten once `capacity` other origins sent messages, which takes as many
+/// devices holding the key if the messages are authenticated.
+pub struct Flood {
+    origin: [u8; 6],
+    role: Role,
+    newest: Lru<[u8; 6], u32>,
+}
+
+impl Flood {
+    /// Size of a saved origin: its address and its newest sequence number.
+    pub const SAVED_LEN: usize = 6 + 4;
+
+    /// Creates a new `Flood` instance.
+    ///
+    /// # Arguments
+    /// * `origin` - The address of the device, whose own messages are dropped.
+    /// * `role` - The part the device plays in the mesh.
+    /// * `capacity` - Maximum number of origins remembered.
+    #[must_use]
+    pub fn new(origin: [u8; 6], role: Role, capacity: usize) -> Self {
+        Self {
+            origin,
+            role,
+            newest: Lru::new(capacity),
+        }
+    }
+
+    /// Returns the part the device plays in the mesh.
+    ///
+    /// # Returns
+    /// The role the flood was created with.
+    #[must_use]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Saves the newest sequence number of each origin.
+    ///
+    /// # Returns
+    /// The origins and their newest sequence numbers, least recently heard
+    /// first, `SAVED_LEN` bytes each.
+    #[must_use]
+    pub fn save(&self) -> Vec<u8> {
+        let mut saved = Vec::with_capacity(self.newest.len() * Self::SAVED_LEN);
+        for (origin, seq) in self.newest.iter() {
+            saved.extend_from_slice(origin);
+            saved.extend_from_slice(&seq.to_le_bytes());
+        }
+
+        saved
+    }
+
+    /// Restores the newest sequence numbers saved before a restart.
+    ///
+    /// # Arguments
+    /// * `saved` - The output of `save`. A truncated origin is ignored, as are
+    ///   the least recently heard origins beyond the capacity.
+    pub fn restore(&mut self, saved: &[u8]) {
+        self.newest.clear();
+        for origin in saved.chunks_exact(Self::SAVED_LEN) {
+            let (address, seq) = origin.split_at(6);
+            self.newest.insert(
+                address.try_into().unwrap_or_default(),
+                u32::from_le_bytes(seq.try_into().unwrap_or_default()),
+            );
+        }
+    }
+
+    /// Creates a message originating from the device.
+    ///
+    /// # Arguments
+    /// * `seq` - The sequence number, which must never decrease, even across
+    ///   restarts.
+    /// * `ttl` - Number of times the message can be relayed.
+    /// * `group` - The group the message is meant for.
+    /// * `command` - What the message asks for.
+    ///
+    /// # Returns
+    /// The message to send.
+    #[must_use]
+    pub fn originate(
+        &self,
+        seq: u32,
+        ttl: u8,
+        group: Group,
+        command: Command,
+    ) -> Message {
+        Message {
+            origin: self.origin,
+            seq,
+            ttl: ttl.min(MAX_TTL),
+            group,
+            command,
+        }
+    }
+
+    /// Handles a message heard from a device in range.
+    ///
+    /// # Arguments
+    /// * `message` - The message, already checked to be meant for the group of
+    ///   the device.
+    ///
+    /// # Returns
+    /// What to do with the message, `None` if it is disabled, from the device
+    /// itself or already handled.
+    pub fn receive(&mut self, message: Message) -> Option<Receipt> {
+        if self.role == Role::Off || message.origin == self.origin {
+            return None;
+        }
+
+        if self
+            .newest
+            .get(&message.origin)
+            .is_some_and(|newest| *newest >= message.seq)
+        {
+            return None;
+        }
+        self.newest.insert(message.origin, message.seq);
+
+        let forward =
+            (self.role == Role::Relay && message.ttl > 0).then(|| Message {
+                ttl: message.ttl - 1,
+                ..message
+            });
+        Some(Receipt {
+            command: message.command,
+            forward,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    const NAME: &str = "ESPlayground";
+
+    /// Floods a message from the first node of a network.
+    ///
+    /// Every node only hears its neighbours, and hears every frame three
+    /// times.
+    ///
+    /// # Returns
+    /// The command each node obeyed, and the number of frames sent.
+    fn flood(
+        roles: &[Role],
+        neighbours: impl Fn(usize, usize) -> bool,
+        key: Option<&Key>,
+        ttl: u8,
+    ) -> (Vec<Option<Command>>, usize) {
+        let mut floods: Vec<_> = (0..roles.len())
+            .map(|i| {
+                Flood::new([0, 0, 0, 0, 0, u8::try_from(i).unwrap()], roles[i], 16)
+            })
+            .collect();
+        let mut obeyed = vec![None; roles.len()];
+        let message = floods[0].originate(5, ttl, Group::default(), Command::On);
+        let mut air =
+            VecDeque::from([(0, message.encode(key, None, NAME).unwrap())]);
+        let mut sent = 0;
+
+        while let Some((from, frame)) = air.pop_front() {
+            sent += 1;
+            for to in
+                (0..roles.len()).filter(|to| *to != from && neighbours(from, *to))
+            {
+                for _ in 0..3 {
+                    let message = Message::decode(key, NAME, &frame).unwrap();
+                    let Some(receipt) = floods[to].receive(message) else {
+                        continue;
+                    };
+                    obeyed[to] = Some(receipt.command);
+                    if let Some(forward) = receipt.forward {
+                        air.push_back((
+                            to,
+                            forward.encode(key, None, NAME).unwrap(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        (obeyed, sent)
+    }
+
+    #[test]
+    fn floods_a_line() {
+        let key: Key = "000102030405060708090a0b0c0d0e0f".parse().unwrap();
+        let line = |a: usize, b: usize| a.abs_diff(b) == 1;
+        let relays = [Role::Relay; 6];
+
+        for key in [None, Some(&key)] {
+            let (obeyed, sent) = flood(&relays, line, key, MAX_TTL);
+            assert_eq!(obeyed[0], None);
+            assert!(obeyed[1..]
+                .iter()
+                .all(|command| *command == Some(Command::On)));
+            // Every relay forwards the message once, the last one back.
+            assert_eq!(sent, relays.len());
+
+            // The origin and two relays reach three nodes.
+            let (obeyed, _) = flood(&relays, line, key, 2);
+            assert_eq!(obeyed.iter().flatten().count(), 3);
+        }
+    }
+
+    #[test]
+    fn stops_at_nodes() {
+        let mut roles = [Role::Relay; 6];
+        roles[2] = Role::Node;
+
+        let (obeyed, _) = flood(&roles, |a, b| a.abs_diff(b) == 1, None, MAX_TTL);
+        assert_eq!(
+            obeyed,
+            [None, Some(Command::On), Some(Command::On), None, None, None]
+        );
+    }
+
+    #[test]
+    fn floods_a_grid() {
+        let width = 5;
+        let grid = |a: usize, b: usize| {
+            (a % width).abs_diff(b % width) + (a / width).abs_diff(b / width) == 1
+        };
+        let relays = vec![Role::Relay; width * width];
+
+        let (obeyed, sent) = flood(&relays, grid, None, MAX_TTL);
+        assert!(obeyed[1..].iter().all(Option::is_some));
+        // The far corner is reached with a time to live of zero.
+        assert_eq!(sent, relays.len() - 1);
+    }
+
+    #[test]
+    fn encodes_messages() {
+        let key: Key = "000102030405060708090a0b0c0d0e0f".parse().unwrap();
+        let group = Group::try_from(2).unwrap();
+        let message =
+            Flood::new([1; 6], Role::Relay, 2).originate(42, 9, group, Command::Off);
+        assert_eq!(message.ttl, MAX_TTL);
+
+        assert_eq!(message.encode(None, None, NAME).unwrap().len(), 13);
+        let mut frame = message.encode(Some(&key), None, NAME).unwrap();
+        assert_eq!(frame.len(), 17);
+        assert_eq!(Message::decode(Some(&key), NAME, &frame), Some(message));
+        assert_eq!(Message::decode(Some(&key), "other", &frame), None);
+
+        // The time to live is not authenticated, but bounded.
+        frame[0] = 3;
+        assert_eq!(Message::decode(Some(&key), NAME, &frame).unwrap().ttl, 3);
+        frame[0] = MAX_TTL + 1;
+        assert_eq!(Message::decode(Some(&key), NAME, &frame), None);
+        frame[0] = 3;
+        frame[2] ^= 1;
+        assert_eq!(Message::decode(Some(&key), NAME, &frame), None);
+
+        let nonce = key.nonce(&[1; 6], 0);
+        let frame = message.encode(Some(&key), Some(nonce), NAME).unwrap();
+        assert_eq!(frame.len(), 21);
+        assert_eq!(Message::decode(Some(&key), NAME, &frame), Some(message));
+        assert!(message.encode(None, Some(nonce), NAME).is_err());
+    }
+
+    #[test]
+    fn rejects_state_frames() {
+        let key: Key = "000102030405060708090a0b0c0d0e0f".parse().unwrap();
+        let state = key.seal(NAME.as_bytes(), 1, &[1, 0, 1, 1, 1, 1, 1, 1]);
+
+        assert_eq!(
+            Message::decode(Some(&key), NAME, &[&[3], state.as_slice()].concat()),
+            None
+        );
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let mut flood = Flood::new([9; 6], Role::Node, 2);
+        let message = Flood::new([1; 6], Role::Node, 2).originate(
+            1,
+            3,
+            Group::default(),
+            Command::On,
+        );
+
+        assert_eq!(flood.receive(message).unwrap().forward, None);
+        assert_eq!(flood.receive(message), None);
+        assert_eq!(flood.receive(Message { seq: 0, ..message }), None);
+        assert!(flood.receive(Message { seq: 2, ..message }).is_some());
+        assert_eq!(
+            flood.receive(Message {
+                origin: [9; 6],
+                ..message
+            }),
+            None
+        );
+        assert_eq!(Flood::new([9; 6], Role::Off, 2).receive(message), None);
+    }
+
+    #[test]
+    fn forgets_least_recent_origin() {
+        let mut flood = Flood::new([9; 6], Role::Node, 2);
+        let message = Flood::new([1; 6], Role::Node, 2).originate(
+            1,
+            3,
+            Group::default(),
+            Command::On,
+        );
+
+        flood.receive(message);
+        flood.receive(Message {
+            origin: [2; 6],
+            ..message
+        });
+        flood.receive(Message {
+            origin: [3; 6],
+            ..message
+        });
+        assert!(flood.receive(message).is_some());
+    }
+
+    #[test]
+    fn remembers_across_restarts() {
+        let message = Flood::new([1; 6], Role::Node, 2).originate(
+            1,
+            3,
+            Group::default(),
+            Command::On,
+        );
+        let mut flood = Flood::new([9; 6], Role::Relay, 2);
+        for origin in 1..=3 {
+            flood.receive(Message {
+                origin: [origin; 6],
+                ..message
+            });
+        }
+        let saved = flood.save();
+        assert_eq!(saved.len(), 2 * Flood::SAVED_LEN);
+
+        let mut restarted = Flood::new([9; 6], Role::Relay, 2);
+        restarted.restore(&saved);
+        assert_eq!(
+            restarted.receive(Message {
+                origin: [2; 6],
+                ..message
+            }),
+            None
+        );
+        assert_eq!(
+            restarted.receive(Message {
+                origin: [3; 6],
+                ..message
+            }),
+            None
+        );
+        assert!(restarted
+            .receive(Message {
+                origin: [3; 6],
+                seq: 2,
+                ..message
+            })
+            .is_some());
+
+        // The least recently heard origins do not fit, truncated ones are
+        // ignored.
+        let mut smaller = Flood::new([9; 6], Role::Relay, 1);
+        smaller.restore(&saved[..saved.len() - 1]);
+        assert_eq!(smaller.save(), saved[..Flood::SAVED_LEN]);
+        smaller.restore(&saved);
+        assert_eq!(smaller.save(), saved[Flood::SAVED_LEN..]);
+    }
+}
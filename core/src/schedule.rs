@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Represents the trade-off between discovery latency and power of the BLE
+/// scans.
+///
+/// # Variants
+/// * `Fast` - Scan almost continuously, peers are found within seconds.
+/// * `Balanced` - Back off to a scan every few seconds when nobody is around.
+/// * `LowPower` - Scan briefly, and back off to a scan per minute when nobody
+///   is around.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    Fast,
+    Balanced,
+    LowPower,
+}
+
+/// Represents the parameters of a scan policy.
+///
+/// # Fields
+/// * `window` - Duration of a scan.
+/// * `min_interval` - Interval between the scans when something is happening.
+/// * `max_interval` - Interval between the scans once fully backed off.
+/// * `patience` - Number of consecutive scans without peers before backing
+///   off.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Params {
+    pub window: Duration,
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub patience: u32,
+}
+
+impl Policy {
+    /// Names of the policies, as used by `FromStr` and `Display`.
+    pub const NAMES: [&'static str; 3] = ["fast", "balanced", "low_power"];
+
+    /// Returns the parameters of the policy.
+    ///
+    /// # Returns
+    /// The scan window, the interval bounds and the patience.
+    #[must_use]
+    pub const fn params(self) -> Params {
+        match self {
+            Self::Fast => Params {
+                window: Duration::from_secs(1),
+                min_interval: Duration::from_secs(1),
+                max_interval: Duration::from_secs(2),
+                patience: 10,
+            },
+            Self::Balanced => Params {
+                window: Duration::from_secs(1),
+                min_interval: Duration::from_secs(1),
+                max_interval: Duration::from_secs(10),
+                patience: 5,
+            },
+            Self::LowPower => Params {
+                window: Duration::from_millis(500),
+                min_interval: Duration::from_secs(2),
+                max_interval: Duration::from_secs(60),
+                patience: 3,
+            },
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    /// Parses a policy from its name, e.g. `low_power`.
+    ///
+    /// # Errors
+    /// Returns an error if no policy has this name.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "balanced" => Ok(Self::Balanced),
+            "low_power" => Ok(Self::LowPower),
+            _ => Err(anyhow!(
+                "Unknown scan policy: {}, expected one of {:?}",
+                s,
+                Self::NAMES
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    /// Formats the policy as its name.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Fast => Self::NAMES[0],
+            Self::Balanced => Self::NAMES[1],
+            Self::LowPower => Self::NAMES[2],
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Represents the adaptive schedule of the BLE scans.
+///
+/// The interval between the scans is reset to its minimum when something
+/// happens, i.e. when the system state changes or a peer is found, and doubles
+/// after every `patience` consecutive scans without peers, up to its maximum.
+///
+/// The schedule only counts scans, so that it does not depend on a clock.
+pub struct Schedule {
+    params: Params,
+    interval: Duration,
+    misses: u32,
+}
+
+impl Schedule {
+    /// Creates a new `Schedule` instance, scanning aggressively.
+    ///
+    /// # Arguments
+    /// * `policy` - The scan policy.
+    #[must_use]
+    pub fn new(policy: Policy) -> Self {
+        let params = policy.params();
+
+        Self {
+            params,
+            interval: params.min_interval,
+            misses: 0,
+        }
+    }
+
+    /// Returns the duration of a scan.
+    ///
+    /// # Returns
+    /// The scan window of the policy.
+    #[must_use]
+    pub fn window(&self) -> Duration {
+        self.params.window
+    }
+
+    /// Returns the interval to wait for before the next scan.
+    ///
+    /// # Returns
+    /// The current interval, between the bounds of the policy.
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Resets the interval to its minimum, e.g. after a state change.
+    pub fn reset(&mut self) {
+        self.interval = self.params.min_interval;
+        self.misses = 0;
+    }
+
+    /// Adapts the interval to the result of a scan.
+    ///
+    /// # Arguments
+    /// * `found` - Whether the scan found a peer.
+    pub fn record(&mut self, found: bool) {
+        if found {
+            self.reset();
+            return;
+        }
+
+        self.misses += 1;
+        if self.misses >= self.params.patience {
+            self.misses = 0;
+            self.interval = (self.interval * 2).min(self.params.max_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [Policy; 3] = [Policy::Fast, Policy::Balanced, Policy::LowPower];
+
+    /// Records `count` scans without peers.
+    fn miss(schedule: &mut Schedule, count: u32) {
+        for _ in 0..count {
+            schedule.record(false);
+        }
+    }
+
+    #[test]
+    fn starts_aggressive() {
+        for policy in POLICIES {
+            let schedule = Schedule::new(policy);
+            assert_eq!(schedule.interval(), policy.params().min_interval);
+            assert_eq!(schedule.window(), policy.params().window);
+        }
+    }
+
+    #[test]
+    fn backs_off_after_patience() {
+        let params = Policy::Balanced.params();
+        let mut schedule = Schedule::new(Policy::Balanced);
+
+        miss(&mut schedule, params.patience - 1);
+        assert_eq!(schedule.interval(), params.min_interval);
+        miss(&mut schedule, 1);
+        assert_eq!(schedule.interval(), params.min_interval * 2);
+        miss(&mut schedule, params.patience);
+        assert_eq!(schedule.interval(), params.min_interval * 4);
+    }
+
+    #[test]
+    fn caps_at_max_interval() {
+        for policy in POLICIES {
+            let params = policy.params();
+            let mut schedule = Schedule::new(policy);
+
+            miss(&mut schedule, params.patience * 32);
+            assert_eq!(schedule.interval(), params.max_interval);
+        }
+    }
+
+    #[test]
+    fn resets_on_found() {
+        let params = Policy::LowPower.params();
+        let mut schedule = Schedule::new(Policy::LowPower);
+
+        miss(&mut schedule, params.patience * 3 - 1);
+        assert!(schedule.interval() > params.min_interval);
+        schedule.record(true);
+        assert_eq!(schedule.interval(), params.min_interval);
+        // The misses before the peer was found do not count anymore.
+        miss(&mut schedule, params.patience - 1);
+        assert_eq!(schedule.interval(), params.min_interval);
+
+        miss(&mut schedule, params.patience);
+        schedule.reset();
+        assert_eq!(schedule.interval(), params.min_interval);
+    }
+
+    #[test]
+    fn names_policies() {
+        for policy in POLICIES {
+            assert_eq!(policy.to_string().parse::<Policy>().ok(), Some(policy));
+        }
+        assert!("slow".parse::<Policy>().is_err());
+    }
+}
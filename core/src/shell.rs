@@ -0,0 +1,502 @@
+use anyhow::{anyhow, Result};
+use log::LevelFilter;
+use std::{collections::VecDeque, fmt::Write};
+
+use crate::{color::Rgb, timestamp::Timestamp};
+
+/// Maximum length of a command line.
+const MAX_LINE: usize = 128;
+
+/// Number of command lines kept in the history.
+const HISTORY_LEN: usize = 16;
+
+/// Description of the commands, printed by `help`.
+pub const HELP: &str = "\
+help                            Show this help
+state                           Show the state and the LED color
+trigger <name>                  Publish an external trigger, e.g. ButtonPressed
+led <rrggbb|auto>               Override the LED color, or follow the state
+scan now                        Request an immediate BLE scan
+peers                           List the devices seen by the scanner
+crashes                         Show the last crashes and the failed boots
+config get [key]                Show the configuration, or one setting
+config set <key> <value>        Change a setting, `none` disables it
+reboot                          Restart the device
+log level [module] <level>      Set the log level of a module, or the default
+log recent                      Show the most recent log lines
+journal [json|csv] [from] [to]  Export the journal, between boot:ms timestamps
+metrics [prometheus|json]       Export the runtime metrics
+sniff [json|pcap]               Stream every advertisement, any key stops
+";
+
+/// Represents a command of the shell.
+///
+/// # Variants
+/// * `Help` - Describe the commands.
+/// * `State` - Show the state of the state machine.
+/// * `Trigger` - Publish the external trigger with the given name.
+/// * `Led` - Override the LED color, or follow the state if `None`.
+/// * `ScanNow` - Request an immediate BLE scan.
+/// * `Peers` - List the devices seen by the scanner.
+/// * `Crashes` - Show the crash records kept across restarts.
+/// * `ConfigGet` - Show the configuration, or only the given setting.
+/// * `ConfigSet` - Change a setting.
+/// * `Reboot` - Restart the device.
+/// * `LogLevel` - Set the level of a module, or the default level if `None`.
+/// * `LogRecent` - Show the most recent log lines.
+/// * `Journal` - Export the journal entries from `from` until `to`, both
+///   unbounded if `None`, as JSON if `json` or as CSV otherwise.
+/// * `Metrics` - Export the metrics, as JSON if `json` or as Prometheus text
+///   otherwise.
+/// * `Sniff` - Stream the advertisements heard, as a pcap capture if `pcap` or
+///   as JSON lines otherwise.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Command {
+    Help,
+    State,
+    Trigger(String),
+    Led(Option<Rgb>),
+    ScanNow,
+    Peers,
+    Crashes,
+    ConfigGet(Option<String>),
+    ConfigSet(String, String),
+    Reboot,
+    LogLevel(Option<String>, LevelFilter),
+    LogRecent,
+    Journal {
+        json: bool,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    },
+    Metrics {
+        json: bool,
+    },
+    Sniff {
+        pcap: bool,
+    },
+}
+
+/// Parses a log level, e.g. `debug` or `off`.
+///
+/// # Arguments
+/// * `level` - The name of the level.
+///
+/// # Errors
+/// Returns an error if the level does not exist.
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level
+        .parse()
+        .map_err(|_| anyhow!("Invalid log level: {}", level))
+}
+
+/// Parses the time range of a `journal` command, e.g. `3` or `3:1500 4`.
+///
+/// # Arguments
+/// * `json` - Whether to export as JSON rather than CSV.
+/// * `range` - The optional start and end of the range.
+///
+/// # Errors
+/// Returns an error if a bound is not a valid timestamp.
+fn parse_journal(json: bool, range: &[&str]) -> Result<Command> {
+    let mut bounds = range.iter().map(|bound| bound.parse::<Timestamp>());
+
+    Ok(Command::Journal {
+        json,
+        from: bounds.next().transpose()?,
+        to: bounds.next().transpose()?,
+    })
+}
+
+/// Parses a command line.
+///
+/// # Arguments
+/// * `line` - The command line, words separated by whitespace.
+///
+/// # Errors
+/// Returns an error describing the expected usage if the line is not a valid
+/// command.
+pub fn parse(line: &str) -> Result<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let command = match words.as_slice() {
+        ["help"] => Command::Help,
+        ["state"] => Command::State,
+        ["trigger", name] => Command::Trigger((*name).into()),
+        ["led", "auto"] => Command::Led(None),
+        ["led", color] => Command::Led(Some(color.parse()?)),
+        ["scan", "now"] => Command::ScanNow,
+        ["peers"] => Command::Peers,
+        ["crashes"] => Command::Crashes,
+        ["config", "get"] => Command::ConfigGet(None),
+        ["config", "get", key] => Command::ConfigGet(Some((*key).into())),
+        ["config", "set", key, value] => {
+            Command::ConfigSet((*key).into(), (*value).into())
+        }
+        ["reboot"] => Command::Reboot,
+        ["log", "recent"] => Command::LogRecent,
+        ["log", "level", level] => Command::LogLevel(None, parse_level(level)?),
+        ["log", "level", module, level] => {
+            Command::LogLevel(Some((*module).into()), parse_level(level)?)
+        }
+        ["journal", format @ ("json" | "csv"), range @ ..] if range.len() <= 2 => {
+            parse_journal(*format == "json", range)?
+        }
+        ["journal", range @ ..] if range.len() <= 2 => parse_journal(true, range)?,
+        ["metrics"] | ["metrics", "prometheus"] => Command::Metrics { json: false },
+        ["metrics", "json"] => Command::Metrics { json: true },
+        ["sniff"] | ["sniff", "json"] => Command::Sniff { pcap: false },
+        ["sniff", "pcap"] => Command::Sniff { pcap: true },
+        [name, ..] => {
+            // Point at the usage of the command if it exists, the one sharing
+            // the most leading words with the line, e.g. `config set` rather
+            // than `config get`.
+            let shared = |usage: &str| {
+                usage
+                    .split_whitespace()
+                    .zip(&words)
+                    .take_while(|(expected, word)| expected == *word)
+                    .count()
+            };
+            return Err(HELP
+                .lines()
+                .map(|usage| usage.split("  ").next().unwrap_or(usage))
+                .rev()
+                .max_by_key(|usage| shared(usage))
+                .filter(|usage| shared(usage) > 0)
+                .map_or_else(
+                    || anyhow!("Unknown command: {}, try `help`", name),
+                    |usage| anyhow!("Usage: {}", usage),
+                ));
+        }
+        [] => Err(anyhow!("Empty command"))?,
+    };
+
+    Ok(command)
+}
+
+/// Represents the state of an escape sequence being received.
+///
+/// # Variants
+/// * `None` - No escape sequence is being received.
+/// * `Esc` - `ESC` was received.
+/// * `Bracket` - `ESC [` was received.
+#[derive(Clone, Copy)]
+enum Escape {
+    None,
+    Esc,
+    Bracket,
+}
+
+/// Represents a line editor for a VT100-compatible terminal.
+///
+/// It supports backspace, `Ctrl-C` and `Ctrl-U` to discard the line, and the
+/// up and down arrows to browse the history.
+pub struct Editor {
+    prompt: &'static str,
+    line: String,
+    history: VecDeque<String>,
+    browsing: Option<usize>,
+    escape: Escape,
+    carriage_return: bool,
+}
+
+impl Editor {
+    /// Creates a new `Editor` instance.
+    ///
+    /// # Arguments
+    /// * `prompt` - The prompt printed before each line.
+    #[must_use]
+    pub fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: String::new(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            browsing: None,
+            escape: Escape::None,
+            carriage_return: false,
+        }
+    }
+
+    /// Returns the prompt.
+    ///
+    /// # Returns
+    /// The prompt printed before each line.
+    #[must_use]
+    pub fn prompt(&self) -> &'static str {
+        self.prompt
+    }
+
+    /// Replaces the line being edited, e.g. with an entry of the history.
+    ///
+    /// # Arguments
+    /// * `line` - The new line.
+    /// * `echo` - The string the terminal output is written to.
+    fn replace(&mut self, line: String, echo: &mut String) {
+        self.line = line;
+        let _ = write!(echo, "\r\x1b[K{}{}", self.prompt, self.line);
+    }
+
+    /// Moves in the history.
+    ///
+    /// # Arguments
+    /// * `older` - `true` to move to an older entry, `false` to a newer one.
+    /// * `echo` - The string the terminal output is written to.
+    fn browse(&mut self, older: bool, echo: &mut String) {
+        let len = self.history.len();
+        let browsing = match (self.browsing, older) {
+            (None, true) if len > 0 => Some(len - 1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < len => Some(i + 1),
+            _ => None,
+        };
+
+        if browsing != self.browsing || browsing.is_some() {
+            let line = browsing
+                .and_then(|i| self.history.get(i).cloned())
+                .unwrap_or_default();
+            self.browsing = browsing;
+            self.replace(line, echo);
+        }
+    }
+
+    /// Ends the line being edited and adds it to the history.
+    ///
+    /// # Returns
+    /// The line.
+    fn submit(&mut self) -> String {
+        let line = std::mem::take(&mut self.line);
+        self.browsing = None;
+
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+
+        line
+    }
+
+    /// Handles a byte received from the terminal.
+    ///
+    /// # Arguments
+    /// * `byte` - The received byte.
+    /// * `echo` - The string the terminal output is written to.
+    ///
+    /// # Returns
+    /// The line, once it is complete.
+    pub fn feed(&mut self, byte: u8, echo: &mut String) -> Option<String> {
+        let carriage_return = std::mem::replace(&mut self.carriage_return, false);
+
+        match (self.escape, byte) {
+            (Escape::None, 0x1b) => self.escape = Escape::Esc,
+            (Escape::Esc, b'[') => self.escape = Escape::Bracket,
+            (Escape::Bracket, b'A' | b'B') => {
+                self.escape = Escape::None;
+                self.browse(byte == b'A', echo);
+            }
+            (Escape::Esc | Escape::Bracket, _) => self.escape = Escape::None,
+            // Terminals send either CR, LF or CR LF.
+            (Escape::None, b'\n') if carriage_return => {}
+            (Escape::None, b'\r' | b'\n') => {
+                self.carriage_return = byte == b'\r';
+                echo.push_str("\r\n");
+                return Some(self.submit());
+            }
+            (Escape::None, 0x08 | 0x7f) => {
+                if self.line.pop().is_some() {
+                    echo.push_str("\x08 \x08");
+                }
+            }
+            // Ctrl-C and Ctrl-U.
+            (Escape::None, 0x03 | 0x15) => self.replace(String::new(), echo),
+            (Escape::None, b' '..=b'~') if self.line.len() < MAX_LINE => {
+                self.line.push(char::from(byte));
+                echo.push(char::from(byte));
+            }
+            (Escape::None, _) => {}
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds bytes to an editor.
+    ///
+    /// # Arguments
+    /// * `editor` - The editor.
+    /// * `bytes` - The bytes received from the terminal.
+    ///
+    /// # Returns
+    /// The completed lines and the echo.
+    fn feed(editor: &mut Editor, bytes: &[u8]) -> (Vec<String>, String) {
+        let mut echo = String::new();
+        let lines = bytes
+            .iter()
+            .filter_map(|byte| editor.feed(*byte, &mut echo))
+            .collect();
+
+        (lines, echo)
+    }
+
+    fn journal(
+        json: bool,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    ) -> Command {
+        Command::Journal { json, from, to }
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let cases = [
+            ("help", Command::Help),
+            ("state", Command::State),
+            (
+                "trigger ButtonPressed",
+                Command::Trigger("ButtonPressed".into()),
+            ),
+            ("led auto", Command::Led(None)),
+            ("scan now", Command::ScanNow),
+            ("peers", Command::Peers),
+            ("crashes", Command::Crashes),
+            ("config get", Command::ConfigGet(None)),
+            ("config get name", Command::ConfigGet(Some("name".into()))),
+            (
+                "config set name demo",
+                Command::ConfigSet("name".into(), "demo".into()),
+            ),
+            ("reboot", Command::Reboot),
+            (
+                "log level debug",
+                Command::LogLevel(None, LevelFilter::Debug),
+            ),
+            (
+                "log level esp_layground::ble off",
+                Command::LogLevel(
+                    Some("esp_layground::ble".into()),
+                    LevelFilter::Off,
+                ),
+            ),
+            ("log recent", Command::LogRecent),
+            ("journal", journal(true, None, None)),
+            ("journal json", journal(true, None, None)),
+            ("journal csv", journal(false, None, None)),
+            ("metrics", Command::Metrics { json: false }),
+            ("metrics prometheus", Command::Metrics { json: false }),
+            ("metrics json", Command::Metrics { json: true }),
+            ("sniff", Command::Sniff { pcap: false }),
+            ("sniff json", Command::Sniff { pcap: false }),
+            ("sniff pcap", Command::Sniff { pcap: true }),
+        ];
+
+        for (line, command) in cases {
+            assert_eq!(parse(line).unwrap(), command, "{line}");
+        }
+        assert_eq!(
+            parse("  led   ff8000 ").unwrap(),
+            Command::Led(Some("ff8000".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn parses_journal_ranges() {
+        let at = |timestamp: &str| Some(timestamp.parse().unwrap());
+
+        assert_eq!(parse("journal 3").unwrap(), journal(true, at("3"), None));
+        assert_eq!(
+            parse("journal csv 3:1500 4").unwrap(),
+            journal(false, at("3:1500"), at("4"))
+        );
+        assert_eq!(
+            parse("journal 0:10 0:20").unwrap(),
+            journal(true, at("0:10"), at("0:20"))
+        );
+        assert_eq!(
+            parse("journal json soon").unwrap_err().to_string(),
+            "Invalid timestamp: soon, expected boot:ms"
+        );
+        assert_eq!(
+            parse("journal csv 1 2 3").unwrap_err().to_string(),
+            "Usage: journal [json|csv] [from] [to]"
+        );
+    }
+
+    #[test]
+    fn reports_usage() {
+        assert_eq!(
+            parse("frobnicate").unwrap_err().to_string(),
+            "Unknown command: frobnicate, try `help`"
+        );
+        assert_eq!(
+            parse("scan later").unwrap_err().to_string(),
+            "Usage: scan now"
+        );
+        assert_eq!(
+            parse("config set name").unwrap_err().to_string(),
+            "Usage: config set <key> <value>"
+        );
+        assert_eq!(
+            parse("config unset name").unwrap_err().to_string(),
+            "Usage: config get [key]"
+        );
+        assert_eq!(
+            parse("log level esp_layground::ble loud now")
+                .unwrap_err()
+                .to_string(),
+            "Usage: log level [module] <level>"
+        );
+        assert!(parse("log level loud").is_err());
+        assert!(parse("led orange").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn edits_lines() {
+        let mut editor = Editor::new("> ");
+
+        let (lines, echo) = feed(&mut editor, b"stat\r");
+        assert_eq!(lines, ["stat"]);
+        assert_eq!(echo, "stat\r\n");
+
+        // Backspace erases the last character, and nothing once empty.
+        let (lines, echo) = feed(&mut editor, b"peerx\x7f\x08\x08\x08\x08\x08s\n");
+        assert_eq!(lines, ["s"]);
+        assert_eq!(echo, format!("peerx{}s\r\n", "\x08 \x08".repeat(5)));
+
+        // The line feed following a carriage return is not another line.
+        let (lines, _) = feed(&mut editor, b"help\r\nstate\r\n");
+        assert_eq!(lines, ["help", "state"]);
+
+        // Ctrl-U discards the line.
+        let (lines, _) = feed(&mut editor, b"reboot\x15peers\r");
+        assert_eq!(lines, ["peers"]);
+
+        // Unprintable bytes are ignored.
+        let (lines, _) = feed(&mut editor, b"pe\x01ers\r");
+        assert_eq!(lines, ["peers"]);
+    }
+
+    #[test]
+    fn browses_history() {
+        let mut editor = Editor::new("> ");
+        feed(&mut editor, b"help\rstate\rstate\r\r");
+
+        // Repeated and empty lines are not recorded.
+        let (_, echo) = feed(&mut editor, b"\x1b[A");
+        assert_eq!(echo, "\r\x1b[K> state");
+        let (lines, _) = feed(&mut editor, b"\x1b[A\x1b[A\r");
+        assert_eq!(lines, ["help"]);
+
+        // Moving past the newest entry clears the line.
+        let (_, echo) = feed(&mut editor, b"\x1b[A\x1b[B");
+        assert_eq!(echo, "\r\x1b[K> help\r\x1b[K> ");
+        let (lines, _) = feed(&mut editor, b"peers\r");
+        assert_eq!(lines, ["peers"]);
+    }
+}
@@ -0,0 +1,677 @@
+use std::{
+    fmt::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{beacon::Uuid, lru::Lru};
+
+/// Access address of the advertising channels.
+const ACCESS_ADDRESS: u32 = 0x8E89_BED6;
+
+/// Initial value of the CRC on the advertising channels, `0x555555` with its
+/// bits reversed as the register shifts the least significant bit first.
+const CRC_INIT: u32 = 0x00AA_AAAA;
+
+/// Taps of the CRC polynomial, `0x00065B` with its bits reversed.
+const CRC_TAPS: u32 = 0x005A_6000;
+
+/// Link type of the pcap captures: Bluetooth LE link layer with a pseudo
+/// header carrying the signal power.
+const LINKTYPE: u32 = 256;
+
+/// Flags of the pseudo header: packet dewhitened, signal power and reference
+/// access address valid, CRC checked and valid, as the controller only
+/// reports the packets with a valid CRC.
+const PHDR_FLAGS: u16 = 0x0001 | 0x0002 | 0x0010 | 0x0400 | 0x0800;
+
+/// Maximum length of a captured packet.
+const SNAPLEN: u32 = 255;
+
+/// Represents a stream format of the sniffer.
+///
+/// # Variants
+/// * `Json` - One JSON object per line and advertisement.
+/// * `Pcap` - A pcap capture of the advertising packets, as Wireshark reads.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    Pcap,
+}
+
+/// Represents the type of an advertising packet.
+///
+/// # Variants
+/// * `AdvInd` - Connectable and scannable advertisement.
+/// * `DirectInd` - Connectable advertisement directed at a device.
+/// * `NonconnInd` - Non-connectable and non-scannable advertisement.
+/// * `ScanRsp` - Response to a scan request.
+/// * `ScanInd` - Scannable advertisement.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pdu {
+    AdvInd,
+    DirectInd,
+    NonconnInd,
+    ScanRsp,
+    ScanInd,
+}
+
+impl Pdu {
+    /// Returns the code of the packet type in the link layer header.
+    ///
+    /// # Returns
+    /// The 4-bit code.
+    fn code(self) -> u8 {
+        match self {
+            Self::AdvInd => 0,
+            Self::DirectInd => 1,
+            Self::NonconnInd => 2,
+            Self::ScanRsp => 4,
+            Self::ScanInd => 6,
+        }
+    }
+}
+
+impl fmt::Display for Pdu {
+    /// Formats the packet type as named by the specification.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AdvInd => write!(f, "ADV_IND"),
+            Self::DirectInd => write!(f, "ADV_DIRECT_IND"),
+            Self::NonconnInd => write!(f, "ADV_NONCONN_IND"),
+            Self::ScanRsp => write!(f, "SCAN_RSP"),
+            Self::ScanInd => write!(f, "ADV_SCAN_IND"),
+        }
+    }
+}
+
+/// Represents an advertisement heard by the sniffer.
+///
+/// # Fields
+/// * `addr` - The address of the advertiser, most significant byte first.
+/// * `random` - Whether the address is random rather than public.
+/// * `pdu` - The type of the packet.
+/// * `rssi` - The received signal strength, in dBm.
+/// * `payload` - The advertising data, made of AD structures.
+#[derive(Clone, Debug)]
+pub struct Advertisement {
+    pub addr: [u8; 6],
+    pub random: bool,
+    pub pdu: Pdu,
+    pub rssi: i8,
+    pub payload: Vec<u8>,
+}
+
+/// Represents a service UUID, in one of its three sizes.
+///
+/// # Variants
+/// * `Uuid16` - A 16-bit UUID, assigned by the Bluetooth SIG.
+/// * `Uuid32` - A 32-bit UUID, assigned by the Bluetooth SIG.
+/// * `Uuid128` - A 128-bit UUID.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Service {
+    Uuid16(u16),
+    Uuid32(u32),
+    Uuid128(Uuid),
+}
+
+impl Service {
+    /// Decodes a service UUID from the advertising data.
+    ///
+    /// # Arguments
+    /// * `bytes` - The UUID, least significant byte first, of 2, 4 or 16
+    ///   bytes.
+    ///
+    /// # Returns
+    /// The UUID, `None` if its size is invalid.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            2 => Some(Self::Uuid16(u16::from_le_bytes(bytes.try_into().ok()?))),
+            4 => Some(Self::Uuid32(u32::from_le_bytes(bytes.try_into().ok()?))),
+            16 => {
+                let mut uuid: [u8; 16] = bytes.try_into().ok()?;
+                uuid.reverse();
+                Some(Self::Uuid128(Uuid::from(uuid)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Service {
+    /// Formats the UUID in hexadecimal, the 128-bit ones in their canonical
+    /// notation.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uuid16(uuid) => write!(f, "{uuid:04x}"),
+            Self::Uuid32(uuid) => write!(f, "{uuid:08x}"),
+            Self::Uuid128(uuid) => write!(f, "{uuid}"),
+        }
+    }
+}
+
+/// Represents an AD structure of the advertising data.
+///
+/// # Variants
+/// * `Flags` - The discoverability and capabilities of the advertiser.
+/// * `Name` - The name of the advertiser, possibly shortened.
+/// * `Services` - Service UUIDs, possibly an incomplete list.
+/// * `TxPower` - The transmit power, in dBm.
+/// * `ServiceData` - Data of a service.
+/// * `Manufacturer` - Data specific to a manufacturer, by company identifier.
+/// * `Other` - Any other structure, by AD type.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Field {
+    Flags(u8),
+    Name { name: String, complete: bool },
+    Services(Vec<Service>),
+    TxPower(i8),
+    ServiceData { uuid: Service, data: Vec<u8> },
+    Manufacturer { company: u16, data: Vec<u8> },
+    Other { kind: u8, data: Vec<u8> },
+}
+
+impl Field {
+    /// Decodes an AD structure.
+    ///
+    /// # Arguments
+    /// * `kind` - The AD type of the structure.
+    /// * `data` - The data of the structure.
+    ///
+    /// # Returns
+    /// The structure, as `Other` if its type is unknown or its data malformed.
+    fn decode(kind: u8, data: &[u8]) -> Self {
+        // Size of the UUIDs of the service structures.
+        let size = match kind {
+            0x02 | 0x03 | 0x16 => 2,
+            0x04 | 0x05 | 0x20 => 4,
+            0x06 | 0x07 | 0x21 => 16,
+            _ => 0,
+        };
+
+        match kind {
+            0x01 if data.len() == 1 => Self::Flags(data[0]),
+            0x02..=0x07 if data.len() % size == 0 => Self::Services(
+                data.chunks_exact(size)
+                    .filter_map(Service::decode)
+                    .collect(),
+            ),
+            0x08 | 0x09 => Self::Name {
+                name: String::from_utf8_lossy(data).into_owned(),
+                complete: kind == 0x09,
+            },
+            0x0A if data.len() == 1 => Self::TxPower(i8::from_le_bytes([data[0]])),
+            0x16 | 0x20 | 0x21 if data.len() >= size => {
+                match Service::decode(&data[..size]) {
+                    Some(uuid) => Self::ServiceData {
+                        uuid,
+                        data: data[size..].to_vec(),
+                    },
+                    None => Self::Other {
+                        kind,
+                        data: data.to_vec(),
+                    },
+                }
+            }
+            0xFF if data.len() >= 2 => Self::Manufacturer {
+                company: u16::from_le_bytes([data[0], data[1]]),
+                data: data[2..].to_vec(),
+            },
+            _ => Self::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+/// Parses the AD structures of advertising data.
+///
+/// # Arguments
+/// * `payload` - The advertising data.
+///
+/// # Returns
+/// The structures, up to the first empty or truncated one.
+#[must_use]
+pub fn parse(payload: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut rest = payload;
+
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = usize::from(len);
+        // An empty structure starts the padding.
+        if len == 0 || len > tail.len() {
+            break;
+        }
+        let (structure, tail) = tail.split_at(len);
+        fields.push(Field::decode(structure[0], &structure[1..]));
+        rest = tail;
+    }
+
+    fields
+}
+
+/// Formats bytes in hexadecimal.
+///
+/// # Arguments
+/// * `bytes` - The bytes.
+///
+/// # Returns
+/// Two lowercase digits per byte.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+/// Quotes a string for JSON.
+///
+/// # Arguments
+/// * `s` - The string.
+///
+/// # Returns
+/// The string between double quotes, with the special characters escaped.
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Formats an advertisement as a JSON line.
+///
+/// The AD structures are decoded, and the raw advertising data is kept in
+/// `data`.
+///
+/// # Arguments
+/// * `adv` - The advertisement.
+/// * `uptime` - The time the advertisement was heard at, since boot.
+///
+/// # Returns
+/// The JSON object, ending with a line feed.
+#[must_use]
+pub fn json(adv: &Advertisement, uptime: Duration) -> String {
+    let addr = adv
+        .addr
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(":");
+    let mut members = vec![
+        format!("\"uptime_ms\":{}", uptime.as_millis()),
+        format!("\"addr\":\"{}\"", addr),
+        format!("\"random\":{}", adv.random),
+        format!("\"type\":\"{}\"", adv.pdu),
+        format!("\"rssi\":{}", adv.rssi),
+    ];
+    let mut services = Vec::new();
+    let mut service_data = Vec::new();
+    let mut manufacturer = Vec::new();
+    let mut other = Vec::new();
+
+    for field in parse(&adv.payload) {
+        match field {
+            Field::Flags(flags) => members.push(format!("\"flags\":{flags}")),
+            Field::Name { name, complete } => members.push(format!(
+                "\"{}\":{}",
+                if complete { "name" } else { "short_name" },
+                quote(&name)
+            )),
+            Field::Services(uuids) => {
+                services.extend(uuids.iter().map(|uuid| format!("\"{uuid}\"")));
+            }
+            Field::TxPower(power) => members.push(format!("\"tx_power\":{power}")),
+            Field::ServiceData { uuid, data } => service_data.push(format!(
+                "{{\"uuid\":\"{}\",\"data\":\"{}\"}}",
+                uuid,
+                hex(&data)
+            )),
+            Field::Manufacturer { company, data } => manufacturer.push(format!(
+                "{{\"company\":{},\"data\":\"{}\"}}",
+                company,
+                hex(&data)
+            )),
+            Field::Other { kind, data } => other.push(format!(
+                "{{\"type\":{},\"data\":\"{}\"}}",
+                kind,
+                hex(&data)
+            )),
+        }
+    }
+
+    for (name, list) in [
+        ("services", services),
+        ("service_data", service_data),
+        ("manufacturer_data", manufacturer),
+        ("other", other),
+    ] {
+        if !list.is_empty() {
+            members.push(format!("\"{}\":[{}]", name, list.join(",")));
+        }
+    }
+    members.push(format!("\"data\":\"{}\"", hex(&adv.payload)));
+
+    format!("{{{}}}\n", members.join(","))
+}
+
+/// Computes the CRC of a link layer packet on the advertising channels.
+///
+/// # Arguments
+/// * `pdu` - The header and the payload of the packet.
+///
+/// # Returns
+/// The 24-bit CRC, in the order of its bytes on air.
+fn crc(pdu: &[u8]) -> u32 {
+    let mut state = CRC_INIT;
+    for byte in pdu {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let feedback = (state ^ u32::from(byte)) & 1;
+            byte >>= 1;
+            state >>= 1;
+            if feedback == 1 {
+                state |= 1 << 23;
+                state ^= CRC_TAPS;
+            }
+        }
+    }
+
+    state
+}
+
+/// Returns the global header of a pcap capture.
+///
+/// # Returns
+/// The header, in little endian.
+#[must_use]
+pub fn pcap_header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xA1B2_C3D4_u32.to_le_bytes());
+    header.extend_from_slice(&2_u16.to_le_bytes());
+    header.extend_from_slice(&4_u16.to_le_bytes());
+    // Time zone and accuracy of the timestamps.
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE.to_le_bytes());
+
+    header
+}
+
+/// Formats an advertisement as a pcap record, rebuilding its link layer
+/// packet.
+///
+/// The channel is unknown, and reported as the first advertising channel.
+///
+/// # Arguments
+/// * `adv` - The advertisement.
+/// * `uptime` - The time the advertisement was heard at, since boot, used as
+///   timestamp.
+///
+/// # Returns
+/// The record, in little endian.
+#[must_use]
+pub fn pcap_record(adv: &Advertisement, uptime: Duration) -> Vec<u8> {
+    let mut pdu = Vec::with_capacity(8 + adv.payload.len());
+    pdu.push(adv.pdu.code() | (u8::from(adv.random) << 6));
+    pdu.push(u8::try_from(6 + adv.payload.len()).unwrap_or(u8::MAX));
+    pdu.extend(adv.addr.iter().rev());
+    pdu.extend_from_slice(&adv.payload);
+
+    let mut packet = Vec::with_capacity(14 + pdu.len() + 3);
+    // Pseudo header: channel, signal and noise power, access address
+    // offenses, reference access address and flags.
+    packet.extend_from_slice(&[0, adv.rssi.to_le_bytes()[0], 0, 0]);
+    packet.extend_from_slice(&ACCESS_ADDRESS.to_le_bytes());
+    packet.extend_from_slice(&PHDR_FLAGS.to_le_bytes());
+    packet.extend_from_slice(&ACCESS_ADDRESS.to_le_bytes());
+    packet.extend_from_slice(&pdu);
+    packet.extend_from_slice(&crc(&pdu).to_le_bytes()[..3]);
+
+    let len = u32::try_from(packet.len()).unwrap_or(u32::MAX);
+    let mut record = Vec::with_capacity(16 + packet.len());
+    record.extend_from_slice(
+        &u32::try_from(uptime.as_secs())
+            .unwrap_or(u32::MAX)
+            .to_le_bytes(),
+    );
+    record.extend_from_slice(&uptime.subsec_micros().to_le_bytes());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&packet);
+
+    record
+}
+
+/// Represents the last report of an advertiser.
+///
+/// # Fields
+/// * `payload` - The advertising data reported.
+/// * `reported` - When the advertisement was reported.
+struct Sighting {
+    payload: Vec<u8>,
+    reported: Instant,
+}
+
+/// Represents a capture of the advertisements, streamed in a given format.
+///
+/// Advertisements are deduplicated by address and packet type: an advertiser
+/// is reported again only once its data changes, or after `refresh` to show
+/// that it is still around.
+pub struct Capture {
+    format: Format,
+    refresh: Duration,
+    limit: usize,
+    seen: Lru<([u8; 6], Pdu), Sighting>,
+    pending: Vec<u8>,
+}
+
+impl Capture {
+    /// Creates a new `Capture` instance, starting with the pcap header if
+    /// needed.
+    ///
+    /// # Arguments
+    /// * `format` - The stream format.
+    /// * `refresh` - Duration after which an unchanged advertisement is
+    ///   reported again.
+    /// * `capacity` - Maximum number of advertisers remembered.
+    /// * `limit` - Maximum number of bytes waiting to be streamed.
+    #[must_use]
+    pub fn new(
+        format: Format,
+        refresh: Duration,
+        capacity: usize,
+        limit: usize,
+    ) -> Self {
+        Self {
+            format,
+            refresh,
+            limit,
+            seen: Lru::new(capacity),
+            pending: match format {
+                Format::Json => Vec::new(),
+                Format::Pcap => pcap_header(),
+            },
+        }
+    }
+
+    /// Reports an advertisement, unless it was recently reported.
+    ///
+    /// # Arguments
+    /// * `adv` - The advertisement.
+    /// * `now` - The current time.
+    /// * `uptime` - The time since boot.
+    ///
+    /// # Returns
+    /// `true` if the advertisement was reported, `false` if it is a duplicate
+    /// or if the stream is lagging behind.
+    pub fn record(
+        &mut self,
+        adv: &Advertisement,
+        now: Instant,
+        uptime: Duration,
+    ) -> bool {
+        let key = (adv.addr, adv.pdu);
+        if self.seen.get(&key).is_some_and(|seen| {
+            seen.payload == adv.payload
+                && now.saturating_duration_since(seen.reported) < self.refresh
+        }) {
+            return false;
+        }
+
+        let out = match self.format {
+            Format::Json => json(adv, uptime).into_bytes(),
+            Format::Pcap => pcap_record(adv, uptime),
+        };
+        // Left unreported, the advertisement is reported once the stream
+        // catches up.
+        if self.pending.len() + out.len() > self.limit {
+            return false;
+        }
+        self.pending.extend_from_slice(&out);
+
+        self.seen.insert(
+            key,
+            Sighting {
+                payload: adv.payload.clone(),
+                reported: now,
+            },
+        );
+
+        true
+    }
+
+    /// Takes the bytes waiting to be streamed.
+    ///
+    /// # Returns
+    /// The bytes, in the stream format.
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertisement(payload: &[u8]) -> Advertisement {
+        Advertisement {
+            addr: [0x24, 0x0A, 0xC4, 0x01, 0x02, 0x03],
+            random: true,
+            pdu: Pdu::AdvInd,
+            rssi: -60,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn parses_structures() {
+        let payload = [
+            2, 0x01, 0x06, 3, 0x03, 0x0F, 0x18, 2, 0x0A, 0xFC, 5, 0xFF, 0x4C, 0x00,
+            0x02, 0x15,
+        ];
+
+        assert_eq!(
+            parse(&payload),
+            vec![
+                Field::Flags(0x06),
+                Field::Services(vec![Service::Uuid16(0x180F)]),
+                Field::TxPower(-4),
+                Field::Manufacturer {
+                    company: 0x004C,
+                    data: vec![0x02, 0x15],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_padding() {
+        assert_eq!(parse(&[2, 0x01, 0x06, 0, 0, 0]), vec![Field::Flags(0x06)]);
+        assert!(parse(&[0, 2, 0x01, 0x06]).is_empty());
+    }
+
+    #[test]
+    fn stops_at_truncated_structure() {
+        assert_eq!(
+            parse(&[2, 0x01, 0x06, 5, 0x09, b'a']),
+            vec![Field::Flags(0x06)]
+        );
+        assert!(parse(&[1]).is_empty());
+    }
+
+    #[test]
+    fn keeps_malformed_data() {
+        assert_eq!(
+            parse(&[3, 0x01, 0x06, 0x00]),
+            vec![Field::Other {
+                kind: 0x01,
+                data: vec![0x06, 0x00],
+            }]
+        );
+        assert_eq!(
+            parse(&[4, 0x03, 0x0F, 0x18, 0x0A]),
+            vec![Field::Other {
+                kind: 0x03,
+                data: vec![0x0F, 0x18, 0x0A],
+            }]
+        );
+        assert_eq!(
+            parse(&[2, 0xFF, 0x4C]),
+            vec![Field::Other {
+                kind: 0xFF,
+                data: vec![0x4C],
+            }]
+        );
+        assert_eq!(
+            parse(&[2, 0x16, 0xAA]),
+            vec![Field::Other {
+                kind: 0x16,
+                data: vec![0xAA],
+            }]
+        );
+    }
+
+    #[test]
+    fn writes_pcap_header() {
+        assert_eq!(
+            pcap_header(),
+            [
+                0xD4, 0xC3, 0xB2, 0xA1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0,
+                0, 0, 0x00, 0x01, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_pcap_record() {
+        let record = pcap_record(
+            &advertisement(&[2, 0x01, 0x06]),
+            Duration::from_millis(1500),
+        );
+
+        #[rustfmt::skip]
+        let expected = [
+            // Timestamp, captured and original lengths.
+            1, 0, 0, 0, 0x20, 0xA1, 0x07, 0, 28, 0, 0, 0, 28, 0, 0, 0,
+            // Pseudo header.
+            0, 0xC4, 0, 0, 0xD6, 0xBE, 0x89, 0x8E, 0x13, 0x0C,
+            // Access address.
+            0xD6, 0xBE, 0x89, 0x8E,
+            // Header, random address and payload.
+            0x40, 9, 0x03, 0x02, 0x01, 0xC4, 0x0A, 0x24, 2, 0x01, 0x06,
+            // CRC.
+            0x94, 0xBA, 0x42,
+        ];
+        assert_eq!(record, expected);
+    }
+}
@@ -0,0 +1,439 @@
+use std::time::{Duration, Instant};
+
+use crate::lru::Lru;
+
+/// Returns the remainder of a duration divided by another.
+///
+/// # Arguments
+/// * `duration` - The dividend.
+/// * `period` - The divisor, not zero.
+///
+/// # Returns
+/// The remainder, below `period`.
+fn rem(duration: Duration, period: Duration) -> Duration {
+    let nanos = duration.as_nanos() % period.as_nanos().max(1);
+    // The remainder is below the period, which fits.
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+/// Represents the blinking rhythm of a device.
+///
+/// The LED turns on at every flash, one per period, and off half a period
+/// later. The flashes of the leader seen during a period pull the next flash
+/// halfway towards them.
+pub struct Oscillator {
+    period: Duration,
+    epoch: Instant,
+    pull: i128,
+    pulls: i128,
+}
+
+impl Oscillator {
+    /// Creates a new `Oscillator` instance.
+    ///
+    /// # Arguments
+    /// * `period` - The blinking period.
+    /// * `now` - The time of the first flash.
+    #[must_use]
+    pub fn new(period: Duration, now: Instant) -> Self {
+        Self {
+            period,
+            epoch: now,
+            pull: 0,
+            pulls: 0,
+        }
+    }
+
+    /// Returns the time since the last flash.
+    ///
+    /// # Arguments
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// The phase, below the period.
+    #[must_use]
+    pub fn phase(&self, now: Instant) -> Duration {
+        match now.checked_duration_since(self.epoch) {
+            Some(since) => rem(since, self.period),
+            // The epoch moved ahead of the current time.
+            None => rem(
+                self.period
+                    .saturating_sub(rem(self.epoch - now, self.period)),
+                self.period,
+            ),
+        }
+    }
+
+    /// Returns the edge of the blinking nearest to a time.
+    ///
+    /// # Arguments
+    /// * `now` - The time.
+    ///
+    /// # Returns
+    /// Whether the edge is a flash, and the duration until the next edge.
+    fn nearest(&self, now: Instant) -> (bool, Duration) {
+        let half = self.period / 2;
+        let phase = self.phase(now);
+        // Rounded to the nearest edge, 2 being the next flash.
+        let edge = (phase + half / 2).as_nanos() / half.as_nanos().max(1);
+        let next = half * u32::try_from(edge + 1).unwrap_or(u32::MAX);
+
+        (edge % 2 == 0, next.saturating_sub(phase))
+    }
+
+    /// Returns the edge of the blinking nearest to the current time, i.e. the
+    /// LED state to apply right now and when to apply the next one.
+    ///
+    /// The pull of the leader is applied at every flash, which moves the next
+    /// edges.
+    ///
+    /// # Arguments
+    /// * `now` - The current time, close to an edge.
+    ///
+    /// # Returns
+    /// Whether the LED is on after the edge, i.e. whether the edge is a flash,
+    /// and the duration until the next edge.
+    pub fn edge(&mut self, now: Instant) -> (bool, Duration) {
+        let (flash, _) = self.nearest(now);
+        if flash && self.pulls > 0 {
+            let period = i128::try_from(self.period.as_nanos()).unwrap_or(i128::MAX);
+            let pull = self.pull / self.pulls / 2;
+            // The epoch only moves forward: moving backwards by a pull is
+            // moving forward by the period minus the pull.
+            let shift = u64::try_from(pull.rem_euclid(period)).unwrap_or(0);
+            self.epoch += Duration::from_nanos(shift);
+            self.pull = 0;
+            self.pulls = 0;
+        }
+
+        (flash, self.nearest(now).1)
+    }
+
+    /// Records that the leader flashed, to pull the next flash towards it.
+    ///
+    /// # Arguments
+    /// * `flash` - The time the leader was seen flashing.
+    pub fn observe(&mut self, flash: Instant) {
+        let phase = i128::try_from(self.phase(flash).as_nanos()).unwrap_or(0);
+        let period = i128::try_from(self.period.as_nanos()).unwrap_or(i128::MAX);
+
+        // Positive if the device flashed first, negative if it lags behind.
+        self.pull += if 2 * phase <= period {
+            phase
+        } else {
+            phase - period
+        };
+        self.pulls += 1;
+    }
+}
+
+/// Represents the beat of a peer.
+///
+/// # Fields
+/// * `beat` - The last beat of the peer.
+/// * `since` - When the beat was first seen.
+/// * `flashed` - When the peer was last seen flashing, if ever.
+#[derive(Clone, Copy)]
+struct Peer {
+    beat: u8,
+    since: Instant,
+    flashed: Option<Instant>,
+}
+
+/// Represents the beats of the peers, i.e. the counters of their flashes, to
+/// tell when the leader flashes.
+///
+/// A peer flashes when its beat advances by one, provided that its previous
+/// beat was seen about a period before. Otherwise, the beat advanced while
+/// the device was not scanning, at an unknown time.
+///
+/// The leader is the peer with the lowest identifier among the peers that
+/// recently flashed, unless the device has a lower one. Following a single
+/// leader, rather than every peer, keeps the delay of the advertisements from
+/// making the devices chase each other: the followers flash a constant delay
+/// after their leader.
+///
+/// # Type Parameters
+/// * `T` - Type of the identifier of a peer.
+pub struct Beats<T> {
+    own: T,
+    period: Duration,
+    peers: Lru<T, Peer>,
+}
+
+impl<T: Copy + Ord> Beats<T> {
+    /// Number of periods a leader is followed for after its last flash, long
+    /// enough to span the pauses between the scans.
+    const LEAD: u32 = 16;
+
+    /// Creates a new `Beats` instance.
+    ///
+    /// # Arguments
+    /// * `own` - The identifier of the device.
+    /// * `period` - The blinking period.
+    /// * `capacity` - Maximum number of peers remembered.
+    #[must_use]
+    pub fn new(own: T, period: Duration, capacity: usize) -> Self {
+        Self {
+            own,
+            period,
+            peers: Lru::new(capacity),
+        }
+    }
+
+    /// Returns the leader of the device.
+    ///
+    /// # Arguments
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// The identifier of the leader, `None` if the device leads.
+    #[must_use]
+    pub fn leader(&self, now: Instant) -> Option<T> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| {
+                peer.flashed.is_some_and(|flashed| {
+                    now.saturating_duration_since(flashed) < self.period * Self::LEAD
+                })
+            })
+            .map(|(id, _)| *id)
+            .filter(|id| *id < self.own)
+            .min()
+    }
+
+    /// Records the beat of a peer.
+    ///
+    /// # Arguments
+    /// * `id` - The identifier of the peer.
+    /// * `beat` - The beat advertised by the peer.
+    /// * `now` - The time the beat was seen at.
+    ///
+    /// # Returns
+    /// `true` if the leader just flashed, `false` otherwise.
+    pub fn check(&mut self, id: T, beat: u8, now: Instant) -> bool {
+        let Some(mut peer) = self.peers.get(&id).copied() else {
+            self.peers.insert(
+                id,
+                Peer {
+                    beat,
+                    since: now,
+                    flashed: None,
+                },
+            );
+            return false;
+        };
+
+        if beat == peer.beat {
+            return false;
+        }
+        let flashed = beat == peer.beat.wrapping_add(1)
+            && now.saturating_duration_since(peer.since) <= self.period * 3 / 2;
+        peer.beat = beat;
+        peer.since = now;
+        if flashed {
+            peer.flashed = Some(now);
+        }
+        self.peers.insert(id, peer);
+
+        flashed && self.leader(now) == Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blinking period of the devices, as at 3 Hz.
+    const PERIOD: Duration = Duration::from_millis(666);
+    /// Delay between a flash and its advertisement being heard.
+    const LATENCY: Duration = Duration::from_millis(20);
+    /// Interval between two advertisements of the same beat.
+    const ADVERTISING: u64 = 100;
+
+    /// Represents a xorshift generator, so that the simulations repeat.
+    struct Rng(u64);
+
+    impl Rng {
+        /// Returns a number below `n`.
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    /// Represents a simulated device.
+    struct Device {
+        oscillator: Oscillator,
+        beats: Beats<usize>,
+        beat: u8,
+        tick: Duration,
+        scan_offset: u64,
+    }
+
+    /// Returns the largest phase difference between the devices and the first
+    /// one.
+    fn spread(devices: &[Device], now: Instant) -> Duration {
+        let first = devices[0].oscillator.phase(now);
+
+        devices
+            .iter()
+            .map(|device| {
+                let diff = rem(
+                    (device.oscillator.phase(now) + PERIOD).saturating_sub(first),
+                    PERIOD,
+                );
+                diff.min(PERIOD.saturating_sub(diff))
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Simulates devices starting with random phases, one millisecond at a
+    /// time. The devices scan one second out of two, advertise their beat
+    /// every `ADVERTISING` milliseconds and as they flash, and their timer
+    /// ticks up to 2 ms late.
+    ///
+    /// # Returns
+    /// The spread of the phases at the end.
+    fn simulate(
+        count: usize,
+        seed: u64,
+        duration: Duration,
+        in_range: impl Fn(usize, usize) -> bool,
+    ) -> Duration {
+        let mut rng = Rng(seed);
+        let start = Instant::now();
+        let mut devices: Vec<_> = (0..count)
+            .map(|id| Device {
+                oscillator: Oscillator::new(
+                    PERIOD,
+                    start + Duration::from_millis(rng.below(667)),
+                ),
+                beats: Beats::new(id, PERIOD, 16),
+                beat: 0,
+                tick: Duration::from_millis(rng.below(333)),
+                scan_offset: rng.below(2000),
+            })
+            .collect();
+        let mut air: Vec<(Duration, usize, u8)> = Vec::new();
+
+        for ms in 0..u64::try_from(duration.as_millis()).unwrap() {
+            let elapsed = Duration::from_millis(ms);
+            let now = start + elapsed;
+
+            for (id, device) in devices.iter_mut().enumerate() {
+                if device.tick <= elapsed {
+                    let (flash, next) = device.oscillator.edge(now);
+                    device.tick =
+                        elapsed + next + Duration::from_millis(rng.below(3));
+                    if flash {
+                        device.beat = device.beat.wrapping_add(1);
+                        air.push((elapsed + LATENCY, id, device.beat));
+                    }
+                }
+                if (ms + id as u64 * 17) % ADVERTISING == 0 {
+                    air.push((elapsed + LATENCY, id, device.beat));
+                }
+            }
+
+            let (heard, flying) =
+                air.into_iter().partition(|(at, ..)| *at <= elapsed);
+            air = flying;
+            for (_, from, beat) in heard {
+                for (to, device) in devices.iter_mut().enumerate() {
+                    let scanning = (ms + device.scan_offset) / 1000 % 2 == 1;
+                    if to != from
+                        && in_range(from, to)
+                        && scanning
+                        && device.beats.check(from, beat, now)
+                    {
+                        device.oscillator.observe(now);
+                    }
+                }
+            }
+        }
+
+        spread(&devices, start + duration)
+    }
+
+    #[test]
+    fn converges_in_range() {
+        for seed in 1..6 {
+            let spread =
+                simulate(12, seed * 7919, Duration::from_secs(120), |_, _| true);
+            assert!(
+                spread < Duration::from_millis(80),
+                "seed {seed}: {spread:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn converges_along_a_line() {
+        for seed in 1..6 {
+            let spread =
+                simulate(5, seed * 104_729, Duration::from_secs(120), |a, b| {
+                    a.abs_diff(b) == 1
+                });
+            assert!(
+                spread < Duration::from_millis(120),
+                "seed {seed}: {spread:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn alternates_edges() {
+        let start = Instant::now();
+        let mut oscillator = Oscillator::new(
+            Duration::from_millis(600),
+            start + Duration::from_millis(100),
+        );
+
+        assert_eq!(oscillator.phase(start), Duration::from_millis(500));
+        assert_eq!(
+            oscillator.edge(start + Duration::from_millis(99)),
+            (true, Duration::from_millis(301))
+        );
+        assert_eq!(
+            oscillator.edge(start + Duration::from_millis(402)),
+            (false, Duration::from_millis(298))
+        );
+    }
+
+    #[test]
+    fn pulls_towards_leader() {
+        let start = Instant::now();
+        let mut oscillator = Oscillator::new(PERIOD, start);
+
+        // The leader flashes 100 ms after the device, which flashes 50 ms
+        // later from its next flash on.
+        oscillator.observe(start + PERIOD + Duration::from_millis(100));
+        oscillator.edge(start + PERIOD * 2);
+        assert_eq!(
+            oscillator.phase(start + PERIOD * 3),
+            Duration::from_millis(666 - 50)
+        );
+    }
+
+    #[test]
+    fn follows_lowest_flashing_peer() {
+        let start = Instant::now();
+        let mut beats = Beats::new(9, PERIOD, 2);
+
+        assert!(!beats.check(1, 5, start));
+        assert!(!beats.check(1, 5, start + PERIOD));
+        assert!(beats.check(1, 6, start + PERIOD));
+        assert_eq!(beats.leader(start + PERIOD), Some(1));
+        // Skipped beats and stale beats do not count as flashes.
+        assert!(!beats.check(1, 8, start + PERIOD * 2));
+        assert!(!beats.check(1, 9, start + PERIOD * 4));
+        // Peers with a higher identifier do not lead.
+        assert!(!beats.check(10, 1, start));
+        assert!(!beats.check(10, 2, start + PERIOD));
+        assert!(Beats::new(0, PERIOD, 2).leader(start).is_none());
+    }
+}
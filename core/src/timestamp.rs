@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Represents a point in time across restarts.
+///
+/// # Fields
+/// * `boot` - Index of the boot, incremented at each restart.
+/// * `uptime` - Time elapsed since the boot.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp {
+    pub boot: u16,
+    pub uptime: Duration,
+}
+
+impl Timestamp {
+    /// The earliest timestamp, the start of the first boot.
+    pub const MIN: Self = Self {
+        boot: 0,
+        uptime: Duration::ZERO,
+    };
+    /// The latest timestamp.
+    pub const MAX: Self = Self {
+        boot: u16::MAX,
+        uptime: Duration::MAX,
+    };
+}
+
+impl FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    /// Parses a timestamp as `<boot>:<uptime in milliseconds>`, e.g. `3:1500`,
+    /// or as `<boot>` for the start of a boot.
+    ///
+    /// # Errors
+    /// Returns an error if the boot or the uptime is not a number.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid timestamp: {}, expected boot:ms", s);
+        let (boot, uptime) = s.split_once(':').unwrap_or((s, "0"));
+
+        Ok(Self {
+            boot: boot.parse().map_err(|_| invalid())?,
+            uptime: Duration::from_millis(uptime.parse().map_err(|_| invalid())?),
+        })
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats the timestamp as `<boot>:<uptime in milliseconds>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.boot, self.uptime.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(boot: u16, millis: u64) -> Timestamp {
+        Timestamp {
+            boot,
+            uptime: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!("3:1500".parse::<Timestamp>().unwrap(), at(3, 1500));
+        assert_eq!("2".parse::<Timestamp>().unwrap(), at(2, 0));
+        assert_eq!(at(3, 1500).to_string(), "3:1500");
+        assert_eq!(
+            "3:soon".parse::<Timestamp>().unwrap_err().to_string(),
+            "Invalid timestamp: 3:soon, expected boot:ms"
+        );
+        assert!("-1:0".parse::<Timestamp>().is_err());
+        assert!("65536".parse::<Timestamp>().is_err());
+        assert!(":5".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn orders_boots_first() {
+        assert!(at(1, 600_000) < at(2, 0));
+        assert!(at(2, 0) < at(2, 1));
+        assert!(Timestamp::MIN <= at(0, 0));
+        assert!(at(u16::MAX, u64::MAX) < Timestamp::MAX);
+    }
+
+    #[test]
+    fn selects_ranges() {
+        let timestamps = [at(0, 5), at(1, 0), at(1, 900), at(2, 10), at(3, 0)];
+        let select = |start: &str, end: &str| {
+            let range = start.parse::<Timestamp>().unwrap()
+                ..end.parse::<Timestamp>().unwrap();
+            timestamps
+                .iter()
+                .copied()
+                .filter(|timestamp| range.contains(timestamp))
+                .collect::<Vec<_>>()
+        };
+
+        // The start is inclusive, the end exclusive.
+        assert_eq!(select("1", "2"), [at(1, 0), at(1, 900)]);
+        assert_eq!(select("1:900", "3"), [at(1, 900), at(2, 10)]);
+        assert_eq!(select("2:11", "3"), []);
+        assert_eq!(select("3", "1"), []);
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Result};
+use esp_idf_hal::sys::{
+    adc_atten_t_ADC_ATTEN_DB_12, adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+    adc_cali_create_scheme_line_fitting, adc_cali_delete_scheme_line_fitting,
+    adc_cali_handle_t, adc_cali_line_fitting_config_t, adc_cali_raw_to_voltage,
+    adc_channel_t, adc_oneshot_chan_cfg_t, adc_oneshot_config_channel,
+    adc_oneshot_del_unit, adc_oneshot_io_to_channel, adc_oneshot_new_unit,
+    adc_oneshot_read, adc_oneshot_unit_handle_t, adc_oneshot_unit_init_cfg_t,
+    adc_unit_t_ADC_UNIT_1, esp,
+};
+use log::{info, warn};
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicU32, AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    charge::{state_of_charge, Filter, Level},
+    infra::Poller,
+    message::{Notifier, Trigger},
+    metrics,
+    time::sleep,
+    watchdog,
+};
+
+/// Value of `Charge::percent` before the first sample.
+const UNKNOWN: u8 = u8::MAX;
+
+/// Represents a calibrated ADC1 channel.
+struct Adc {
+    unit: adc_oneshot_unit_handle_t,
+    channel: adc_channel_t,
+    cali: adc_cali_handle_t,
+}
+
+// The handles are only used by the thread owning the monitor.
+unsafe impl Send for Adc {}
+
+impl Adc {
+    /// Creates a new `Adc` instance.
+    ///
+    /// # Arguments
+    /// * `pin` - The GPIO number of the channel.
+    ///
+    /// # Errors
+    /// Returns an error if the pin is not an ADC1 pin or if the unit, the
+    /// channel or the calibration cannot be configured.
+    fn new(pin: i32) -> Result<Self> {
+        let mut unit_id = 0;
+        let mut channel = 0;
+        esp!(unsafe { adc_oneshot_io_to_channel(pin, &mut unit_id, &mut channel) })?;
+        // ADC2 is shared with the radio.
+        if unit_id != adc_unit_t_ADC_UNIT_1 {
+            return Err(anyhow!("GPIO{} is not an ADC1 pin", pin));
+        }
+
+        let mut unit = ptr::null_mut();
+        let unit_cfg = adc_oneshot_unit_init_cfg_t {
+            unit_id,
+            ..Default::default()
+        };
+        esp!(unsafe { adc_oneshot_new_unit(&unit_cfg, &mut unit) })?;
+
+        let chan_cfg = adc_oneshot_chan_cfg_t {
+            atten: adc_atten_t_ADC_ATTEN_DB_12,
+            bitwidth: adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        esp!(unsafe { adc_oneshot_config_channel(unit, channel, &chan_cfg) })?;
+
+        // Uses the reference voltage burnt in eFuse, if any.
+        let mut cali = ptr::null_mut();
+        let cali_cfg = adc_cali_line_fitting_config_t {
+            unit_id,
+            atten: chan_cfg.atten,
+            bitwidth: chan_cfg.bitwidth,
+            default_vref: 1100,
+        };
+        esp!(unsafe { adc_cali_create_scheme_line_fitting(&cali_cfg, &mut cali) })?;
+
+        Ok(Self {
+            unit,
+            channel,
+            cali,
+        })
+    }
+
+    /// Reads the voltage of the channel.
+    ///
+    /// # Returns
+    /// The calibrated voltage, in millivolts.
+    ///
+    /// # Errors
+    /// Returns an error if the channel cannot be read.
+    fn read(&self) -> Result<u32> {
+        let mut raw = 0;
+        esp!(unsafe { adc_oneshot_read(self.unit, self.channel, &mut raw) })?;
+        let mut millivolts = 0;
+        esp!(unsafe { adc_cali_raw_to_voltage(self.cali, raw, &mut millivolts) })?;
+
+        Ok(u32::try_from(millivolts)?)
+    }
+}
+
+impl Drop for Adc {
+    /// Releases the calibration and the unit.
+    fn drop(&mut self) {
+        unsafe {
+            adc_cali_delete_scheme_line_fitting(self.cali);
+            adc_oneshot_del_unit(self.unit);
+        }
+    }
+}
+
+/// Represents the charge of the pack, shared with the state machine.
+pub struct Charge {
+    millivolts: AtomicU32,
+    percent: AtomicU8,
+    level: AtomicU8,
+}
+
+impl Charge {
+    /// Creates a new `Charge` instance.
+    ///
+    /// # Errors
+    /// Returns an error if the charge cannot be initialized.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            millivolts: AtomicU32::new(0),
+            percent: AtomicU8::new(UNKNOWN),
+            level: AtomicU8::new(Level::Normal.into()),
+        })
+    }
+
+    /// Returns the filtered voltage of the pack.
+    ///
+    /// # Returns
+    /// The voltage in millivolts, 0 before the first sample.
+    #[must_use]
+    pub fn millivolts(&self) -> u32 {
+        self.millivolts.load(Ordering::Relaxed)
+    }
+
+    /// Returns the state of charge of the pack.
+    ///
+    /// # Returns
+    /// The state of charge in percent, `None` before the first sample.
+    #[must_use]
+    pub fn percent(&self) -> Option<u8> {
+        Some(self.percent.load(Ordering::Relaxed)).filter(|&p| p != UNKNOWN)
+    }
+
+    /// Returns the charge level of the pack.
+    ///
+    /// # Returns
+    /// The level, `Normal` before the first sample.
+    #[must_use]
+    pub fn level(&self) -> Level {
+        Level::try_from(self.level.load(Ordering::Relaxed)).unwrap_or(Level::Normal)
+    }
+
+    /// Publishes a new estimate of the charge.
+    ///
+    /// # Arguments
+    /// * `millivolts` - The filtered voltage.
+    /// * `percent` - The state of charge.
+    /// * `level` - The charge level.
+    fn set(&self, millivolts: u32, percent: u8, level: Level) {
+        self.millivolts.store(millivolts, Ordering::Relaxed);
+        self.percent.store(percent, Ordering::Relaxed);
+        self.level.store(level.into(), Ordering::Relaxed);
+    }
+}
+
+/// Represents a monitor of the battery voltage.
+///
+/// The pack is expected to be connected to an ADC1 pin through a voltage
+/// divider, as its voltage exceeds the range of the ADC.
+pub struct Monitor {
+    notifier: Notifier,
+    charge: Arc<Charge>,
+    adc: Adc,
+    scale: u32,
+    filter: Filter,
+}
+
+impl Monitor {
+    /// Period of the sampling, in milliseconds.
+    const PERIOD: u32 = 5000;
+    /// Number of readings averaged in a sample.
+    const READINGS: u32 = 16;
+
+    /// Creates a new `Monitor` instance.
+    ///
+    /// # Arguments
+    /// * `notifier` - A notifier to publish the low and critical triggers.
+    /// * `charge` - The charge the estimates are published to.
+    /// * `pin` - The GPIO number the divider is connected to.
+    /// * `scale` - Ratio of the divider in per mille, i.e. the pack voltage
+    ///   over the pin voltage times 1000.
+    ///
+    /// # Errors
+    /// Returns an error if the ADC cannot be configured.
+    pub fn new(
+        notifier: Notifier,
+        charge: Arc<Charge>,
+        pin: i32,
+        scale: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            notifier,
+            charge,
+            adc: Adc::new(pin)?,
+            scale,
+            filter: Filter::default(),
+        })
+    }
+
+    /// Samples the voltage of the pack.
+    ///
+    /// # Returns
+    /// The average of `READINGS` readings, in millivolts.
+    ///
+    /// # Errors
+    /// Returns an error if the ADC cannot be read.
+    fn sample(&self) -> Result<u32> {
+        let mut sum = 0;
+        for _ in 0..Self::READINGS {
+            sum += self.adc.read()?;
+        }
+
+        Ok(sum / Self::READINGS * self.scale / 1000)
+    }
+}
+
+impl Poller for Monitor {
+    /// Samples the battery and publishes the charge level changes.
+    ///
+    /// # Errors
+    /// Returns an error if the ADC, the notifier or the watchdog fails.
+    fn poll(&mut self) -> Result<!> {
+        loop {
+            watchdog::feed()?;
+
+            let millivolts = self.filter.update(self.sample()?);
+            let percent = state_of_charge(millivolts);
+            let previous = self.charge.level();
+            let level = previous.classify(percent);
+            self.charge.set(millivolts, percent, level);
+            metrics::BATTERY_VOLTAGE.set(millivolts);
+            metrics::BATTERY_CHARGE.set(percent.into());
+
+            if level < previous {
+                info!(millivolts, percent, level:? = level; "battery recovered");
+            } else if level > previous {
+                warn!(millivolts, percent, level:? = level; "battery drained");
+                self.notifier.notify(match level {
+                    Level::Critical => Trigger::BatteryCritical,
+                    _ => Trigger::BatteryLow,
+                })?;
+            }
+
+            sleep(Self::PERIOD);
+        }
+    }
+}
@@ -1,77 +1,391 @@
 use anyhow::Result;
 use esp_idf_hal::{
-    gpio::PinDriver,
+    cpu::Core,
+    gpio::{AnyIOPin, PinDriver},
     prelude::Peripherals,
     rmt::{config::TransmitConfig, TxRmtDriver},
     timer::{TimerConfig, TimerDriver},
+    uart::{config::Config as UartConfig, UartDriver},
+    units::Hertz,
 };
-use esp_idf_svc::log::EspLogger;
-use std::sync::{Arc, Mutex};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use log::warn;
+use std::sync::Arc;
 
 use esp_layground::{
-    ble::{Advertiser, Scanner},
-    button::{Button, State},
+    battery::{Charge, Monitor},
+    beacon::Frame,
+    ble::{Advertiser, Leadership, Mesh, Peers, Rhythm, Scanner, Sequence, Sniffer},
+    button::Button,
     clock::Timer,
+    config::Store,
+    console::Console,
+    control::Controller,
+    crash,
+    group::Membership,
+    hibernate::{self, Hibernate},
     infra::Poller,
+    journal::{Journal, Mirror},
     light::{Led, BLINK_FREQ},
-    logic::StateMachine,
-    message::Dispatcher,
-    thread::{spawn, ExitGuard},
+    logger,
+    logic::{StateMachine, Status},
+    mesh::Role,
+    message::{Bus, Trigger},
+    power,
+    safe::SafeMode,
+    thread::{Builder, ExitGuard},
+    watchdog,
 };
 
-const NAME: &str = "ESPlayground";
+/// Number of entries kept in the journal, across restarts.
+const JOURNAL_CAPACITY: usize = 64;
 
-fn main() -> Result<()> {
-    // main() should never return. Restart the device if it does.
-    let _guard = ExitGuard;
+/// Sets up the button, the LED and the serial console and runs the safe mode.
+///
+/// The console is the recovery channel: it shows the journal of the crashed
+/// runs and edits the configuration, BLE being left off.
+///
+/// # Arguments
+/// * `peripherals` - The device peripherals.
+/// * `partition` - The default NVS partition.
+/// * `store` - The configuration store.
+///
+/// # Errors
+/// Returns an error if a component cannot be set up or if the safe mode fails.
+fn safe_mode(
+    peripherals: Peripherals,
+    partition: EspDefaultNvsPartition,
+    store: Store,
+) -> Result<()> {
+    let tx_rmt_cfg = TransmitConfig::new().clock_divider(1);
+    let uart_cfg = UartConfig::new().baudrate(Hertz(115_200));
+    let pin_driver = PinDriver::input(peripherals.pins.gpio39)?;
+    let tx_rmt_driver = TxRmtDriver::new(
+        peripherals.rmt.channel0,
+        peripherals.pins.gpio27,
+        &tx_rmt_cfg,
+    )?;
+    let uart_driver = UartDriver::new(
+        peripherals.uart0,
+        peripherals.pins.gpio1,
+        peripherals.pins.gpio3,
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &uart_cfg,
+    )?;
 
-    // It is necessary to call this function once. Otherwise some patches to the runtime
-    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_hal::sys::link_patches();
+    // Nothing subscribes to the bus, as only the console runs. The journal of
+    // the crashed runs is best effort, it must not keep the console down.
+    let journal = Mirror::new(partition.clone())
+        .and_then(|mirror| Journal::new(JOURNAL_CAPACITY, Some(mirror)))
+        .or_else(|err| {
+            warn!(err:% = err; "Journal unavailable, starting empty");
+            Journal::new(JOURNAL_CAPACITY, None)
+        })?;
+    let controller = Controller::new(
+        Bus::new()?.notifier()?,
+        Arc::new(Status::new()?),
+        Arc::new(Peers::new()?),
+        Arc::new(Sniffer::new()?),
+        Arc::new(journal),
+        Store::new(partition)?,
+    )?;
+    let mut console = Console::new(uart_driver, controller)?;
+    Builder::new(c"console")
+        .stack_size(6144)
+        .priority(3)
+        .spawn(move || console.poll())?;
 
-    EspLogger::initialize_default();
+    watchdog::watch(c"safe-mode")?;
+    let mut safe = SafeMode::new(Led::new(tx_rmt_driver)?, pin_driver, store)?;
+    safe.poll()?
+}
 
-    let dispatcher = Dispatcher::new()?;
-    let ble_notifier = dispatcher.notifier()?;
-    let button_notifier = dispatcher.notifier()?;
-    let led_timer_notifier = dispatcher.notifier()?;
+/// Sets up the components and runs the state machine.
+///
+/// # Arguments
+/// * `peripherals` - The device peripherals.
+/// * `partition` - The default NVS partition.
+/// * `store` - The configuration store.
+/// * `journal` - The journal of the state machine.
+///
+/// # Errors
+/// Returns an error if a component cannot be set up or if the state machine
+/// fails.
+fn run(
+    peripherals: Peripherals,
+    partition: EspDefaultNvsPartition,
+    store: Store,
+    journal: Arc<Journal>,
+) -> Result<()> {
+    // The components outlive this function as it never returns, but the
+    // scanner thread requires a 'static name.
+    let config = store.load()?;
+    let name: &'static str = config.name.leak();
+
+    let bus = Bus::new()?;
+    let dispatcher = bus.subscribe(
+        Trigger::ButtonPressed
+            | Trigger::ButtonHeld
+            | Trigger::TimerTicked
+            | Trigger::DeviceFoundActive
+            | Trigger::DeviceFoundInactive
+            | Trigger::DeviceNotFound
+            | Trigger::SleepTimerExpired
+            | Trigger::BatteryLow
+            | Trigger::BatteryCritical
+            | Trigger::MeshOn
+            | Trigger::MeshOff
+            | Trigger::BeaconFound,
+    )?;
+    let ble_notifier = bus.notifier()?;
+    let button_notifier = bus.notifier()?;
+    let console_notifier = bus.notifier()?;
+    let led_timer_notifier = bus.notifier()?;
+    let sm_notifier = bus.notifier()?;
+    let wake_notifier = bus.notifier()?;
+
+    let status = Arc::new(Status::new()?);
+    let peers = Arc::new(Peers::new()?);
+    let sniffer = Arc::new(Sniffer::new()?);
+    let membership = Arc::new(Membership::new(config.group)?);
+    let mesh = match config.mesh.role {
+        Role::Off => None,
+        role => Some(Arc::new(Mesh::new(partition.clone(), role)?)),
+    };
+    let rhythm = if config.sync {
+        Some(Arc::new(Rhythm::new()?))
+    } else {
+        None
+    };
+    let leadership = if config.election {
+        Some(Arc::new(Leadership::new()?))
+    } else {
+        None
+    };
 
-    let peripherals = Peripherals::take()?;
     let ble_timer_peripheral = peripherals.timer01;
     let button_peripheral = peripherals.pins.gpio39;
     let channel_peripheral = peripherals.rmt.channel0;
     let led_peripheral = peripherals.pins.gpio27;
     let led_timer_peripheral = peripherals.timer00;
+    let uart_peripheral = peripherals.uart0;
+    let uart_rx_peripheral = peripherals.pins.gpio3;
+    let uart_tx_peripheral = peripherals.pins.gpio1;
 
     let timers_cfg = TimerConfig::new().auto_reload(true);
     let tx_rmt_cfg = TransmitConfig::new().clock_divider(1);
+    let uart_cfg = UartConfig::new().baudrate(Hertz(115_200));
 
     let ble_timer_driver = TimerDriver::new(ble_timer_peripheral, &timers_cfg)?;
     let led_timer_driver = TimerDriver::new(led_timer_peripheral, &timers_cfg)?;
     let pin_driver = PinDriver::input(button_peripheral)?;
+    let button_pin = pin_driver.pin();
     let tx_rmt_driver =
         TxRmtDriver::new(channel_peripheral, led_peripheral, &tx_rmt_cfg)?;
+    let uart_driver = UartDriver::new(
+        uart_peripheral,
+        uart_tx_peripheral,
+        uart_rx_peripheral,
+        Option::<AnyIOPin>::None,
+        Option::<AnyIOPin>::None,
+        &uart_cfg,
+    )?;
+    power::init(button_pin, uart_driver.port())?;
 
     // The two inputs to the state machine are the button and the BLE scanner.
     // These inputs are polled in separate threads. However, BLE scanning should
-    // not run if the whole system is off. Consequently, the button also needs
-    // to be an input to the BLE scanner. This cannot be done using the general
-    // dispatcher mechanism because it can have only one listener. Hence, we
-    // use a shared state between the button and the BLE scanner.
-    let button_state = Arc::new(Mutex::new(State::Off));
-    let mut button =
-        Button::new(button_notifier, pin_driver, Arc::clone(&button_state))?;
-    spawn(move || button.poll());
+    // not run if the whole system is off. Consequently, the BLE scanner
+    // subscribes to the system on/off triggers published by the state machine.
+    // Subscriptions are bound to the subscribing task, hence the scanner's
+    // dispatcher has to be created from within its own thread.
+    let mut button = Button::new(button_notifier, pin_driver)?;
+    // The press that woke the device up is reported below, the button must be
+    // released before it is polled again.
+    let wake_trigger = hibernate::wake_trigger();
+    let woken_by_button = wake_trigger == Some(Trigger::ButtonPressed);
+    // Polling the button is not time critical, keep it out of the way.
+    Builder::new(c"button")
+        .stack_size(3072)
+        .priority(2)
+        .spawn(move || {
+            if woken_by_button {
+                button.settle()?;
+            }
+            button.poll()
+        })?;
 
     let ble_timer = Timer::new(ble_timer_driver)?;
-    let mut scanner =
-        Scanner::new(NAME, ble_notifier, ble_timer, Arc::clone(&button_state))?;
-    spawn(move || scanner.poll());
+    let scanner_bus = bus.clone();
+    let scanner_peers = peers.clone();
+    let scanner_membership = membership.clone();
+    let scanner_mesh = mesh.clone();
+    let scanner_rhythm = rhythm.clone();
+    let scanner_leadership = leadership.clone();
+    let scanner_sniffer = sniffer.clone();
+    let scan_config = config.scan;
+    let key = config.auth_key;
+    // The devices are told apart from the third-party iBeacons by their major.
+    let major = config
+        .beacon
+        .frames
+        .contains(&Frame::IBeacon)
+        .then_some(config.beacon.major);
+    let beacon = config.beacon.uuid.map(|uuid| (uuid, major));
+    // Keep the scanner on the same core as the NimBLE host task.
+    Builder::new(c"ble-scanner")
+        .stack_size(8192)
+        .core(Core::Core0)
+        .spawn(move || {
+            let dispatcher = scanner_bus.subscribe(
+                Trigger::SystemOn | Trigger::SystemOff | Trigger::ScanRequested,
+            )?;
+            let mut scanner = Scanner::new(
+                name,
+                ble_notifier,
+                dispatcher,
+                ble_timer,
+                scanner_peers,
+                &scan_config,
+                scanner_membership,
+            )?
+            .with_sniffer(scanner_sniffer);
+            if let Some(key) = key {
+                scanner = scanner.with_key(key);
+            }
+            if let Some(mesh) = scanner_mesh {
+                scanner = scanner.with_mesh(mesh);
+            }
+            if let Some(rhythm) = scanner_rhythm {
+                scanner = scanner.with_rhythm(rhythm);
+            }
+            if let Some(leadership) = scanner_leadership {
+                scanner = scanner.with_leadership(leadership);
+            }
+            if let Some((uuid, major)) = beacon {
+                scanner = scanner.with_beacon(uuid, major);
+            }
+            scanner.poll()
+        })?;
 
-    let advertiser = Advertiser::new(NAME)?;
+    // The serial console controls the other components through the bus and
+    // the shared status, peers, sniffer and journal.
+    let controller = Controller::new(
+        console_notifier,
+        status.clone(),
+        peers,
+        sniffer,
+        journal.clone(),
+        store,
+    )?;
+    let mut console = Console::new(uart_driver, controller)?;
+    Builder::new(c"console")
+        .stack_size(6144)
+        .priority(3)
+        .spawn(move || console.poll())?;
+
+    // Most boards have no battery, monitor it only if a pin is configured.
+    let battery = match config.battery_pin {
+        Some(pin) => {
+            let charge = Arc::new(Charge::new()?);
+            let mut monitor = Monitor::new(
+                bus.notifier()?,
+                charge.clone(),
+                pin,
+                config.battery_scale,
+            )?;
+            Builder::new(c"battery")
+                .stack_size(3072)
+                .priority(2)
+                .spawn(move || monitor.poll())?;
+            Some(charge)
+        }
+        None => None,
+    };
+
+    let mut advertiser = Advertiser::new(name, config.advertising, membership)?
+        .with_beacons(config.beacon)?;
+    if let Some(key) = key {
+        let sequence = Sequence::new(partition.clone(), Sequence::ADVERTISEMENTS)?;
+        advertiser = advertiser.with_key(key, sequence)?;
+    }
+    if let Some(mesh) = mesh {
+        let sequence = Sequence::new(partition.clone(), Sequence::MESH)?;
+        advertiser = advertiser.with_mesh(mesh, sequence, config.mesh.ttl);
+    }
     let led = Led::new(tx_rmt_driver)?;
     let mut led_timer = Timer::new(led_timer_driver)?;
     led_timer.configure_interrupt(BLINK_FREQ, led_timer_notifier)?;
-    let mut sm = StateMachine::new(advertiser, led, led_timer, dispatcher)?;
+    watchdog::watch(c"state-machine")?;
+    let mut sm = StateMachine::new(
+        advertiser,
+        led,
+        led_timer,
+        dispatcher,
+        sm_notifier,
+        journal,
+        status,
+    )?;
+    if let Some(sleep_after) = config.sleep_after {
+        sm = sm.with_hibernate(Hibernate::new(
+            sleep_after,
+            config.wake_every,
+            button_pin,
+        )?);
+    }
+    if let Some(battery) = battery {
+        sm = sm.with_battery(battery);
+    }
+    if let Some(rhythm) = rhythm {
+        sm = sm.with_rhythm(rhythm);
+    }
+    if let Some(leadership) = leadership {
+        sm = sm.with_leadership(leadership);
+    }
+    // The group changed with the button survives restarts and deep sleeps.
+    sm = sm.with_store(Store::new(partition)?);
+    // Report what woke the device up, e.g. the button press that should switch
+    // the system on.
+    if let Some(trigger) = wake_trigger {
+        wake_notifier.notify(trigger)?;
+    }
     sm.run()
 }
+
+/// Starts either the normal firmware or the safe mode after repeated crashes.
+///
+/// # Errors
+/// Returns an error if the firmware fails.
+fn start() -> Result<()> {
+    let peripherals = Peripherals::take()?;
+    let partition = EspDefaultNvsPartition::take()?;
+    let store = Store::new(partition.clone())?;
+
+    if crash::safe_mode() {
+        safe_mode(peripherals, partition, store)
+    } else {
+        let journal = Arc::new(Journal::new(
+            JOURNAL_CAPACITY,
+            Some(Mirror::new(partition.clone())?),
+        )?);
+        // The mirror is written at most every few seconds: save the last
+        // transitions before a crash restarts the device.
+        let rescued = journal.clone();
+        crash::on_crash(move || rescued.rescue());
+        run(peripherals, partition, store, journal)
+    }
+}
+
+fn main() -> Result<()> {
+    // main() should never return. Restart the device if it does.
+    let _guard = ExitGuard;
+
+    // It is necessary to call this function once. Otherwise some patches to the runtime
+    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
+    esp_idf_hal::sys::link_patches();
+
+    logger::init()?;
+    crash::init();
+
+    start().inspect_err(crash::record)
+}
@@ -1,17 +1,1087 @@
 use anyhow::{anyhow, Result};
-use esp32_nimble::{BLEAdvertisementData, BLEDevice, BLEScan};
-use esp_idf_hal::task::block_on;
-use std::sync::{Arc, Mutex};
+use esp32_nimble::{
+    enums::{AdvType, ScanFilterPolicy},
+    utilities::BleUuid,
+    BLEAddressType, BLEAdvertisedData, BLEAdvertisedDevice, BLEAdvertisementData,
+    BLEDevice, BLEScan,
+};
+use esp_idf_hal::{
+    sys::{
+        ble_addr_t, ble_gap_adv_params, ble_gap_adv_start, ble_gap_adv_stop,
+        ble_gap_wl_set, ble_hs_id_set_rnd, esp,
+        esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV, esp_ble_tx_power_set,
+        esp_mac_type_t_ESP_MAC_BT, esp_power_level_t,
+        esp_power_level_t_ESP_PWR_LVL_N0, esp_power_level_t_ESP_PWR_LVL_N12,
+        esp_power_level_t_ESP_PWR_LVL_N3, esp_power_level_t_ESP_PWR_LVL_N6,
+        esp_power_level_t_ESP_PWR_LVL_N9, esp_power_level_t_ESP_PWR_LVL_P3,
+        esp_power_level_t_ESP_PWR_LVL_P6, esp_power_level_t_ESP_PWR_LVL_P9,
+        esp_read_mac, esp_timer_get_time, BLE_ADDR_PUBLIC, BLE_GAP_CONN_MODE_NON,
+        BLE_GAP_DISC_MODE_GEN, BLE_OWN_ADDR_PUBLIC, BLE_OWN_ADDR_RANDOM,
+    },
+    task::block_on,
+};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{debug, trace, warn};
+use std::{
+    collections::VecDeque,
+    fmt, ptr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    button,
+    auth::{Key, Replay, NONCE_LEN},
+    beacon::{self, Beacon, BeaconConfig, Frame, Uuid},
     clock::Timer,
+    election::{self, Election},
+    group::{Group, Membership},
     infra::{Poller, Switch},
-    message::{Notifier, Trigger},
+    light::BLINK_FREQ,
+    lru::Lru,
+    mesh::{Command, Flood, Message, Role},
+    message::{Dispatcher, Notifier, Trigger},
+    metrics::{self, lock},
+    schedule::{Policy, Schedule},
+    sniff::{Advertisement, Capture, Format, Pdu},
+    sync::{Beats, Oscillator},
+    watchdog,
 };
 
+/// Frequency the scanner checks the system triggers and its schedule at.
 const SCAN_FREQ: u64 = 1;
 
+/// Maximum number of peers remembered by `Peers`.
+const MAX_PEERS: usize = 16;
+
+/// UUID of the standard Battery Service, whose data is the battery level.
+const BATTERY_SERVICE: BleUuid = BleUuid::from_uuid16(0x180F);
+
+/// UUID of the Eddystone service, whose data are the Eddystone frames.
+const EDDYSTONE_SERVICE: BleUuid = BleUuid::from_uuid16(beacon::EDDYSTONE);
+
+/// Company identifier of the authenticated advertisements, the one reserved
+/// for testing.
+const COMPANY_ID: u16 = 0xFFFF;
+
+/// Period the counter of the authenticated advertisements advances at, so
+/// that recorded advertisements go stale.
+const ROLL_PERIOD: Duration = Duration::from_secs(30);
+
+/// Period the ephemeral address of a private advertiser changes at, as for
+/// the resolvable private addresses of NimBLE.
+const ROTATE_PERIOD: Duration = Duration::from_secs(15 * 60);
+
+/// Duration a counter is accepted for by the scanner, twice `ROLL_PERIOD` so
+/// that a peer is not rejected for rolling late.
+const FRESH: Duration = Duration::from_secs(60);
+
+/// Duration a mesh message is sent for, long enough for scanners that backed
+/// off to hear it.
+const TRANSMIT: Duration = Duration::from_secs(12);
+
+/// Duration of the slots a mesh message and the state of the device take
+/// turns in, so that peers keep seeing the state while the message is sent.
+const SLOT: Duration = Duration::from_secs(1);
+
+/// Maximum number of mesh messages waiting to be sent.
+const MAX_OUTBOX: usize = 4;
+
+/// Duration a peer is deemed alive for after its last advertisement, longer
+/// than the interval between the scans while peers are around.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Duration of a scan while sniffing, long compared to the pause between the
+/// scans so that few advertisements are missed.
+const SNIFF_WINDOW: Duration = Duration::from_secs(2);
+
+/// Duration after which the sniffer reports an unchanged advertisement again.
+const SNIFF_REFRESH: Duration = Duration::from_secs(10);
+
+/// Maximum number of advertisers the sniffer deduplicates.
+const MAX_ADVERTISERS: usize = 64;
+
+/// Maximum number of bytes the sniffer holds until they are streamed, about
+/// a third of a second of serial output.
+const MAX_SNIFFED: usize = 4096;
+
+/// Returns the public address of the device.
+///
+/// # Returns
+/// The Bluetooth MAC address, most significant byte first.
+///
+/// # Errors
+/// Returns an error if the address cannot be read.
+fn own_address() -> Result<[u8; 6]> {
+    let mut addr = [0; 6];
+    esp!(unsafe { esp_read_mac(addr.as_mut_ptr(), esp_mac_type_t_ESP_MAC_BT) })?;
+
+    Ok(addr)
+}
+
+/// Represents the advertising parameters.
+///
+/// # Fields
+/// * `interval` - Interval between two advertisements, from 100 ms to 10.24 s.
+/// * `tx_power` - Transmit power in dBm, from -12 to 9 by steps of 3.
+/// * `channels` - Advertising channels, bit 0 for channel 37 up to bit 2 for
+///   channel 39.
+/// * `scan_response` - Whether scan requests are answered with extra data,
+///   e.g. the battery level.
+/// * `privacy` - Whether authenticated advertisements are encrypted and sent
+///   from an ephemeral address, so that only the group can track the device.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AdvertisingConfig {
+    pub interval: Duration,
+    pub tx_power: i8,
+    pub channels: u8,
+    pub scan_response: bool,
+    pub privacy: bool,
+}
+
+impl Default for AdvertisingConfig {
+    /// Returns the default advertising parameters: non-connectable, at the
+    /// default transmit power, every 100 ms on all channels.
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            tx_power: 3,
+            channels: Self::ALL_CHANNELS,
+            scan_response: true,
+            privacy: false,
+        }
+    }
+}
+
+impl AdvertisingConfig {
+    /// Mask of the three advertising channels.
+    pub const ALL_CHANNELS: u8 = 0b111;
+    /// Number of the first advertising channel, mapped to bit 0.
+    pub const FIRST_CHANNEL: u8 = 37;
+    /// Bounds of the advertising interval. The device has no GATT server to
+    /// connect to, and non-connectable advertising may not be sent more often
+    /// than every 100 ms.
+    pub const INTERVALS: [Duration; 2] =
+        [Duration::from_millis(100), Duration::from_millis(10_240)];
+
+    /// Checks that the parameters are supported.
+    ///
+    /// # Errors
+    /// Returns an error describing the first invalid parameter.
+    pub fn validate(&self) -> Result<()> {
+        let [min, max] = Self::INTERVALS;
+        if !(min..=max).contains(&self.interval) {
+            return Err(anyhow!(
+                "Invalid adv_interval: expected {:?} to {:?}",
+                min,
+                max
+            ));
+        }
+        self.power_level()?;
+        if self.channels == 0 || self.channels & !Self::ALL_CHANNELS != 0 {
+            return Err(anyhow!("Invalid adv_channels: expected 37, 38 or 39"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the controller power level of the transmit power.
+    ///
+    /// # Errors
+    /// Returns an error if the controller has no such level.
+    fn power_level(&self) -> Result<esp_power_level_t> {
+        match self.tx_power {
+            -12 => Ok(esp_power_level_t_ESP_PWR_LVL_N12),
+            -9 => Ok(esp_power_level_t_ESP_PWR_LVL_N9),
+            -6 => Ok(esp_power_level_t_ESP_PWR_LVL_N6),
+            -3 => Ok(esp_power_level_t_ESP_PWR_LVL_N3),
+            0 => Ok(esp_power_level_t_ESP_PWR_LVL_N0),
+            3 => Ok(esp_power_level_t_ESP_PWR_LVL_P3),
+            6 => Ok(esp_power_level_t_ESP_PWR_LVL_P6),
+            9 => Ok(esp_power_level_t_ESP_PWR_LVL_P9),
+            _ => Err(anyhow!(
+                "Invalid tx_power: {} dBm, expected -12 to 9 by steps of 3",
+                self.tx_power
+            )),
+        }
+    }
+
+    /// Returns the NimBLE advertising parameters.
+    ///
+    /// # Errors
+    /// Returns an error if a parameter is out of range.
+    fn params(&self) -> Result<ble_gap_adv_params> {
+        // Intervals are expressed in units of 0.625 ms.
+        let interval = u16::try_from(self.interval.as_micros() / 625)?;
+
+        Ok(ble_gap_adv_params {
+            conn_mode: u8::try_from(BLE_GAP_CONN_MODE_NON)?,
+            disc_mode: u8::try_from(BLE_GAP_DISC_MODE_GEN)?,
+            itvl_min: interval,
+            itvl_max: interval,
+            channel_map: self.channels,
+            ..Default::default()
+        })
+    }
+}
+
+/// Represents a public BLE address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Address([u8; 6]);
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    /// Parses an address from its colon-separated notation, e.g.
+    /// `24:0a:c4:12:34:56`.
+    ///
+    /// # Errors
+    /// Returns an error if the notation is invalid.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0; 6];
+        let mut parts = s.split(':');
+
+        for byte in &mut bytes {
+            let part = parts
+                .next()
+                .filter(|part| part.len() == 2)
+                .ok_or_else(|| anyhow!("Invalid address: {}", s))?;
+            *byte = u8::from_str_radix(part, 16)?;
+        }
+        if parts.next().is_some() {
+            return Err(anyhow!("Invalid address: {}", s));
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Address {
+    /// Formats the address in its colon-separated notation.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl From<[u8; 6]> for Address {
+    /// Converts bytes, most significant first, into an address.
+    fn from(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Address {
+    /// Returns the bytes of the address.
+    ///
+    /// # Returns
+    /// The bytes, most significant first.
+    #[must_use]
+    pub fn bytes(self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Returns the NimBLE representation of the address.
+    ///
+    /// # Errors
+    /// Returns an error if the address type is out of range.
+    fn raw(self) -> Result<ble_addr_t> {
+        let mut val = self.0;
+        // NimBLE stores addresses least significant byte first.
+        val.reverse();
+
+        Ok(ble_addr_t {
+            type_: u8::try_from(BLE_ADDR_PUBLIC)?,
+            val,
+        })
+    }
+}
+
+/// Represents the scan parameters.
+///
+/// # Fields
+/// * `policy` - The policy the scans are scheduled with.
+/// * `active` - Whether scan requests are sent to get the scan responses. The
+///   state of the peers is in their advertisements, but their battery level is
+///   in their scan responses.
+/// * `interval` - Interval between two listening windows during a scan, from
+///   2.5 ms to 10.24 s.
+/// * `window` - Duration of a listening window, at most `interval`.
+/// * `filter_duplicates` - Whether the controller reports a device once per
+///   scan only.
+/// * `whitelist` - The addresses of the known peers. If any, the controller
+///   ignores the other devices.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScanConfig {
+    pub policy: Policy,
+    pub active: bool,
+    pub interval: Duration,
+    pub window: Duration,
+    pub filter_duplicates: bool,
+    pub whitelist: Vec<Address>,
+}
+
+impl Default for ScanConfig {
+    /// Returns the default scan parameters: balanced, active, listening
+    /// continuously to every device.
+    fn default() -> Self {
+        Self {
+            policy: Policy::Balanced,
+            active: true,
+            interval: Duration::from_millis(100),
+            window: Duration::from_millis(100),
+            filter_duplicates: true,
+            whitelist: Vec::new(),
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Maximum number of addresses in the whitelist.
+    pub const MAX_WHITELIST: usize = 8;
+    /// Bounds of the scan interval and window.
+    const INTERVALS: [Duration; 2] =
+        [Duration::from_micros(2500), Duration::from_millis(10_240)];
+
+    /// Checks that the parameters are supported.
+    ///
+    /// # Errors
+    /// Returns an error describing the first invalid parameter.
+    pub fn validate(&self) -> Result<()> {
+        let [min, max] = Self::INTERVALS;
+        if !(min..=max).contains(&self.interval) {
+            return Err(anyhow!(
+                "Invalid scan_interval: expected {:?} to {:?}",
+                min,
+                max
+            ));
+        }
+        if !(min..=self.interval).contains(&self.window) {
+            return Err(anyhow!(
+                "Invalid scan_window: expected {:?} to scan_interval",
+                min
+            ));
+        }
+        if self.whitelist.len() > Self::MAX_WHITELIST {
+            return Err(anyhow!(
+                "Invalid whitelist: more than {} addresses",
+                Self::MAX_WHITELIST
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Configures a scan and the controller whitelist.
+    ///
+    /// # Arguments
+    /// * `scan` - The scan to configure.
+    ///
+    /// # Errors
+    /// Returns an error if a parameter is out of range or if the whitelist
+    /// cannot be set.
+    fn apply(&self, scan: &mut BLEScan) -> Result<()> {
+        // Intervals are expressed in units of 0.625 ms.
+        let interval = u16::try_from(self.interval.as_micros() / 625)?;
+        let window = u16::try_from(self.window.as_micros() / 625)?;
+        let filter_policy = if self.whitelist.is_empty() {
+            ScanFilterPolicy::NoWl
+        } else {
+            let addrs = self
+                .whitelist
+                .iter()
+                .map(|addr| addr.raw())
+                .collect::<Result<Vec<_>>>()?;
+            let rc = unsafe {
+                ble_gap_wl_set(addrs.as_ptr(), u8::try_from(addrs.len())?)
+            };
+            if rc != 0 {
+                return Err(anyhow!("Whitelist cannot be set: NimBLE error {}", rc));
+            }
+            ScanFilterPolicy::UseWl
+        };
+
+        scan.active_scan(self.active)
+            .interval(interval)
+            .window(window)
+            .filter_duplicates(self.filter_duplicates)
+            .filter_policy(filter_policy);
+
+        Ok(())
+    }
+}
+
+/// Represents counters increasing across restarts, e.g. the counters of the
+/// authenticated advertisements.
+///
+/// Counters are reserved in NVS by blocks, so that NVS is written once per
+/// block rather than once per counter.
+pub struct Sequence {
+    nvs: EspNvs<NvsDefault>,
+    reserved_key: &'static str,
+    next: u32,
+    reserved: u32,
+}
+
+impl Sequence {
+    /// NVS key of the end of the block reserved for the authenticated
+    /// advertisements.
+    pub const ADVERTISEMENTS: &'static str = "adv_counter";
+    /// NVS key of the end of the block reserved for the mesh messages.
+    pub const MESH: &'static str = "mesh_counter";
+    /// NVS namespace of the sequences.
+    const NAMESPACE: &'static str = "ble";
+    /// Number of counters reserved at once.
+    const BLOCK: u32 = 256;
+
+    /// Creates a new `Sequence` instance, resuming after the counters reserved
+    /// before the restart.
+    ///
+    /// # Arguments
+    /// * `partition` - The default NVS partition.
+    /// * `reserved_key` - The NVS key of the end of the reserved block, e.g.
+    ///   `ADVERTISEMENTS`.
+    ///
+    /// # Errors
+    /// Returns an error if the NVS namespace cannot be opened or read.
+    pub fn new(
+        partition: EspDefaultNvsPartition,
+        reserved_key: &'static str,
+    ) -> Result<Self> {
+        let nvs = EspNvs::new(partition, Self::NAMESPACE, true)?;
+        let next = nvs.get_u32(reserved_key)?.unwrap_or(0);
+
+        Ok(Self {
+            nvs,
+            reserved_key,
+            next,
+            reserved: next,
+        })
+    }
+
+    /// Returns the next counter.
+    ///
+    /// # Errors
+    /// Returns an error if the counters are exhausted or if a block cannot be
+    /// reserved.
+    fn next(&mut self) -> Result<u32> {
+        if self.next == self.reserved {
+            self.reserved = self.next.checked_add(Self::BLOCK).ok_or_else(|| {
+                anyhow!("Counters exhausted: {}", self.reserved_key)
+            })?;
+            self.nvs.set_u32(self.reserved_key, self.reserved)?;
+        }
+
+        let counter = self.next;
+        self.next += 1;
+        Ok(counter)
+    }
+}
+
+/// Represents the authentication of the advertisements.
+///
+/// # Fields
+/// * `key` - The key shared by the group.
+/// * `sequence` - The counters of the advertisements, which the nonces of the
+///   encrypted frames are derived from too.
+/// * `sender` - The address of the device, identifying it to its peers.
+/// * `private` - Whether the frames are encrypted and sent from an ephemeral
+///   address.
+/// * `counter` - The counter of the frames.
+/// * `beat` - The number of flashes of the LED, wrapping, once the blinking is
+///   synchronised.
+/// * `rolled` - When the counter last advanced.
+/// * `rotated` - When the ephemeral address last changed.
+struct Seal {
+    key: Key,
+    sequence: Sequence,
+    sender: [u8; 6],
+    private: bool,
+    counter: u32,
+    beat: Option<u8>,
+    rolled: Instant,
+    rotated: Instant,
+}
+
+impl Seal {
+    /// Advances the counter, so that the frames sealed before go stale.
+    ///
+    /// # Errors
+    /// Returns an error if no counter is available.
+    fn advance(&mut self) -> Result<()> {
+        self.counter = self.sequence.next()?;
+        self.rolled = Instant::now();
+
+        Ok(())
+    }
+
+    /// Returns a fresh nonce to encrypt a frame with.
+    ///
+    /// Every encrypted frame takes a counter, as the frames sealed with the same
+    /// counter differ, e.g. by their beat.
+    ///
+    /// # Errors
+    /// Returns an error if no counter is available.
+    fn nonce(&mut self) -> Result<[u8; NONCE_LEN]> {
+        let counter = self.sequence.next()?;
+
+        Ok(self.key.nonce(&self.sender, counter))
+    }
+
+    /// Seals the state of the advertiser with the current counter.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the devices, which the frame is bound to.
+    /// * `group` - The group of the device.
+    /// * `active` - Whether the advertiser is active.
+    ///
+    /// # Returns
+    /// The manufacturer data: the company identifier, then the frame carrying
+    /// the state, the group, the sender and, if synchronised, the beat.
+    ///
+    /// # Errors
+    /// Returns an error if no nonce is available or if the frame cannot be
+    /// encrypted.
+    fn frame(&mut self, name: &str, group: Group, active: bool) -> Result<Vec<u8>> {
+        let mut payload = vec![u8::from(active), group.id()];
+        payload.extend_from_slice(&self.sender);
+        payload.extend(self.beat);
+        let mut data = COMPANY_ID.to_le_bytes().to_vec();
+        if self.private {
+            let nonce = self.nonce()?;
+            data.extend(self.key.seal_secret(
+                name.as_bytes(),
+                nonce,
+                self.counter,
+                &payload,
+            )?);
+        } else {
+            data.extend(self.key.seal(name.as_bytes(), self.counter, &payload));
+        }
+
+        Ok(data)
+    }
+
+    /// Changes the ephemeral address, which stops advertising.
+    ///
+    /// The address is derived from the key, the address of the device and a
+    /// fresh counter, so that it looks random to outsiders. Peers recognize
+    /// the device from the encrypted frames instead.
+    ///
+    /// # Errors
+    /// Returns an error if no counter is available or if the address cannot be
+    /// set.
+    fn rotate(&mut self) -> Result<()> {
+        let slot = self.sequence.next()?;
+        let mut val = self.key.ephemeral(&self.sender, slot);
+        // NimBLE stores addresses least significant byte first.
+        val.reverse();
+        self.rotated = Instant::now();
+
+        let rc = unsafe {
+            // The address cannot change while advertising.
+            ble_gap_adv_stop();
+            ble_hs_id_set_rnd(val.as_ptr())
+        };
+        if rc != 0 {
+            return Err(anyhow!("Address cannot be set: NimBLE error {}", rc));
+        }
+
+        Ok(())
+    }
+}
+
+/// Authenticates the manufacturer data of an advertisement.
+///
+/// # Arguments
+/// * `key` - The key shared by the devices.
+/// * `name` - The name of the devices, which the frame is bound to.
+/// * `group` - The group of the device, the peers of other groups are ignored.
+/// * `replay` - The replay protection, keyed by sender.
+/// * `frame` - The frame, after the company identifier, in clear or
+///   encrypted.
+///
+/// # Returns
+/// The trigger the peer causes, its permanent address and its beat if it
+/// synchronises its blinking, `None` if the frame is forged, e.g. with another
+/// key, replayed or from another group.
+fn authenticate(
+    key: &Key,
+    name: &str,
+    group: Group,
+    replay: &mut Replay<[u8; 6]>,
+    frame: &[u8],
+) -> Option<(Trigger, Address, Option<u8>)> {
+    let opened = key
+        .open(name.as_bytes(), frame)
+        .map(|(counter, payload)| (counter, payload.to_vec()))
+        .or_else(|| key.open_secret(name.as_bytes(), frame));
+    let Some((counter, payload)) = opened else {
+        metrics::SCAN_REJECTS.inc();
+        debug!("forged advertisement");
+        return None;
+    };
+    let [active, id, rest @ ..] = payload.as_slice() else {
+        return None;
+    };
+    let (sender, beat) = match rest {
+        [sender @ .., beat] if sender.len() == 6 => (sender, Some(*beat)),
+        sender => (sender, None),
+    };
+    let sender = <[u8; 6]>::try_from(sender).ok()?;
+    if !replay.check(sender, counter, Instant::now()) {
+        metrics::SCAN_REJECTS.inc();
+        debug!(peer:% = Address::from(sender), counter; "replayed advertisement");
+        return None;
+    }
+    if *id != group.id() {
+        return None;
+    }
+
+    let trigger = match active {
+        0 => Trigger::DeviceFoundInactive,
+        1 => Trigger::DeviceFoundActive,
+        _ => return None,
+    };
+
+    Some((trigger, Address::from(sender), beat))
+}
+
+/// Recognizes a standard beacon in an advertisement.
+///
+/// # Arguments
+/// * `data` - The advertisement data.
+///
+/// # Returns
+/// The iBeacon or Eddystone-UID beacon, `None` if the advertisement is
+/// neither.
+fn recognize(data: &BLEAdvertisedData<&[u8]>) -> Option<Beacon> {
+    data.manufacture_data()
+        .and_then(|data| {
+            Beacon::from_manufacturer(data.company_identifier, data.payload)
+        })
+        .or_else(|| {
+            data.service_data()
+                .filter(|data| data.uuid == EDDYSTONE_SERVICE)
+                .and_then(|data| Beacon::from_eddystone(data.service_data))
+        })
+}
+
+/// Represents the mesh messages flowing through the device, shared between
+/// the scanner, which receives them, and the advertiser, which sends them.
+pub struct Mesh {
+    flood: Mutex<Flood>,
+    outbox: Mutex<VecDeque<Message>>,
+    nvs: Mutex<EspNvs<NvsDefault>>,
+}
+
+impl Mesh {
+    /// NVS key of the newest sequence numbers of the origins.
+    const NEWEST: &'static str = "mesh_newest";
+
+    /// Creates a new `Mesh` instance, remembering the messages handled before
+    /// the restart.
+    ///
+    /// # Arguments
+    /// * `partition` - The default NVS partition.
+    /// * `role` - The part the device plays in the mesh.
+    ///
+    /// # Errors
+    /// Returns an error if the address of the device cannot be read or if the
+    /// NVS namespace cannot be opened or read.
+    pub fn new(partition: EspDefaultNvsPartition, role: Role) -> Result<Self> {
+        let nvs = EspNvs::new(partition, Sequence::NAMESPACE, true)?;
+        let mut flood = Flood::new(own_address()?, role, MAX_PEERS);
+        let mut buf = [0; MAX_PEERS * Flood::SAVED_LEN];
+        if let Some(saved) = nvs.get_raw(Self::NEWEST, &mut buf)? {
+            flood.restore(saved);
+        }
+
+        Ok(Self {
+            flood: Mutex::new(flood),
+            outbox: Mutex::new(VecDeque::with_capacity(MAX_OUTBOX)),
+            nvs: Mutex::new(nvs),
+        })
+    }
+
+    /// Queues a message to send, forgetting the oldest queued message if the
+    /// outbox is full.
+    ///
+    /// # Arguments
+    /// * `message` - The message to send.
+    fn send(&self, message: Message) {
+        let mut outbox = lock(&self.outbox);
+        if outbox.len() == MAX_OUTBOX {
+            outbox.pop_front();
+        }
+        outbox.push_back(message);
+    }
+
+    /// Returns the next message to send.
+    ///
+    /// # Returns
+    /// The oldest queued message, `None` if there is none.
+    fn next(&self) -> Option<Message> {
+        lock(&self.outbox).pop_front()
+    }
+
+    /// Handles a message heard from a device in range, and queues it to be
+    /// relayed if the device is a relay.
+    ///
+    /// The newest sequence numbers are saved whenever a message is handled,
+    /// which only happens when a button switches a system.
+    ///
+    /// # Arguments
+    /// * `message` - The message, meant for the group of the device.
+    ///
+    /// # Returns
+    /// The command to obey, `None` if the message was already handled.
+    fn receive(&self, message: Message) -> Option<Command> {
+        let mut flood = lock(&self.flood);
+        let receipt = flood.receive(message)?;
+        if let Err(err) = lock(&self.nvs).set_raw(Self::NEWEST, &flood.save()) {
+            warn!(err:% = err; "Mesh origins not saved");
+        }
+        drop(flood);
+        debug!(
+            origin:% = Address::from(message.origin),
+            seq = message.seq,
+            command:? = receipt.command;
+            "mesh message received"
+        );
+        if let Some(forward) = receipt.forward {
+            metrics::MESH_RELAYS.inc();
+            self.send(forward);
+        }
+
+        Some(receipt.command)
+    }
+}
+
+/// Represents the sending of the mesh messages by the advertiser.
+///
+/// A message is sent for `TRANSMIT`, taking turns with the state of the device
+/// every `SLOT`.
+///
+/// # Fields
+/// * `mesh` - The messages flowing through the device.
+/// * `sequence` - The sequence numbers of the messages originating from the
+///   device.
+/// * `ttl` - Number of times the messages originating from the device can be
+///   relayed.
+/// * `current` - The message being sent and when it was first sent, if any.
+/// * `showing` - Whether the message is advertised, rather than the state.
+/// * `flipped` - When the advertisement last changed.
+struct Broadcast {
+    mesh: Arc<Mesh>,
+    sequence: Sequence,
+    ttl: u8,
+    current: Option<(Message, Instant)>,
+    showing: bool,
+    flipped: Instant,
+}
+
+impl Broadcast {
+    /// Returns what to advertise next, if the slot is over.
+    ///
+    /// # Returns
+    /// `Some` message to advertise it, `Some(None)` to advertise the state
+    /// again, `None` to keep the current advertisement.
+    fn flip(&mut self) -> Option<Option<Message>> {
+        if self.flipped.elapsed() < SLOT {
+            return None;
+        }
+
+        if self
+            .current
+            .is_some_and(|(_, since)| since.elapsed() >= TRANSMIT)
+        {
+            self.current = None;
+        }
+        if self.current.is_none() {
+            self.current = self.mesh.next().map(|message| (message, Instant::now()));
+        }
+        let next = self
+            .current
+            .filter(|_| !self.showing)
+            .map(|(message, _)| message);
+        if next.is_none() && !self.showing {
+            return None;
+        }
+
+        self.showing = next.is_some();
+        self.flipped = Instant::now();
+        Some(next)
+    }
+}
+
+/// Represents the blinking rhythm of the device, synchronised with the devices
+/// of its group, shared between the scanner, which hears their beats, and the
+/// state machine, which blinks the LED.
+///
+/// Each device advertises a beat, which advances when its LED flashes. The
+/// devices follow the one with the lowest address among those they hear, so
+/// that the group converges to its rhythm.
+pub struct Rhythm {
+    oscillator: Mutex<Oscillator>,
+    beats: Mutex<Beats<[u8; 6]>>,
+}
+
+impl Rhythm {
+    /// Creates a new `Rhythm` instance, blinking at `BLINK_FREQ` toggles per
+    /// second.
+    ///
+    /// # Errors
+    /// Returns an error if the address of the device cannot be read.
+    pub fn new() -> Result<Self> {
+        let period = Duration::from_millis(2000 / BLINK_FREQ);
+
+        Ok(Self {
+            oscillator: Mutex::new(Oscillator::new(period, Instant::now())),
+            beats: Mutex::new(Beats::new(own_address()?, period, MAX_PEERS)),
+        })
+    }
+
+    /// Returns the edge of the blinking nearest to the current time.
+    ///
+    /// # Returns
+    /// Whether the LED is on after the edge, and the duration until the next
+    /// edge.
+    #[must_use]
+    pub fn edge(&self) -> (bool, Duration) {
+        lock(&self.oscillator).edge(Instant::now())
+    }
+
+    /// Records the beat of a peer, and follows its flash if it leads.
+    ///
+    /// # Arguments
+    /// * `peer` - The permanent address of the peer.
+    /// * `beat` - The beat advertised by the peer.
+    fn heard(&self, peer: Address, beat: u8) {
+        let now = Instant::now();
+        if lock(&self.beats).check(peer.bytes(), beat, now) {
+            trace!(peer:% = peer, beat; "leader flashed");
+            lock(&self.oscillator).observe(now);
+        }
+    }
+}
+
+/// Represents the election of the coordinator of the group, shared between the
+/// scanner, which hears the heartbeats of the peers, and the state machine,
+/// which acts on the role of the device.
+///
+/// The heartbeats are the advertisements of the devices of the group, and the
+/// device with the lowest address leads.
+pub struct Leadership {
+    election: Mutex<Election<[u8; 6]>>,
+}
+
+impl Leadership {
+    /// Creates a new `Leadership` instance, standing as a candidate.
+    ///
+    /// # Errors
+    /// Returns an error if the address of the device cannot be read.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            election: Mutex::new(Election::new(
+                own_address()?,
+                HEARTBEAT_TIMEOUT,
+                MAX_PEERS,
+                Instant::now(),
+            )),
+        })
+    }
+
+    /// Returns the role of the device.
+    ///
+    /// # Returns
+    /// The role after the heartbeats heard so far.
+    #[must_use]
+    pub fn role(&self) -> election::Role {
+        lock(&self.election).role(Instant::now())
+    }
+
+    /// Returns the leader of the group.
+    ///
+    /// # Returns
+    /// The address of the leader, `None` if the device leads or stands as a
+    /// candidate.
+    #[must_use]
+    pub fn leader(&self) -> Option<Address> {
+        lock(&self.election)
+            .leader(Instant::now())
+            .map(Address::from)
+    }
+
+    /// Records the heartbeat of a peer.
+    ///
+    /// # Arguments
+    /// * `peer` - The address of the peer.
+    fn heard(&self, peer: Address) {
+        lock(&self.election).heartbeat(peer.bytes(), Instant::now());
+    }
+}
+
+/// Represents the sniffer, reporting every advertisement the scanner hears,
+/// shared between the scanner and the console, which streams the reports.
+pub struct Sniffer {
+    capture: Mutex<Option<Capture>>,
+}
+
+impl Sniffer {
+    /// Creates a new `Sniffer` instance, stopped.
+    ///
+    /// # Errors
+    /// Returns an error if the sniffer cannot be initialized.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            capture: Mutex::new(None),
+        })
+    }
+
+    /// Starts a new capture, making the scanner scan continuously.
+    ///
+    /// # Arguments
+    /// * `format` - The stream format.
+    pub fn start(&self, format: Format) {
+        *lock(&self.capture) = Some(Capture::new(
+            format,
+            SNIFF_REFRESH,
+            MAX_ADVERTISERS,
+            MAX_SNIFFED,
+        ));
+    }
+
+    /// Stops the capture.
+    pub fn stop(&self) {
+        *lock(&self.capture) = None;
+    }
+
+    /// Returns whether a capture is running.
+    ///
+    /// # Returns
+    /// `true` if the sniffer was started and not stopped since.
+    #[must_use]
+    pub fn active(&self) -> bool {
+        lock(&self.capture).is_some()
+    }
+
+    /// Takes the reports waiting to be streamed.
+    ///
+    /// # Returns
+    /// The reports, in the stream format, empty if no capture is running.
+    #[must_use]
+    pub fn take(&self) -> Vec<u8> {
+        lock(&self.capture)
+            .as_mut()
+            .map(Capture::take)
+            .unwrap_or_default()
+    }
+
+    /// Reports an advertisement if a capture is running.
+    ///
+    /// # Arguments
+    /// * `device` - The advertiser.
+    /// * `data` - The advertising data.
+    fn heard(&self, device: &BLEAdvertisedDevice, data: &BLEAdvertisedData<&[u8]>) {
+        let mut capture = lock(&self.capture);
+        let Some(capture) = capture.as_mut() else {
+            return;
+        };
+        let Ok(addr) = device.addr().to_string().parse::<Address>() else {
+            return;
+        };
+
+        let adv = Advertisement {
+            addr: addr.bytes(),
+            random: matches!(
+                device.addr().addr_type(),
+                BLEAddressType::Random | BLEAddressType::RandomID
+            ),
+            pdu: match device.adv_type() {
+                AdvType::Ind => Pdu::AdvInd,
+                AdvType::DirectInd => Pdu::DirectInd,
+                AdvType::ScanInd => Pdu::ScanInd,
+                AdvType::NonconnInd => Pdu::NonconnInd,
+                AdvType::ScanResponse => Pdu::ScanRsp,
+            },
+            rssi: i8::try_from(device.rssi()).unwrap_or(i8::MIN),
+            payload: data.payload().to_vec(),
+        };
+        let micros = unsafe { esp_timer_get_time() };
+        let uptime = Duration::from_micros(u64::try_from(micros).unwrap_or(0));
+
+        capture.record(&adv, Instant::now(), uptime);
+    }
+}
+
+/// Represents the standard beacon frames the advertiser takes turns with.
+///
+/// A frame is sent for a `SLOT`, taking turns with the state of the device,
+/// each frame in turn.
+///
+/// # Fields
+/// * `config` - The beacon parameters, with at least one frame.
+/// * `instance` - The address of the device, the instance of its
+///   Eddystone-UID frames.
+/// * `millivolts` - The battery voltage, if known.
+/// * `next` - The index of the next frame.
+/// * `showing` - Whether a frame is advertised, rather than the state.
+/// * `flipped` - When the advertisement last changed.
+struct Beacons {
+    config: BeaconConfig,
+    instance: [u8; 6],
+    millivolts: Option<u16>,
+    next: usize,
+    showing: bool,
+    flipped: Instant,
+}
+
+impl Beacons {
+    /// Returns what to advertise next, if the slot is over.
+    ///
+    /// # Returns
+    /// `Some` frame to advertise it, `Some(None)` to advertise the state
+    /// again, `None` to keep the current advertisement.
+    fn flip(&mut self) -> Option<Option<Frame>> {
+        if self.flipped.elapsed() < SLOT {
+            return None;
+        }
+
+        let next = if self.showing {
+            None
+        } else {
+            let frame = self.config.frames[self.next % self.config.frames.len()];
+            self.next = self.next.wrapping_add(1);
+            Some(frame)
+        };
+
+        self.showing = next.is_some();
+        self.flipped = Instant::now();
+        Some(next)
+    }
+}
+
+/// Returns the name advertised without authentication.
+///
+/// # Arguments
+/// * `name` - The name of the devices.
+/// * `group` - The group of the device.
+/// * `active` - Whether the advertiser is active.
+///
+/// # Returns
+/// The name followed by the state and, except for the first group, the
+/// group, e.g. `ESPlayground-Active2`. It fits in an advertisement along
+/// with the flags.
+fn advertised_name(name: &str, group: Group, active: bool) -> String {
+    let state = if active { "Active" } else { "Inactive" };
+
+    match group.id() {
+        0 => format!("{name}-{state}"),
+        id => format!("{name}-{state}{id}"),
+    }
+}
+
 /// Represents the state of the BLE advertiser.
 ///
 /// # Variants
@@ -28,7 +1098,13 @@ enum State {
 /// * `'a` - Lifetime of the advertiser.
 pub struct Advertiser<'a> {
     name: &'a str,
+    config: AdvertisingConfig,
+    membership: Arc<Membership>,
     state: State,
+    battery: Option<u8>,
+    seal: Option<Seal>,
+    broadcast: Option<Broadcast>,
+    beacons: Option<Beacons>,
 }
 
 impl<'a> Advertiser<'a> {
@@ -36,37 +1112,460 @@ impl<'a> Advertiser<'a> {
     ///
     /// # Arguments
     /// * `name` - The name of the advertiser.
+    /// * `config` - The advertising parameters.
+    /// * `membership` - The group of the device, shared with the scanner.
     ///
     /// # Errors
     /// Returns an error if the advertiser cannot be initialized.
-    pub fn new(name: &'a str) -> Result<Self> {
-        let ret = Self {
+    pub fn new(
+        name: &'a str,
+        config: AdvertisingConfig,
+        membership: Arc<Membership>,
+    ) -> Result<Self> {
+        config.validate()?;
+        // Initializes the controller before setting its transmit power.
+        BLEDevice::take();
+        esp!(unsafe {
+            esp_ble_tx_power_set(
+                esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV,
+                config.power_level()?,
+            )
+        })?;
+
+        let mut ret = Self {
             name,
+            config,
+            membership,
             state: State::Inactive,
+            battery: None,
+            seal: None,
+            broadcast: None,
+            beacons: None,
         };
         ret.apply()?;
 
         Ok(ret)
     }
 
-    /// Applies the current state to the BLE advertiser.
+    /// Authenticates the advertisements with a key shared by the group.
+    ///
+    /// Peers without the key no longer recognize the device. If privacy is
+    /// enabled, outsiders cannot track it either.
+    ///
+    /// # Arguments
+    /// * `key` - The key shared by the group.
+    /// * `sequence` - The counters of the advertisements.
+    ///
+    /// # Errors
+    /// Returns an error if the address of the device cannot be read or if the
+    /// advertising data cannot be configured.
+    pub fn with_key(mut self, key: Key, sequence: Sequence) -> Result<Self> {
+        self.seal = Some(Seal {
+            key,
+            sequence,
+            sender: own_address()?,
+            private: self.config.privacy,
+            counter: 0,
+            beat: None,
+            rolled: Instant::now(),
+            rotated: Instant::now(),
+        });
+        if let Some(seal) = self.seal.as_mut().filter(|seal| seal.private) {
+            seal.rotate()?;
+        }
+        self.apply()?;
+
+        Ok(self)
+    }
+
+    /// Sends the mesh messages, originating from the device or relayed.
+    ///
+    /// # Arguments
+    /// * `mesh` - The messages flowing through the device, shared with the
+    ///   scanner.
+    /// * `sequence` - The sequence numbers of the messages originating from
+    ///   the device.
+    /// * `ttl` - Number of times the messages originating from the device can
+    ///   be relayed.
+    ///
+    /// # Returns
+    /// The advertiser, taking part in the mesh.
+    #[must_use]
+    pub fn with_mesh(
+        mut self,
+        mesh: Arc<Mesh>,
+        sequence: Sequence,
+        ttl: u8,
+    ) -> Self {
+        self.broadcast = Some(Broadcast {
+            mesh,
+            sequence,
+            ttl,
+            current: None,
+            showing: false,
+            flipped: Instant::now(),
+        });
+        self
+    }
+
+    /// Takes turns with standard beacon frames, so that the device doubles as
+    /// an iBeacon or Eddystone beacon.
+    ///
+    /// # Arguments
+    /// * `config` - The beacon parameters.
+    ///
+    /// # Returns
+    /// The advertiser, sending the frames if there is any.
+    ///
+    /// # Errors
+    /// Returns an error if the parameters are invalid or if the address of the
+    /// device cannot be read.
+    pub fn with_beacons(mut self, config: BeaconConfig) -> Result<Self> {
+        config.validate()?;
+        if config.frames.is_empty() {
+            return Ok(self);
+        }
+
+        self.beacons = Some(Beacons {
+            config,
+            instance: own_address()?,
+            millivolts: None,
+            next: 0,
+            showing: false,
+            flipped: Instant::now(),
+        });
+        Ok(self)
+    }
+
+    /// Returns whether a mesh message or a beacon frame is advertised, rather
+    /// than the state.
+    ///
+    /// # Returns
+    /// `true` if the state is not advertised.
+    fn showing(&self) -> bool {
+        self.broadcast
+            .as_ref()
+            .is_some_and(|broadcast| broadcast.showing)
+            || self.beacons.as_ref().is_some_and(|beacons| beacons.showing)
+    }
+
+    /// Applies the current state to the BLE advertiser, with a fresh counter.
     ///
     /// # Errors
-    /// Returns an error if the BLE device or advertising data cannot be configured.
-    fn apply(&self) -> Result<()> {
+    /// Returns an error if no counter is available or if the BLE device or
+    /// advertising data cannot be configured.
+    fn apply(&mut self) -> Result<()> {
+        if let Some(seal) = self.seal.as_mut() {
+            seal.advance()?;
+        }
+
+        self.advertise()
+    }
+
+    /// Advertises the current state.
+    ///
+    /// # Errors
+    /// Returns an error if no nonce is available or if the BLE device or
+    /// advertising data cannot be configured.
+    fn advertise(&mut self) -> Result<()> {
         let device = BLEDevice::take();
         let advertising = device.get_advertising();
-        let name = match self.state {
-            // TODO: This doesn't take into account the fact that multiple devices could be nearby.
-            //       That could be handled with some kind of an ID mechanism...
-            State::Active => format!("{}-Active", self.name),
-            State::Inactive => format!("{}-Inactive", self.name),
+        let active = matches!(self.state, State::Active);
+        let group = self.membership.get();
+
+        let mut data = BLEAdvertisementData::new();
+        let mut response = BLEAdvertisementData::new();
+        if let Some(seal) = self.seal.as_mut() {
+            data.manufacturer_data(&seal.frame(self.name, group, active)?);
+            // The frame fills most of the advertisement, the name is only a
+            // courtesy to the humans in the scan response, unless private.
+            if self.config.scan_response && !seal.private {
+                response.name(self.name);
+            }
+        } else {
+            data.name(&advertised_name(self.name, group, active));
+        }
+        // The advertisement is full, the battery level goes in the scan
+        // response.
+        if let (true, Some(battery)) = (self.config.scan_response, self.battery) {
+            response.service_data(BATTERY_SERVICE, &[battery]);
+        }
+        advertising.lock().set_data(&mut data)?;
+        advertising.lock().scan_response_data(&mut response)?;
+
+        self.start()
+    }
+
+    /// Takes turns between the state, the mesh messages and the beacon frames,
+    /// advances the
+    /// counter of the authenticated advertisements if due, so that recorded
+    /// advertisements go stale, and changes the ephemeral address if due.
+    ///
+    /// # Errors
+    /// Returns an error if the address cannot be changed or if the advertising
+    /// data cannot be configured.
+    pub fn refresh(&mut self) -> Result<()> {
+        if let Some(next) = self.broadcast.as_mut().and_then(Broadcast::flip) {
+            return match next {
+                Some(message) => self.transmit(&message),
+                None => self.apply(),
+            };
+        }
+        // The beacon frames wait for the mesh messages.
+        let broadcasting = self
+            .broadcast
+            .as_ref()
+            .is_some_and(|broadcast| broadcast.showing);
+        if let Some(next) = self
+            .beacons
+            .as_mut()
+            .filter(|_| !broadcasting)
+            .and_then(Beacons::flip)
+        {
+            return match next {
+                Some(frame) => self.signal(frame),
+                None => self.advertise(),
+            };
+        }
+
+        let Some(seal) = self.seal.as_mut() else {
+            return Ok(());
+        };
+
+        if seal.private && seal.rotated.elapsed() >= ROTATE_PERIOD {
+            seal.rotate()?;
+            self.apply()?;
+        } else if seal.rolled.elapsed() >= ROLL_PERIOD {
+            self.apply()?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks the devices of the group to switch their system on or off, through
+    /// the mesh if the device takes part in it.
+    ///
+    /// # Arguments
+    /// * `command` - What the devices are asked for.
+    ///
+    /// # Errors
+    /// Returns an error if no sequence number is available.
+    pub fn broadcast(&mut self, command: Command) -> Result<()> {
+        let Some(broadcast) = self.broadcast.as_mut() else {
+            return Ok(());
+        };
+
+        let seq = broadcast.sequence.next()?;
+        let message = lock(&broadcast.mesh.flood).originate(
+            seq,
+            broadcast.ttl,
+            self.membership.get(),
+            command,
+        );
+        broadcast.mesh.send(message);
+
+        Ok(())
+    }
+
+    /// Advances the beat, as the LED flashes, so that the devices of the group
+    /// can follow the rhythm of the device.
+    ///
+    /// The counter does not advance, the beat changing every flash. The beat
+    /// is only advertised with the state, i.e. not while a mesh message or a
+    /// beacon frame is.
+    ///
+    /// # Errors
+    /// Returns an error if no nonce is available or if the advertising data
+    /// cannot be configured.
+    pub fn beat(&mut self) -> Result<()> {
+        let Some(seal) = self.seal.as_mut() else {
+            return Ok(());
         };
+        seal.beat = Some(seal.beat.map_or(0, |beat| beat.wrapping_add(1)));
 
-        advertising
+        if self.showing() {
+            return Ok(());
+        }
+        self.advertise()
+    }
+
+    /// Advertises a mesh message instead of the state.
+    ///
+    /// The message is authenticated, and encrypted if private, as the state.
+    ///
+    /// # Arguments
+    /// * `message` - The message to send.
+    ///
+    /// # Errors
+    /// Returns an error if no nonce is available or if the advertising data
+    /// cannot be configured.
+    fn transmit(&mut self, message: &Message) -> Result<()> {
+        let nonce = match self.seal.as_mut() {
+            Some(seal) if seal.private => Some(seal.nonce()?),
+            _ => None,
+        };
+        let key = self.seal.as_ref().map(|seal| &seal.key);
+
+        let mut frame = COMPANY_ID.to_le_bytes().to_vec();
+        frame.extend(message.encode(key, nonce, self.name)?);
+        let mut data = BLEAdvertisementData::new();
+        data.manufacturer_data(&frame);
+        BLEDevice::take()
+            .get_advertising()
             .lock()
-            .set_data(BLEAdvertisementData::new().name(&name))?;
-        advertising.lock().start()?;
+            .set_data(&mut data)?;
+
+        self.start()
+    }
+
+    /// Advertises a beacon frame instead of the state.
+    ///
+    /// # Arguments
+    /// * `frame` - The frame to send.
+    ///
+    /// # Errors
+    /// Returns an error if the frame cannot be encoded or if the advertising
+    /// data cannot be configured.
+    fn signal(&self, frame: Frame) -> Result<()> {
+        let Some(beacons) = self.beacons.as_ref() else {
+            return Ok(());
+        };
+        let config = &beacons.config;
+        let tx_power = self.config.tx_power;
+        let uuid = || config.uuid.ok_or_else(|| anyhow!("Beacon UUID missing"));
+
+        let mut data = BLEAdvertisementData::new();
+        let eddystone = match frame {
+            Frame::IBeacon => {
+                data.manufacturer_data(&beacon::ibeacon(
+                    uuid()?,
+                    config.major,
+                    config.minor,
+                    tx_power,
+                ));
+                None
+            }
+            Frame::Uid => {
+                Some(beacon::uid(uuid()?.namespace(), beacons.instance, tx_power))
+            }
+            Frame::Url => Some(beacon::url(&config.url, tx_power)?),
+            Frame::Tlm => {
+                let micros = unsafe { esp_timer_get_time() };
+                let uptime = Duration::from_micros(u64::try_from(micros)?);
+                // Estimated from the advertising interval.
+                let count =
+                    uptime.as_millis() / self.config.interval.as_millis().max(1);
+                Some(beacon::tlm(
+                    beacons.millivolts,
+                    u32::try_from(count).unwrap_or(u32::MAX),
+                    uptime,
+                ))
+            }
+        };
+        if let Some(eddystone) = eddystone {
+            data.add_service_uuid(EDDYSTONE_SERVICE)
+                .service_data(EDDYSTONE_SERVICE, &eddystone);
+        }
+        BLEDevice::take()
+            .get_advertising()
+            .lock()
+            .set_data(&mut data)?;
+
+        self.start()
+    }
+
+    /// (Re)starts advertising with the configured parameters.
+    ///
+    /// NimBLE is driven directly as `BLEAdvertising` does not expose the
+    /// channel map.
+    ///
+    /// # Errors
+    /// Returns an error if a parameter is out of range or if advertising
+    /// cannot be started.
+    fn start(&self) -> Result<()> {
+        let params = self.config.params()?;
+        let own_addr_type = if self.seal.as_ref().is_some_and(|seal| seal.private) {
+            BLE_OWN_ADDR_RANDOM
+        } else {
+            BLE_OWN_ADDR_PUBLIC
+        };
+        let rc = unsafe {
+            // Fails harmlessly if not advertising yet.
+            ble_gap_adv_stop();
+            ble_gap_adv_start(
+                u8::try_from(own_addr_type)?,
+                ptr::null(),
+                // BLE_HS_FOREVER
+                i32::MAX,
+                &params,
+                None,
+                ptr::null_mut(),
+            )
+        };
+        if rc != 0 {
+            return Err(anyhow!("Advertising cannot start: NimBLE error {}", rc));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the advertised battery level.
+    ///
+    /// # Returns
+    /// The battery level in percent, `None` if not advertised.
+    #[must_use]
+    pub fn battery(&self) -> Option<u8> {
+        self.battery
+    }
+
+    /// Changes the advertised battery level.
+    ///
+    /// # Arguments
+    /// * `battery` - The battery level in percent, `None` to stop advertising
+    ///   it.
+    ///
+    /// # Errors
+    /// Returns an error if the advertising data cannot be configured.
+    pub fn set_battery(&mut self, battery: Option<u8>) -> Result<()> {
+        if battery != self.battery {
+            self.battery = battery;
+            self.apply()?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the battery voltage reported by the Eddystone-TLM frames.
+    ///
+    /// # Arguments
+    /// * `millivolts` - The battery voltage, `None` if unknown.
+    pub fn set_voltage(&mut self, millivolts: Option<u32>) {
+        if let Some(beacons) = self.beacons.as_mut() {
+            beacons.millivolts = millivolts.and_then(|mv| u16::try_from(mv).ok());
+        }
+    }
+
+    /// Returns the group of the device.
+    ///
+    /// # Returns
+    /// The group advertised and scanned for.
+    #[must_use]
+    pub fn group(&self) -> Group {
+        self.membership.get()
+    }
+
+    /// Moves the device to another group, for the scanner as well.
+    ///
+    /// # Arguments
+    /// * `group` - The new group.
+    ///
+    /// # Errors
+    /// Returns an error if the advertising data cannot be configured.
+    pub fn set_group(&mut self, group: Group) -> Result<()> {
+        if group != self.group() {
+            self.membership.set(group);
+            self.apply()?;
+        }
 
         Ok(())
     }
@@ -87,6 +1586,73 @@ impl Switch for Advertiser<'_> {
     }
 }
 
+/// Represents a device seen by the scanner.
+///
+/// # Fields
+/// * `addr` - The BLE address of the device.
+/// * `trigger` - The trigger the device caused.
+/// * `last_seen` - When the device was last seen.
+#[derive(Clone)]
+pub struct Peer {
+    pub addr: String,
+    pub trigger: Trigger,
+    pub last_seen: Instant,
+}
+
+/// Represents the devices recently seen by the scanner, by address.
+pub struct Peers {
+    seen: Mutex<Lru<String, (Trigger, Instant)>>,
+}
+
+impl Peers {
+    /// Creates a new `Peers` instance.
+    ///
+    /// # Errors
+    /// Returns an error if the list cannot be initialized.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            seen: Mutex::new(Lru::new(MAX_PEERS)),
+        })
+    }
+
+    /// Records that a device was seen.
+    ///
+    /// # Arguments
+    /// * `addr` - The BLE address of the device.
+    /// * `trigger` - The trigger the device caused.
+    fn seen(&self, addr: String, trigger: Trigger) {
+        lock(&self.seen).insert(addr, (trigger, Instant::now()));
+    }
+
+    /// Returns the addresses of the devices recently seen.
+    ///
+    /// # Returns
+    /// The addresses, most significant byte first.
+    fn addresses(&self) -> Vec<[u8; 6]> {
+        lock(&self.seen)
+            .iter()
+            .filter_map(|(addr, _)| addr.parse().ok())
+            .map(Address::bytes)
+            .collect()
+    }
+
+    /// Returns the devices recently seen, least recently seen first.
+    ///
+    /// # Returns
+    /// Up to `MAX_PEERS` devices.
+    #[must_use]
+    pub fn list(&self) -> Vec<Peer> {
+        lock(&self.seen)
+            .iter()
+            .map(|(addr, (trigger, last_seen))| Peer {
+                addr: addr.clone(),
+                trigger: *trigger,
+                last_seen: *last_seen,
+            })
+            .collect()
+    }
+}
+
 /// Represents a BLE scanner.
 ///
 /// # Type Parameters
@@ -94,87 +1660,431 @@ impl Switch for Advertiser<'_> {
 pub struct Scanner<'a> {
     name: &'a str,
     notifier: Notifier,
+    dispatcher: Dispatcher,
     timer: Timer<'a>,
-    state: Arc<Mutex<button::State>>,
+    peers: Arc<Peers>,
+    membership: Arc<Membership>,
+    key: Option<Key>,
+    replay: Replay<[u8; 6]>,
+    mesh: Option<Arc<Mesh>>,
+    rhythm: Option<Arc<Rhythm>>,
+    leadership: Option<Arc<Leadership>>,
+    beacon: Option<(Uuid, Option<u16>)>,
+    sniffer: Option<Arc<Sniffer>>,
+    schedule: Schedule,
+    scanned: Option<Instant>,
+    enabled: bool,
+    config: ScanConfig,
+    filtered: bool,
     device: &'a BLEDevice,
     scan: BLEScan,
 }
 
 impl<'a> Scanner<'a> {
-    const WINDOW: i32 = 1000;
-
     /// Creates a new `Scanner` instance.
     ///
     /// # Arguments
     /// * `name` - The name of the scanner.
     /// * `notifier` - A notifier to send scan results.
+    /// * `dispatcher` - A dispatcher subscribed to the system on/off and scan
+    ///   request triggers.
     /// * `timer` - A timer for scan intervals.
-    /// * `state` - Shared state of the scanner.
+    /// * `peers` - The list the devices found are recorded in.
+    /// * `config` - The scan parameters.
+    /// * `membership` - The group of the device, shared with the advertiser.
     ///
     /// # Errors
     /// Returns an error if the scanner cannot be initialized.
     pub fn new(
         name: &'a str,
         notifier: Notifier,
+        dispatcher: Dispatcher,
         timer: Timer<'a>,
-        state: Arc<Mutex<button::State>>,
+        peers: Arc<Peers>,
+        config: &ScanConfig,
+        membership: Arc<Membership>,
     ) -> Result<Self> {
+        config.validate()?;
         let device = BLEDevice::take();
-        let scan = BLEScan::new();
+        let mut scan = BLEScan::new();
+        config.apply(&mut scan)?;
 
         Ok(Self {
             name,
             notifier,
+            dispatcher,
             timer,
-            state,
+            peers,
+            membership,
+            key: None,
+            replay: Replay::new(FRESH, MAX_PEERS),
+            mesh: None,
+            rhythm: None,
+            leadership: None,
+            beacon: None,
+            sniffer: None,
+            schedule: Schedule::new(config.policy),
+            scanned: None,
+            enabled: false,
+            config: config.clone(),
+            filtered: true,
             device,
             scan,
         })
     }
 
+    /// Only accepts the advertisements authenticated with a key shared by the
+    /// devices.
+    ///
+    /// # Arguments
+    /// * `key` - The key shared by the devices.
+    ///
+    /// # Returns
+    /// The scanner, ignoring the devices without the key.
+    #[must_use]
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Receives the mesh messages, and keeps scanning while the system is off
+    /// to obey them.
+    ///
+    /// # Arguments
+    /// * `mesh` - The messages flowing through the device, shared with the
+    ///   advertiser.
+    ///
+    /// # Returns
+    /// The scanner, taking part in the mesh.
+    #[must_use]
+    pub fn with_mesh(mut self, mesh: Arc<Mesh>) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    /// Follows the beats of the devices of the group, to blink in unison.
+    ///
+    /// Scans then last their whole window, rather than stopping at the first
+    /// device found, so as to hear the beats.
+    ///
+    /// # Arguments
+    /// * `rhythm` - The blinking rhythm of the device, shared with the state
+    ///   machine.
+    ///
+    /// # Returns
+    /// The scanner, synchronising the blinking.
+    #[must_use]
+    pub fn with_rhythm(mut self, rhythm: Arc<Rhythm>) -> Self {
+        self.rhythm = Some(rhythm);
+        self
+    }
+
+    /// Takes part in the election of the coordinator of the group.
+    ///
+    /// Scans then last their whole window, rather than stopping at the first
+    /// device found, so as to hear the heartbeats of all the peers.
+    ///
+    /// # Arguments
+    /// * `leadership` - The election, shared with the state machine.
+    ///
+    /// # Returns
+    /// The scanner, electing a coordinator.
+    #[must_use]
+    pub fn with_leadership(mut self, leadership: Arc<Leadership>) -> Self {
+        self.leadership = Some(leadership);
+        self
+    }
+
+    /// Recognizes the third-party iBeacon and Eddystone-UID beacons of a
+    /// deployment, reported as `Trigger::BeaconFound` when no peer is found.
+    ///
+    /// The beacon frames of the devices themselves are not third-party
+    /// beacons, see `Beacon::is_own`. Beacons cannot be authenticated, hence
+    /// they are ignored if the scanner has a key.
+    ///
+    /// # Arguments
+    /// * `uuid` - The UUID of the deployment, or whose namespace the Eddystone
+    ///   beacons have.
+    /// * `major` - The major number of the iBeacon frames of the devices, if
+    ///   they send any.
+    ///
+    /// # Returns
+    /// The scanner, recognizing the beacons.
+    #[must_use]
+    pub fn with_beacon(mut self, uuid: Uuid, major: Option<u16>) -> Self {
+        self.beacon = Some((uuid, major));
+        self
+    }
+
+    /// Reports every advertisement heard to the sniffer while it captures.
+    ///
+    /// # Arguments
+    /// * `sniffer` - The sniffer, shared with the console.
+    ///
+    /// # Returns
+    /// The scanner, feeding the sniffer.
+    #[must_use]
+    pub fn with_sniffer(mut self, sniffer: Arc<Sniffer>) -> Self {
+        self.sniffer = Some(sniffer);
+        self
+    }
+
+    /// Returns whether the sniffer captures.
+    ///
+    /// # Returns
+    /// `true` if a sniffer is set and started.
+    fn sniffing(&self) -> bool {
+        self.sniffer
+            .as_ref()
+            .is_some_and(|sniffer| sniffer.active())
+    }
+
+    /// Lifts the whitelist and the duplicate filter while the sniffer
+    /// captures, so that it hears every advertisement, and applies the scan
+    /// parameters again once it stops.
+    ///
+    /// # Errors
+    /// Returns an error if the scan cannot be configured.
+    fn refilter(&mut self) -> Result<()> {
+        let sniffing = self.sniffing();
+        if self.filtered != sniffing {
+            return Ok(());
+        }
+        if sniffing {
+            ScanConfig {
+                filter_duplicates: false,
+                whitelist: Vec::new(),
+                ..self.config.clone()
+            }
+            .apply(&mut self.scan)?;
+        } else {
+            self.config.apply(&mut self.scan)?;
+        }
+        self.filtered = !sniffing;
+
+        Ok(())
+    }
+
+    /// Returns whether the scans last their whole window, to hear all the
+    /// peers.
+    ///
+    /// # Returns
+    /// `true` if the blinking is synchronised, a coordinator is elected or the
+    /// sniffer captures.
+    fn listens(&self) -> bool {
+        self.rhythm.is_some() || self.leadership.is_some() || self.sniffing()
+    }
+
+    /// Updates whether scanning is enabled from the pending system triggers.
+    ///
+    /// A system state change makes the scanner scan right away and
+    /// aggressively again.
+    ///
+    /// # Returns
+    /// `true` if an immediate scan was requested, `false` otherwise.
+    ///
+    /// # Errors
+    /// Returns an error if the triggers cannot be collected.
+    fn update_enabled(&mut self) -> Result<bool> {
+        let triggers = self.dispatcher.try_collect()?;
+        if triggers.contains(Trigger::SystemOff) {
+            self.enabled = false;
+        } else if triggers.contains(Trigger::SystemOn) {
+            self.enabled = true;
+        }
+        if triggers.intersects(Trigger::SystemOn | Trigger::SystemOff) {
+            self.schedule.reset();
+            self.scanned = None;
+        }
+
+        Ok(triggers.contains(Trigger::ScanRequested))
+    }
+
+    /// Returns whether the schedule calls for a scan.
+    ///
+    /// # Returns
+    /// `true` if the sniffer captures, or if the scanner is enabled, or
+    /// listens to the mesh, and the interval since the last scan elapsed.
+    fn due(&self) -> bool {
+        self.sniffing()
+            || ((self.enabled || self.mesh.is_some())
+                && !self.scanned.is_some_and(|scanned| {
+                    scanned.elapsed() < self.schedule.interval()
+                }))
+    }
+
     /// Performs a BLE scan.
     ///
+    /// Mesh messages, beats and heartbeats are handled as they are heard, and
+    /// the last command received is notified once the scan is over. While the
+    /// sniffer captures, every advertisement is reported to it, unfiltered,
+    /// and the scan lasts `SNIFF_WINDOW`.
+    ///
     /// # Errors
-    /// Returns an error if the scan fails.
+    /// Returns an error if the scan or the notification fails.
     async fn do_scan(&mut self) -> Result<Option<Trigger>> {
-        Ok(self
+        self.refilter()?;
+        let window = if self.sniffing() {
+            SNIFF_WINDOW
+        } else {
+            self.schedule.window()
+        };
+        let window = i32::try_from(window.as_millis()).unwrap_or(i32::MAX);
+        let group = self.membership.get();
+        let listens = self.listens();
+        let mut command = None;
+        let mut first = None;
+        let mut sighted = None;
+
+        let found = self
             .scan
-            .start(self.device, Self::WINDOW, |_, data| {
-                data.name().and_then(|name| {
-                    if name == format!("{}-Active", self.name) {
-                        Some(Trigger::DeviceFoundActive)
-                    } else if name == format!("{}-Inactive", self.name) {
-                        Some(Trigger::DeviceFoundInactive)
-                    } else {
-                        None
+            .start(self.device, window, |device, data| {
+                if let Some(sniffer) = self.sniffer.as_ref() {
+                    sniffer.heard(device, &data);
+                }
+
+                let frame = data
+                    .manufacture_data()
+                    .filter(|data| data.company_identifier == COMPANY_ID)
+                    .map(|data| data.payload);
+                if let Some(message) = frame.and_then(|frame| {
+                    Message::decode(self.key.as_ref(), self.name, frame)
+                }) {
+                    if let Some(mesh) =
+                        self.mesh.as_ref().filter(|_| message.group == group)
+                    {
+                        command = mesh.receive(message).or(command);
                     }
-                })
+                    return None;
+                }
+                // Beacons only count if no peer is found during the window.
+                let deployment = self.beacon.filter(|_| self.key.is_none());
+                if deployment.is_some_and(|(uuid, major)| {
+                    recognize(&data).is_some_and(|beacon| {
+                        let mut devices = self.peers.addresses();
+                        devices.extend(
+                            device
+                                .addr()
+                                .to_string()
+                                .parse::<Address>()
+                                .ok()
+                                .map(Address::bytes),
+                        );
+                        beacon.belongs(uuid) && !beacon.is_own(&devices, major)
+                    })
+                }) {
+                    if sighted.is_none() {
+                        debug!(peer:% = device.addr(); "beacon found");
+                    }
+                    sighted = sighted.or_else(|| Some(device.addr().to_string()));
+                    return None;
+                }
+
+                // Authenticated peers are known by their permanent address,
+                // even behind an ephemeral one.
+                let found = if let Some(key) = self.key.as_ref() {
+                    frame
+                        .and_then(|frame| {
+                            authenticate(
+                                key,
+                                self.name,
+                                group,
+                                &mut self.replay,
+                                frame,
+                            )
+                        })
+                        .map(|(trigger, addr, beat)| {
+                            if let (Some(rhythm), Some(beat)) =
+                                (self.rhythm.as_ref(), beat)
+                            {
+                                rhythm.heard(addr, beat);
+                            }
+                            (trigger, addr.to_string())
+                        })
+                } else {
+                    data.name()
+                        .and_then(|name| {
+                            if name == advertised_name(self.name, group, true) {
+                                Some(Trigger::DeviceFoundActive)
+                            } else if name
+                                == advertised_name(self.name, group, false)
+                            {
+                                Some(Trigger::DeviceFoundInactive)
+                            } else {
+                                None
+                            }
+                        })
+                        .map(|trigger| (trigger, device.addr().to_string()))
+                };
+                let (trigger, addr) = found?;
+                debug!(peer:% = addr, trigger:? = trigger; "device found");
+                if let (Some(leadership), Ok(peer)) =
+                    (self.leadership.as_ref(), addr.parse())
+                {
+                    leadership.heard(peer);
+                }
+                self.peers.seen(addr, trigger);
+
+                // Keep hearing the peers until the end of the window.
+                if listens {
+                    first = first.or(Some(trigger));
+                    return None;
+                }
+                Some(trigger)
             })
-            .await?)
+            .await?;
+
+        if let Some(command) = command {
+            self.notifier.notify(match command {
+                Command::Off => Trigger::MeshOff,
+                Command::On => Trigger::MeshOn,
+            })?;
+        }
+
+        Ok(found.or(first).or_else(|| {
+            sighted.map(|addr| {
+                self.peers.seen(addr, Trigger::BeaconFound);
+                Trigger::BeaconFound
+            })
+        }))
     }
 }
 
 impl Poller for Scanner<'_> {
     /// Polls the BLE scanner for devices.
     ///
-    /// This function continuously scans for BLE devices and notifies the results.
+    /// This function scans for BLE devices following the schedule and notifies
+    /// the results.
     ///
     /// # Errors
-    /// Returns an error if the scan or notification fails.
+    /// Returns an error if the scan, the notification or the watchdog fails.
     fn poll(&mut self) -> Result<!> {
         block_on(async {
             loop {
+                watchdog::feed()?;
                 self.timer.delay(SCAN_FREQ).await?;
 
-                if let button::State::Off = *self
-                    .state
-                    .lock()
-                    .map_err(|e| anyhow!("Mutex lock error: {:?}", e))?
-                {
+                // A requested scan also runs while the system is off.
+                let requested = self.update_enabled()?;
+                if !requested && !self.due() {
                     continue;
                 }
 
-                let trigger = if let Some(trigger) = self.do_scan().await? {
+                let start = Instant::now();
+                let found = self.do_scan().await?;
+                self.scanned = Some(Instant::now());
+                self.schedule.record(found.is_some());
+                metrics::SCANS.inc();
+                metrics::SCAN_INTERVAL.set(
+                    u32::try_from(self.schedule.interval().as_millis())
+                        .unwrap_or(u32::MAX),
+                );
+                metrics::SCAN_DURATION.observe(
+                    u32::try_from(start.elapsed().as_millis()).unwrap_or(u32::MAX),
+                );
+
+                let trigger = if let Some(trigger) = found {
+                    metrics::SCAN_HITS.inc();
                     trigger
                 } else {
                     Trigger::DeviceNotFound
@@ -1,25 +1,14 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use esp_idf_hal::gpio::{InputMode, InputPin, PinDriver};
-use std::sync::{Arc, Mutex};
 
 use crate::{
     infra::Poller,
     message::{Notifier, Trigger},
-    time::{sleep, yield_now},
+    metrics,
+    time::sleep,
+    watchdog,
 };
 
-/// Represents the state of a button.
-///
-/// The button can either be `On` or `Off`.
-///
-/// # Variants
-/// * `On` - The button is in the "on" state.
-/// * `Off` - The button is in the "off" state.
-pub enum State {
-    On,
-    Off,
-}
-
 /// Represents a button with a notifier and a GPIO pin.
 ///
 /// # Type Parameters
@@ -33,7 +22,6 @@ where
 {
     notifier: Notifier,
     pin: PinDriver<'a, T, MODE>,
-    state: Arc<Mutex<State>>,
 }
 
 impl<'a, T, MODE> Button<'a, T, MODE>
@@ -46,22 +34,22 @@ where
     /// # Arguments
     /// * `notifier` - A notifier to send button press events.
     /// * `pin` - A GPIO pin driver.
-    /// * `state` - Shared state of the button.
     ///
     /// # Errors
     /// Returns an error if the button cannot be initialized.
-    pub fn new(
-        notifier: Notifier,
-        pin: PinDriver<'a, T, MODE>,
-        state: Arc<Mutex<State>>,
-    ) -> Result<Self> {
-        Ok(Self {
-            notifier,
-            pin,
-            state,
-        })
+    pub fn new(notifier: Notifier, pin: PinDriver<'a, T, MODE>) -> Result<Self> {
+        Ok(Self { notifier, pin })
     }
 
+    /// Period of the button polling, long enough to let the chip light sleep
+    /// in between.
+    const POLL_PERIOD: u32 = 50;
+    /// Duration the button has to be held down for to be reported as held, in
+    /// milliseconds.
+    const HOLD_TIME: u32 = 1500;
+    /// Duration the button is ignored for after a release, in milliseconds.
+    const DEBOUNCE: u32 = 200;
+
     /// Checks if the button is pressed.
     ///
     /// # Returns
@@ -70,20 +58,39 @@ where
         self.pin.is_low()
     }
 
-    /// Toggles the state of the button.
+    /// Waits for the button to be released, for up to `HOLD_TIME`.
+    ///
+    /// # Returns
+    /// `true` if the button is still pressed after `HOLD_TIME`, `false` if it
+    /// was released before.
     ///
     /// # Errors
-    /// Returns an error if the mutex lock cannot be acquired.
-    fn toggle_state(&self) -> Result<()> {
-        let mut state = self
-            .state
-            .lock()
-            .map_err(|e| anyhow!("Mutex lock error: {:?}", e))?;
+    /// Returns an error if the watchdog fails.
+    fn held(&self) -> Result<bool> {
+        let mut elapsed = 0;
+        while self.pressed() {
+            if elapsed >= Self::HOLD_TIME {
+                return Ok(true);
+            }
+            watchdog::feed()?;
+            sleep(Self::POLL_PERIOD);
+            elapsed += Self::POLL_PERIOD;
+        }
+
+        Ok(false)
+    }
 
-        *state = match *state {
-            State::On => State::Off,
-            State::Off => State::On,
-        };
+    /// Waits for the button to be released, e.g. after the press that woke the
+    /// device up, so that the press is not reported twice.
+    ///
+    /// # Errors
+    /// Returns an error if the watchdog fails.
+    pub fn settle(&self) -> Result<()> {
+        while self.pressed() {
+            watchdog::feed()?;
+            sleep(Self::POLL_PERIOD);
+        }
+        sleep(Self::DEBOUNCE);
 
         Ok(())
     }
@@ -96,22 +103,36 @@ where
 {
     /// Polls the button for state changes.
     ///
-    /// This function continuously checks the button state and notifies when it is pressed.
+    /// This function continuously checks the button state and notifies when it
+    /// is pressed, on release, or when it is held down.
     ///
     /// # Errors
-    /// Returns an error if the notifier fails or if the state cannot be toggled.
+    /// Returns an error if the notifier or the watchdog fails.
     fn poll(&mut self) -> Result<!> {
         // Using polling instead of interrupts for the button as on some boards
         // (e.g. M5Stack's Atom Lite) the interrupt pin of the button is too close
         // to the WiFi antenna which causes interference.
 
         loop {
+            watchdog::feed()?;
+
             if self.pressed() {
-                self.notifier.notify(Trigger::ButtonPressed)?;
-                self.toggle_state()?;
-                sleep(500);
+                metrics::BUTTON_PRESSES.inc();
+                let trigger = if self.held()? {
+                    Trigger::ButtonHeld
+                } else {
+                    Trigger::ButtonPressed
+                };
+                self.notifier.notify(trigger)?;
+
+                // Report a hold once, however long it lasts.
+                while self.pressed() {
+                    watchdog::feed()?;
+                    sleep(Self::POLL_PERIOD);
+                }
+                sleep(Self::DEBOUNCE);
             }
-            yield_now();
+            sleep(Self::POLL_PERIOD);
         }
     }
 }
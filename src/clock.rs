@@ -1,5 +1,6 @@
 use anyhow::Result;
 use esp_idf_hal::timer::TimerDriver;
+use std::time::Duration;
 
 use crate::{
     message::{Notifier, Trigger},
@@ -12,6 +13,8 @@ use crate::{
 /// * `'a` - Lifetime of the timer.
 pub struct Timer<'a> {
     timer: TimerDriver<'a>,
+    period: u64,
+    aligned: bool,
 }
 
 impl<'a> Timer<'a> {
@@ -23,7 +26,11 @@ impl<'a> Timer<'a> {
     /// # Errors
     /// Returns an error if the timer cannot be initialized.
     pub fn new(timer: TimerDriver<'a>) -> Result<Self> {
-        Ok(Self { timer })
+        Ok(Self {
+            timer,
+            period: 0,
+            aligned: false,
+        })
     }
 
     /// Configures the timer interrupt.
@@ -47,12 +54,52 @@ impl<'a> Timer<'a> {
             })?;
         }
 
-        self.timer.set_alarm(self.timer.tick_hz() / freq)?;
+        self.period = self.timer.tick_hz() / freq;
+        self.timer.set_alarm(self.period)?;
         self.timer.enable_interrupt()?;
 
         Ok(())
     }
 
+    /// Moves the next timer interrupt, e.g. to align it with a rhythm.
+    ///
+    /// The alarm only applies to the next interrupt: `resume` restores the
+    /// period of `configure_interrupt` once it fired.
+    ///
+    /// # Arguments
+    /// * `next` - Duration until the next timer interrupt.
+    ///
+    /// # Errors
+    /// Returns an error if the timer cannot be set.
+    pub fn align(&mut self, next: Duration) -> Result<()> {
+        let ticks = next.as_micros() * u128::from(self.timer.tick_hz()) / 1_000_000;
+
+        self.timer.set_counter(0)?;
+        self.timer
+            .set_alarm(u64::try_from(ticks).unwrap_or(u64::MAX).max(1))?;
+        self.aligned = true;
+
+        Ok(())
+    }
+
+    /// Restores the period of the timer interrupt, if the last interrupt was
+    /// moved by `align`.
+    ///
+    /// It has to be called on every timer interrupt, as the counter restarts
+    /// from zero when the alarm fires and the aligned alarm would repeat
+    /// otherwise.
+    ///
+    /// # Errors
+    /// Returns an error if the timer cannot be set.
+    pub fn resume(&mut self) -> Result<()> {
+        if self.aligned {
+            self.timer.set_alarm(self.period)?;
+            self.aligned = false;
+        }
+
+        Ok(())
+    }
+
     /// Enables or disables the timer.
     ///
     /// # Arguments
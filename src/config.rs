@@ -0,0 +1,782 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::warn;
+use std::{ops::RangeInclusive, time::Duration};
+
+use crate::{
+    auth::Key,
+    beacon::{BeaconConfig, Frame, Uuid},
+    ble::{Address, AdvertisingConfig, ScanConfig},
+    group::Group,
+    mesh::MeshConfig,
+};
+
+/// Maximum length of the device name, so that it fits in an advertisement
+/// along with its state suffix.
+const MAX_NAME_LEN: usize = 16;
+
+/// Maximum length of the name of a scan policy.
+const MAX_POLICY_LEN: usize = 16;
+
+/// Maximum length of the name of a mesh role.
+const MAX_ROLE_LEN: usize = 8;
+
+/// Maximum length of the list of beacon frames, e.g. `ibeacon,uid,url,tlm`.
+const MAX_FRAMES_LEN: usize = 24;
+
+/// Maximum length of a beacon URL, bounded by its encoding.
+const MAX_URL_LEN: usize = 128;
+
+/// Minimum duration of the deep sleep settings, so that the device stays
+/// reachable from the serial console.
+const MIN_SLEEP: Duration = Duration::from_secs(60);
+
+/// GPIO numbers of the ADC1 pins, the only ones the battery can be monitored
+/// on.
+const ADC1_PINS: RangeInclusive<i32> = 32..=39;
+
+/// GPIO number of the button, an ADC1 pin the battery cannot be monitored on.
+const BUTTON_PIN: i32 = 39;
+
+/// Range of the battery divider ratio, in per mille.
+const BATTERY_SCALES: RangeInclusive<u32> = 1000..=10_000;
+
+/// Formats an optional duration in seconds.
+///
+/// # Arguments
+/// * `duration` - The duration, `None` if disabled.
+///
+/// # Returns
+/// The number of seconds, or `none`.
+fn format_secs(duration: Option<Duration>) -> String {
+    duration.map_or_else(|| "none".into(), |duration| duration.as_secs().to_string())
+}
+
+/// Parses an optional duration in seconds.
+///
+/// # Arguments
+/// * `value` - The number of seconds, or `none`.
+///
+/// # Errors
+/// Returns an error if the value is not a number of seconds.
+fn parse_secs(value: &str) -> Result<Option<Duration>> {
+    match value {
+        "none" => Ok(None),
+        _ => Ok(Some(Duration::from_secs(value.parse()?))),
+    }
+}
+
+/// Formats an advertising channel mask.
+///
+/// # Arguments
+/// * `channels` - The mask, bit 0 for channel 37.
+///
+/// # Returns
+/// The channel numbers separated by commas, e.g. `37,39`.
+fn format_channels(channels: u8) -> String {
+    (0..3)
+        .filter(|bit| channels & (1 << bit) != 0)
+        .map(|bit| (AdvertisingConfig::FIRST_CHANNEL + bit).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses an advertising channel mask.
+///
+/// # Arguments
+/// * `value` - The channel numbers separated by commas, e.g. `37,39`.
+///
+/// # Errors
+/// Returns an error if a channel is not an advertising channel.
+fn parse_channels(value: &str) -> Result<u8> {
+    value
+        .split(',')
+        .try_fold(0, |channels, channel| -> Result<u8> {
+            let bit = channel
+                .trim()
+                .parse::<u8>()?
+                .checked_sub(AdvertisingConfig::FIRST_CHANNEL)
+                .filter(|bit| *bit < 3)
+                .ok_or_else(|| anyhow!("Not an advertising channel: {}", channel))?;
+
+            Ok(channels | (1 << bit))
+        })
+}
+
+/// Formats a list of addresses.
+///
+/// # Arguments
+/// * `addrs` - The addresses.
+///
+/// # Returns
+/// The addresses separated by commas, or `none` if there is none.
+fn format_addrs(addrs: &[Address]) -> String {
+    if addrs.is_empty() {
+        return "none".into();
+    }
+
+    addrs
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a list of addresses.
+///
+/// # Arguments
+/// * `value` - The addresses separated by commas, or `none`.
+///
+/// # Errors
+/// Returns an error if an address is invalid.
+fn parse_addrs(value: &str) -> Result<Vec<Address>> {
+    match value {
+        "none" => Ok(Vec::new()),
+        _ => value.split(',').map(|addr| addr.trim().parse()).collect(),
+    }
+}
+
+/// Formats a list of beacon frames.
+///
+/// # Arguments
+/// * `frames` - The frames.
+///
+/// # Returns
+/// The frames separated by commas, or `none` if there is none.
+fn format_frames(frames: &[Frame]) -> String {
+    if frames.is_empty() {
+        return "none".into();
+    }
+
+    frames
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a list of beacon frames.
+///
+/// # Arguments
+/// * `value` - The frames separated by commas, or `none`.
+///
+/// # Errors
+/// Returns an error if a frame is unknown.
+fn parse_frames(value: &str) -> Result<Vec<Frame>> {
+    match value {
+        "none" => Ok(Vec::new()),
+        _ => value.split(',').map(|frame| frame.trim().parse()).collect(),
+    }
+}
+
+/// Converts an optional duration to the number of seconds stored in NVS.
+///
+/// # Arguments
+/// * `duration` - The duration, `None` if disabled.
+///
+/// # Returns
+/// The number of seconds, saturated, or 0 if disabled.
+fn to_secs(duration: Option<Duration>) -> u32 {
+    duration.map_or(0, |duration| {
+        u32::try_from(duration.as_secs()).unwrap_or(u32::MAX)
+    })
+}
+
+/// Represents the persistent configuration of the device.
+///
+/// # Fields
+/// * `name` - The name shared by the devices that react to each other.
+/// * `group` - The group of the device, among the devices sharing the name.
+/// * `sleep_after` - Duration the system has to be off for before the device
+///   deep sleeps, if ever.
+/// * `wake_every` - Duration after which a deep sleep ends on its own, if any.
+/// * `battery_pin` - The GPIO number the battery divider is connected to, if
+///   any.
+/// * `battery_scale` - Ratio of the battery divider in per mille, i.e. the
+///   battery voltage over the pin voltage times 1000.
+/// * `scan` - The BLE scan parameters.
+/// * `advertising` - The BLE advertising parameters.
+/// * `auth_key` - The key shared by the group to authenticate the
+///   advertisements, if any.
+/// * `mesh` - The mesh parameters.
+/// * `sync` - Whether the device blinks in unison with the devices of its
+///   group, which requires an `auth_key`.
+/// * `election` - Whether the devices of the group elect a coordinator.
+/// * `beacon` - The standard beacon parameters.
+pub struct Config {
+    pub name: String,
+    pub group: Group,
+    pub sleep_after: Option<Duration>,
+    pub wake_every: Option<Duration>,
+    pub battery_pin: Option<i32>,
+    pub battery_scale: u32,
+    pub scan: ScanConfig,
+    pub advertising: AdvertisingConfig,
+    pub auth_key: Option<Key>,
+    pub mesh: MeshConfig,
+    pub sync: bool,
+    pub election: bool,
+    pub beacon: BeaconConfig,
+}
+
+impl Default for Config {
+    /// Returns the default configuration.
+    fn default() -> Self {
+        Self {
+            name: "ESPlayground".into(),
+            group: Group::default(),
+            sleep_after: Some(Duration::from_secs(30 * 60)),
+            wake_every: None,
+            battery_pin: None,
+            battery_scale: 2000,
+            scan: ScanConfig::default(),
+            advertising: AdvertisingConfig::default(),
+            auth_key: None,
+            mesh: MeshConfig::default(),
+            sync: false,
+            election: false,
+            beacon: BeaconConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Names of the settings, as used by `get` and `set`.
+    pub const KEYS: [&'static str; 27] = [
+        "name",
+        "group",
+        "sleep_after",
+        "wake_every",
+        "battery_pin",
+        "battery_scale",
+        "scan_policy",
+        "scan_active",
+        "scan_interval",
+        "scan_window",
+        "scan_dedup",
+        "whitelist",
+        "adv_interval",
+        "tx_power",
+        "adv_channels",
+        "adv_scan_rsp",
+        "adv_privacy",
+        "auth_key",
+        "mesh_role",
+        "mesh_ttl",
+        "sync",
+        "election",
+        "beacons",
+        "beacon_uuid",
+        "beacon_major",
+        "beacon_minor",
+        "beacon_url",
+    ];
+
+    /// Returns a setting as a string.
+    ///
+    /// # Arguments
+    /// * `key` - The name of the setting.
+    ///
+    /// # Errors
+    /// Returns an error if the setting does not exist.
+    pub fn get(&self, key: &str) -> Result<String> {
+        match key {
+            "name" => Ok(self.name.clone()),
+            "group" => Ok(self.group.to_string()),
+            "sleep_after" => Ok(format_secs(self.sleep_after)),
+            "wake_every" => Ok(format_secs(self.wake_every)),
+            "battery_pin" => Ok(self
+                .battery_pin
+                .map_or_else(|| "none".into(), |pin| pin.to_string())),
+            "battery_scale" => Ok(self.battery_scale.to_string()),
+            "scan_policy" => Ok(self.scan.policy.to_string()),
+            "scan_active" => Ok(self.scan.active.to_string()),
+            "scan_interval" => Ok(self.scan.interval.as_millis().to_string()),
+            "scan_window" => Ok(self.scan.window.as_millis().to_string()),
+            "scan_dedup" => Ok(self.scan.filter_duplicates.to_string()),
+            "whitelist" => Ok(format_addrs(&self.scan.whitelist)),
+            "adv_interval" => Ok(self.advertising.interval.as_millis().to_string()),
+            "tx_power" => Ok(self.advertising.tx_power.to_string()),
+            "adv_channels" => Ok(format_channels(self.advertising.channels)),
+            "adv_scan_rsp" => Ok(self.advertising.scan_response.to_string()),
+            "adv_privacy" => Ok(self.advertising.privacy.to_string()),
+            // The key is secret, only tell whether there is one.
+            "auth_key" => Ok(self
+                .auth_key
+                .map_or_else(|| "none".into(), |_| "hidden".into())),
+            "mesh_role" => Ok(self.mesh.role.to_string()),
+            "mesh_ttl" => Ok(self.mesh.ttl.to_string()),
+            "sync" => Ok(self.sync.to_string()),
+            "election" => Ok(self.election.to_string()),
+            "beacons" => Ok(format_frames(&self.beacon.frames)),
+            "beacon_uuid" => Ok(self
+                .beacon
+                .uuid
+                .map_or_else(|| "none".into(), |uuid| uuid.to_string())),
+            "beacon_major" => Ok(self.beacon.major.to_string()),
+            "beacon_minor" => Ok(self.beacon.minor.to_string()),
+            "beacon_url" => Ok(match self.beacon.url.as_str() {
+                "" => "none".into(),
+                url => url.into(),
+            }),
+            _ => Err(anyhow!("Unknown setting: {}", key)),
+        }
+    }
+
+    /// Sets a setting from a string.
+    ///
+    /// The configuration is not validated, see `validate`.
+    ///
+    /// # Arguments
+    /// * `key` - The name of the setting.
+    /// * `value` - The new value, `none` to disable an optional setting.
+    ///
+    /// # Errors
+    /// Returns an error if the setting does not exist or if the value cannot be
+    /// parsed.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "name" => self.name = value.into(),
+            "group" => self.group = value.parse()?,
+            "sleep_after" => self.sleep_after = parse_secs(value)?,
+            "wake_every" => self.wake_every = parse_secs(value)?,
+            "battery_pin" => {
+                self.battery_pin = match value {
+                    "none" => None,
+                    _ => Some(value.parse()?),
+                };
+            }
+            "battery_scale" => self.battery_scale = value.parse()?,
+            "scan_policy" => self.scan.policy = value.parse()?,
+            "scan_active" => self.scan.active = value.parse()?,
+            "scan_interval" => {
+                self.scan.interval = Duration::from_millis(value.parse()?);
+            }
+            "scan_window" => {
+                self.scan.window = Duration::from_millis(value.parse()?)
+            }
+            "scan_dedup" => self.scan.filter_duplicates = value.parse()?,
+            "whitelist" => self.scan.whitelist = parse_addrs(value)?,
+            "adv_interval" => {
+                self.advertising.interval = Duration::from_millis(value.parse()?);
+            }
+            "tx_power" => self.advertising.tx_power = value.parse()?,
+            "adv_channels" => self.advertising.channels = parse_channels(value)?,
+            "adv_scan_rsp" => self.advertising.scan_response = value.parse()?,
+            "adv_privacy" => self.advertising.privacy = value.parse()?,
+            "auth_key" => {
+                self.auth_key = match value {
+                    "none" => None,
+                    _ => Some(value.parse()?),
+                };
+            }
+            "mesh_role" => self.mesh.role = value.parse()?,
+            "mesh_ttl" => self.mesh.ttl = value.parse()?,
+            "sync" => self.sync = value.parse()?,
+            "election" => self.election = value.parse()?,
+            "beacons" => self.beacon.frames = parse_frames(value)?,
+            "beacon_uuid" => {
+                self.beacon.uuid = match value {
+                    "none" => None,
+                    _ => Some(value.parse()?),
+                };
+            }
+            "beacon_major" => self.beacon.major = value.parse()?,
+            "beacon_minor" => self.beacon.minor = value.parse()?,
+            "beacon_url" => {
+                self.beacon.url = match value {
+                    "none" => String::new(),
+                    _ => value.into(),
+                };
+            }
+            _ => Err(anyhow!("Unknown setting: {}", key))?,
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the configuration is usable.
+    ///
+    /// # Errors
+    /// Returns an error describing the first invalid setting.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty()
+            || self.name.len() > MAX_NAME_LEN
+            || !self.name.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(anyhow!("Invalid name: {:?}", self.name));
+        }
+        for (key, duration) in [
+            ("sleep_after", self.sleep_after),
+            ("wake_every", self.wake_every),
+        ] {
+            if duration.is_some_and(|duration| duration < MIN_SLEEP) {
+                return Err(anyhow!(
+                    "Invalid {}: below {}s",
+                    key,
+                    MIN_SLEEP.as_secs()
+                ));
+            }
+        }
+        if self
+            .battery_pin
+            .is_some_and(|pin| !ADC1_PINS.contains(&pin))
+        {
+            return Err(anyhow!("Invalid battery_pin: not an ADC1 pin"));
+        }
+        if self.battery_pin == Some(BUTTON_PIN) {
+            return Err(anyhow!("Invalid battery_pin: used by the button"));
+        }
+        if !BATTERY_SCALES.contains(&self.battery_scale) {
+            return Err(anyhow!(
+                "Invalid battery_scale: {}, expected {:?}",
+                self.battery_scale,
+                BATTERY_SCALES
+            ));
+        }
+        self.scan.validate()?;
+        self.advertising.validate()?;
+        if self.advertising.privacy && self.auth_key.is_none() {
+            return Err(anyhow!("Invalid adv_privacy: requires an auth_key"));
+        }
+        // Private peers advertise from ephemeral addresses, which a whitelist
+        // of their permanent addresses would filter out.
+        if self.advertising.privacy && !self.scan.whitelist.is_empty() {
+            return Err(anyhow!("Invalid whitelist: incompatible with adv_privacy"));
+        }
+        self.mesh.validate()?;
+        if self.sync && self.auth_key.is_none() {
+            return Err(anyhow!("Invalid sync: requires an auth_key"));
+        }
+        self.beacon.validate()?;
+        if self.beacon.url.len() > MAX_URL_LEN {
+            return Err(anyhow!("Invalid beacon_url: {:?}", self.beacon.url));
+        }
+        if format_frames(&self.beacon.frames).len() > MAX_FRAMES_LEN {
+            return Err(anyhow!("Invalid beacons: too many frames"));
+        }
+        // The beacons identify the device, whatever its ephemeral address.
+        if self.advertising.privacy && !self.beacon.frames.is_empty() {
+            return Err(anyhow!("Invalid beacons: incompatible with adv_privacy"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the storage of the configuration in NVS.
+pub struct Store {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Store {
+    /// NVS namespace of the configuration.
+    const NAMESPACE: &'static str = "config";
+    /// NVS key of the device name.
+    const NAME: &'static str = "name";
+    /// NVS key of the group.
+    const GROUP: &'static str = "group";
+    /// NVS key of the deep sleep delay, in seconds, 0 if disabled.
+    const SLEEP_AFTER: &'static str = "sleep_after";
+    /// NVS key of the deep sleep wakeup period, in seconds, 0 if disabled.
+    const WAKE_EVERY: &'static str = "wake_every";
+    /// NVS key of the battery pin, 0 if disabled.
+    const BATTERY_PIN: &'static str = "battery_pin";
+    /// NVS key of the battery divider ratio.
+    const BATTERY_SCALE: &'static str = "battery_scale";
+    /// NVS key of the scan policy.
+    const SCAN_POLICY: &'static str = "scan_policy";
+    /// NVS key of whether the scans are active.
+    const SCAN_ACTIVE: &'static str = "scan_active";
+    /// NVS key of the scan interval, in milliseconds.
+    const SCAN_INTERVAL: &'static str = "scan_interval";
+    /// NVS key of the scan window, in milliseconds.
+    const SCAN_WINDOW: &'static str = "scan_window";
+    /// NVS key of whether duplicate reports are filtered.
+    const SCAN_DEDUP: &'static str = "scan_dedup";
+    /// NVS key of the whitelisted addresses, 6 bytes each.
+    const WHITELIST: &'static str = "whitelist";
+    /// NVS key of the advertising interval, in milliseconds.
+    const ADV_INTERVAL: &'static str = "adv_interval";
+    /// NVS key of the transmit power, in dBm.
+    const TX_POWER: &'static str = "tx_power";
+    /// NVS key of the advertising channel mask.
+    const ADV_CHANNELS: &'static str = "adv_channels";
+    /// NVS key of whether scan requests are answered.
+    const ADV_SCAN_RSP: &'static str = "adv_scan_rsp";
+    /// NVS key of whether the advertisements are private.
+    const ADV_PRIVACY: &'static str = "adv_privacy";
+    /// NVS key of the key authenticating the advertisements.
+    const AUTH_KEY: &'static str = "auth_key";
+    /// NVS key of the mesh role.
+    const MESH_ROLE: &'static str = "mesh_role";
+    /// NVS key of the time to live of the mesh messages.
+    const MESH_TTL: &'static str = "mesh_ttl";
+    /// NVS key of whether the blinking is synchronised.
+    const SYNC: &'static str = "sync";
+    /// NVS key of whether a coordinator is elected.
+    const ELECTION: &'static str = "election";
+    /// NVS key of the beacon frames, separated by commas.
+    const BEACONS: &'static str = "beacons";
+    /// NVS key of the beacon UUID, 16 bytes.
+    const BEACON_UUID: &'static str = "beacon_uuid";
+    /// NVS key of the iBeacon major number.
+    const BEACON_MAJOR: &'static str = "beacon_major";
+    /// NVS key of the iBeacon minor number.
+    const BEACON_MINOR: &'static str = "beacon_minor";
+    /// NVS key of the Eddystone URL.
+    const BEACON_URL: &'static str = "beacon_url";
+
+    /// Creates a new `Store` instance.
+    ///
+    /// # Arguments
+    /// * `partition` - The default NVS partition.
+    ///
+    /// # Errors
+    /// Returns an error if the NVS namespace cannot be opened.
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, Self::NAMESPACE, true)?,
+        })
+    }
+
+    /// Loads the configuration, using defaults for missing settings.
+    ///
+    /// # Errors
+    /// Returns an error if NVS cannot be read or if the configuration is invalid.
+    pub fn load(&self) -> Result<Config> {
+        let mut config = Config::default();
+        let mut buf = [0; MAX_NAME_LEN + 1];
+
+        if let Some(name) = self.nvs.get_str(Self::NAME, &mut buf)? {
+            config.name = name.into();
+        }
+        if let Some(group) = self.nvs.get_u8(Self::GROUP)? {
+            config.group = Group::try_from(group)?;
+        }
+
+        if let Some(secs) = self.nvs.get_u32(Self::SLEEP_AFTER)? {
+            config.sleep_after =
+                (secs > 0).then(|| Duration::from_secs(secs.into()));
+        }
+        if let Some(secs) = self.nvs.get_u32(Self::WAKE_EVERY)? {
+            config.wake_every = (secs > 0).then(|| Duration::from_secs(secs.into()));
+        }
+        if let Some(pin) = self.nvs.get_u8(Self::BATTERY_PIN)? {
+            // The button pin saved by an earlier firmware is ignored, as it
+            // cannot be driven by both the button and the ADC.
+            if i32::from(pin) == BUTTON_PIN {
+                warn!(pin; "Ignoring battery_pin: used by the button");
+            } else {
+                config.battery_pin = (pin > 0).then(|| pin.into());
+            }
+        }
+        if let Some(scale) = self.nvs.get_u32(Self::BATTERY_SCALE)? {
+            config.battery_scale = scale;
+        }
+
+        let mut buf = [0; MAX_POLICY_LEN + 1];
+        if let Some(policy) = self.nvs.get_str(Self::SCAN_POLICY, &mut buf)? {
+            config.scan.policy = policy.parse()?;
+        }
+        if let Some(active) = self.nvs.get_u8(Self::SCAN_ACTIVE)? {
+            config.scan.active = active != 0;
+        }
+        if let Some(ms) = self.nvs.get_u32(Self::SCAN_INTERVAL)? {
+            config.scan.interval = Duration::from_millis(ms.into());
+        }
+        if let Some(ms) = self.nvs.get_u32(Self::SCAN_WINDOW)? {
+            config.scan.window = Duration::from_millis(ms.into());
+        }
+        if let Some(dedup) = self.nvs.get_u8(Self::SCAN_DEDUP)? {
+            config.scan.filter_duplicates = dedup != 0;
+        }
+        let mut buf = [0; ScanConfig::MAX_WHITELIST * 6];
+        if let Some(whitelist) = self.nvs.get_blob(Self::WHITELIST, &mut buf)? {
+            config.scan.whitelist = whitelist
+                .chunks_exact(6)
+                .map(|chunk| Ok(Address::from(<[u8; 6]>::try_from(chunk)?)))
+                .collect::<Result<_>>()?;
+        }
+
+        if let Some(ms) = self.nvs.get_u32(Self::ADV_INTERVAL)? {
+            // A faster interval saved by an earlier firmware is raised to the
+            // minimum rather than failing, so that the device starts.
+            let [min, _] = AdvertisingConfig::INTERVALS;
+            let interval = Duration::from_millis(ms.into());
+            if interval < min {
+                warn!(ms; "Raising adv_interval to the minimum");
+            }
+            config.advertising.interval = interval.max(min);
+        }
+        if let Some(tx_power) = self.nvs.get_i8(Self::TX_POWER)? {
+            config.advertising.tx_power = tx_power;
+        }
+        if let Some(channels) = self.nvs.get_u8(Self::ADV_CHANNELS)? {
+            config.advertising.channels = channels;
+        }
+        if let Some(scan_response) = self.nvs.get_u8(Self::ADV_SCAN_RSP)? {
+            config.advertising.scan_response = scan_response != 0;
+        }
+        if let Some(privacy) = self.nvs.get_u8(Self::ADV_PRIVACY)? {
+            config.advertising.privacy = privacy != 0;
+        }
+
+        let mut buf = [0; Key::LEN];
+        if let Some(key) = self.nvs.get_blob(Self::AUTH_KEY, &mut buf)? {
+            config.auth_key = Some(Key::from(<[u8; Key::LEN]>::try_from(key)?));
+        }
+
+        let mut buf = [0; MAX_ROLE_LEN + 1];
+        if let Some(role) = self.nvs.get_str(Self::MESH_ROLE, &mut buf)? {
+            config.mesh.role = role.parse()?;
+        }
+        if let Some(ttl) = self.nvs.get_u8(Self::MESH_TTL)? {
+            config.mesh.ttl = ttl;
+        }
+        if let Some(sync) = self.nvs.get_u8(Self::SYNC)? {
+            config.sync = sync != 0;
+        }
+        if let Some(election) = self.nvs.get_u8(Self::ELECTION)? {
+            config.election = election != 0;
+        }
+
+        let mut buf = [0; MAX_FRAMES_LEN + 1];
+        if let Some(frames) = self.nvs.get_str(Self::BEACONS, &mut buf)? {
+            config.beacon.frames = parse_frames(frames)?;
+        }
+        let mut buf = [0; 16];
+        if let Some(uuid) = self.nvs.get_blob(Self::BEACON_UUID, &mut buf)? {
+            config.beacon.uuid = Some(Uuid::from(<[u8; 16]>::try_from(uuid)?));
+        }
+        if let Some(major) = self.nvs.get_u16(Self::BEACON_MAJOR)? {
+            config.beacon.major = major;
+        }
+        if let Some(minor) = self.nvs.get_u16(Self::BEACON_MINOR)? {
+            config.beacon.minor = minor;
+        }
+        let mut buf = [0; MAX_URL_LEN + 1];
+        if let Some(url) = self.nvs.get_str(Self::BEACON_URL, &mut buf)? {
+            config.beacon.url = url.into();
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Saves the configuration.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration to save.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration is invalid or if NVS cannot be
+    /// written.
+    pub fn save(&mut self, config: &Config) -> Result<()> {
+        config.validate()?;
+        self.nvs.set_str(Self::NAME, &config.name)?;
+        self.nvs.set_u8(Self::GROUP, config.group.id())?;
+        self.nvs
+            .set_u32(Self::SLEEP_AFTER, to_secs(config.sleep_after))?;
+        self.nvs
+            .set_u32(Self::WAKE_EVERY, to_secs(config.wake_every))?;
+        // Validated as an ADC1 pin, hence neither 0 nor out of range.
+        let pin = config.battery_pin.and_then(|pin| u8::try_from(pin).ok());
+        self.nvs.set_u8(Self::BATTERY_PIN, pin.unwrap_or(0))?;
+        self.nvs
+            .set_u32(Self::BATTERY_SCALE, config.battery_scale)?;
+        self.nvs
+            .set_str(Self::SCAN_POLICY, &config.scan.policy.to_string())?;
+        let scan = &config.scan;
+        // Validated, hence within 10.24 s.
+        let interval = u32::try_from(scan.interval.as_millis())?;
+        let window = u32::try_from(scan.window.as_millis())?;
+        self.nvs.set_u8(Self::SCAN_ACTIVE, scan.active.into())?;
+        self.nvs.set_u32(Self::SCAN_INTERVAL, interval)?;
+        self.nvs.set_u32(Self::SCAN_WINDOW, window)?;
+        self.nvs
+            .set_u8(Self::SCAN_DEDUP, scan.filter_duplicates.into())?;
+        if scan.whitelist.is_empty() {
+            self.nvs.remove(Self::WHITELIST)?;
+        } else {
+            let whitelist: Vec<u8> = scan
+                .whitelist
+                .iter()
+                .flat_map(|addr| addr.bytes())
+                .collect();
+            self.nvs.set_blob(Self::WHITELIST, &whitelist)?;
+        }
+        let advertising = &config.advertising;
+        // Validated, hence within 10.24 s.
+        let interval = u32::try_from(advertising.interval.as_millis())?;
+        self.nvs.set_u32(Self::ADV_INTERVAL, interval)?;
+        self.nvs.set_i8(Self::TX_POWER, advertising.tx_power)?;
+        self.nvs.set_u8(Self::ADV_CHANNELS, advertising.channels)?;
+        self.nvs
+            .set_u8(Self::ADV_SCAN_RSP, advertising.scan_response.into())?;
+        self.nvs
+            .set_u8(Self::ADV_PRIVACY, advertising.privacy.into())?;
+        match config.auth_key {
+            Some(key) => self.nvs.set_blob(Self::AUTH_KEY, &key.bytes())?,
+            None => {
+                self.nvs.remove(Self::AUTH_KEY)?;
+            }
+        }
+        self.nvs
+            .set_str(Self::MESH_ROLE, &config.mesh.role.to_string())?;
+        self.nvs.set_u8(Self::MESH_TTL, config.mesh.ttl)?;
+        self.nvs.set_u8(Self::SYNC, config.sync.into())?;
+        self.nvs.set_u8(Self::ELECTION, config.election.into())?;
+        let beacon = &config.beacon;
+        self.nvs
+            .set_str(Self::BEACONS, &format_frames(&beacon.frames))?;
+        match beacon.uuid {
+            Some(uuid) => self.nvs.set_blob(Self::BEACON_UUID, &uuid.bytes())?,
+            None => {
+                self.nvs.remove(Self::BEACON_UUID)?;
+            }
+        }
+        self.nvs.set_u16(Self::BEACON_MAJOR, beacon.major)?;
+        self.nvs.set_u16(Self::BEACON_MINOR, beacon.minor)?;
+        self.nvs.set_str(Self::BEACON_URL, &beacon.url)?;
+
+        Ok(())
+    }
+
+    /// Resets the configuration to its defaults.
+    ///
+    /// # Errors
+    /// Returns an error if NVS cannot be written.
+    pub fn reset(&mut self) -> Result<()> {
+        self.nvs.remove(Self::NAME)?;
+        self.nvs.remove(Self::GROUP)?;
+        self.nvs.remove(Self::SLEEP_AFTER)?;
+        self.nvs.remove(Self::WAKE_EVERY)?;
+        self.nvs.remove(Self::BATTERY_PIN)?;
+        self.nvs.remove(Self::BATTERY_SCALE)?;
+        self.nvs.remove(Self::SCAN_POLICY)?;
+        self.nvs.remove(Self::SCAN_ACTIVE)?;
+        self.nvs.remove(Self::SCAN_INTERVAL)?;
+        self.nvs.remove(Self::SCAN_WINDOW)?;
+        self.nvs.remove(Self::SCAN_DEDUP)?;
+        self.nvs.remove(Self::WHITELIST)?;
+        self.nvs.remove(Self::ADV_INTERVAL)?;
+        self.nvs.remove(Self::TX_POWER)?;
+        self.nvs.remove(Self::ADV_CHANNELS)?;
+        self.nvs.remove(Self::ADV_SCAN_RSP)?;
+        self.nvs.remove(Self::ADV_PRIVACY)?;
+        self.nvs.remove(Self::AUTH_KEY)?;
+        self.nvs.remove(Self::MESH_ROLE)?;
+        self.nvs.remove(Self::MESH_TTL)?;
+        self.nvs.remove(Self::SYNC)?;
+        self.nvs.remove(Self::ELECTION)?;
+        self.nvs.remove(Self::BEACONS)?;
+        self.nvs.remove(Self::BEACON_UUID)?;
+        self.nvs.remove(Self::BEACON_MAJOR)?;
+        self.nvs.remove(Self::BEACON_MINOR)?;
+        self.nvs.remove(Self::BEACON_URL)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use esp_idf_hal::{delay::TickType, uart::UartDriver};
+use std::time::Duration;
+
+use crate::{
+    control::Controller,
+    infra::Poller,
+    shell::{self, Editor},
+    watchdog,
+};
+
+/// Represents an interactive shell on a UART.
+///
+/// While the advertisements heard are streamed, the input is not echoed and
+/// any key stops the stream.
+///
+/// # Type Parameters
+/// * `'a` - Lifetime of the console.
+pub struct Console<'a> {
+    uart: UartDriver<'a>,
+    editor: Editor,
+    controller: Controller,
+}
+
+impl<'a> Console<'a> {
+    /// Maximum duration to wait for input, so that the watchdog keeps being fed.
+    const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Creates a new `Console` instance.
+    ///
+    /// # Arguments
+    /// * `uart` - The UART driver of the terminal.
+    /// * `controller` - The command layer the commands are executed by.
+    ///
+    /// # Errors
+    /// Returns an error if the console cannot be initialized.
+    pub fn new(uart: UartDriver<'a>, controller: Controller) -> Result<Self> {
+        Ok(Self {
+            uart,
+            editor: Editor::new("> "),
+            controller,
+        })
+    }
+
+    /// Writes text to the terminal, translating line feeds.
+    ///
+    /// # Arguments
+    /// * `text` - The text to write.
+    ///
+    /// # Errors
+    /// Returns an error if the UART cannot be written.
+    fn write(&mut self, text: &str) -> Result<()> {
+        let text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+        self.uart.write(text.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Parses and executes a command line.
+    ///
+    /// # Arguments
+    /// * `line` - The command line.
+    ///
+    /// # Returns
+    /// The output of the command, or the error it caused.
+    fn run(&mut self, line: &str) -> String {
+        if line.trim().is_empty() {
+            return String::new();
+        }
+
+        shell::parse(line)
+            .and_then(|command| self.controller.execute(command))
+            .unwrap_or_else(|e| format!("error: {e:#}\n"))
+    }
+}
+
+impl Poller for Console<'_> {
+    /// Reads the terminal input and executes the commands, or streams the
+    /// advertisements heard.
+    ///
+    /// # Errors
+    /// Returns an error if the UART or the watchdog fails.
+    fn poll(&mut self) -> Result<!> {
+        let timeout = TickType::from(Self::READ_TIMEOUT).ticks();
+        let mut buf = [0; 32];
+
+        self.write(&format!(
+            "\nType `help` for commands.\n{}",
+            self.editor.prompt()
+        ))?;
+        loop {
+            watchdog::feed()?;
+
+            let len = self.uart.read(&mut buf, timeout)?;
+            if self.controller.sniffing() {
+                // Skip the line feed that may follow the carriage return
+                // ending the command.
+                if buf[..len].iter().any(|byte| *byte != b'\n') {
+                    self.controller.stop_sniffing()?;
+                    self.write(&format!("\nstopped\n{}", self.editor.prompt()))?;
+                } else {
+                    // The stream is written as is, as pcap captures are binary.
+                    self.uart.write(&self.controller.sniffed())?;
+                }
+                continue;
+            }
+
+            for byte in &buf[..len] {
+                let mut echo = String::new();
+                let line = self.editor.feed(*byte, &mut echo);
+                self.write(&echo)?;
+
+                if let Some(line) = line {
+                    let out = self.run(&line);
+                    self.write(&out)?;
+                    if !self.controller.sniffing() {
+                        self.write(self.editor.prompt())?;
+                    }
+                }
+            }
+        }
+    }
+}
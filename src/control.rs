@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Result};
+use esp_idf_hal::reset::restart;
+use log::warn;
+use std::{fmt::Write, sync::Arc};
+
+use crate::{
+    ble::{Peers, Sniffer},
+    config::{Config, Store},
+    crash, hibernate,
+    journal::{self, Format, Journal},
+    logger,
+    logic::Status,
+    message::{Notifier, Trigger},
+    metrics, power,
+    shell::{Command, HELP},
+    sniff,
+    time::sleep,
+    timestamp::Timestamp,
+};
+
+/// Represents the command layer shared by the control surfaces, e.g. the
+/// serial console.
+pub struct Controller {
+    notifier: Notifier,
+    status: Arc<Status>,
+    peers: Arc<Peers>,
+    sniffer: Arc<Sniffer>,
+    journal: Arc<Journal>,
+    store: Store,
+}
+
+impl Controller {
+    /// Creates a new `Controller` instance.
+    ///
+    /// # Arguments
+    /// * `notifier` - A notifier to publish triggers.
+    /// * `status` - The status of the state machine.
+    /// * `peers` - The devices seen by the scanner.
+    /// * `sniffer` - The sniffer fed by the scanner.
+    /// * `journal` - The journal of the state machine.
+    /// * `store` - The configuration store.
+    ///
+    /// # Errors
+    /// Returns an error if the controller cannot be initialized.
+    pub fn new(
+        notifier: Notifier,
+        status: Arc<Status>,
+        peers: Arc<Peers>,
+        sniffer: Arc<Sniffer>,
+        journal: Arc<Journal>,
+        store: Store,
+    ) -> Result<Self> {
+        Ok(Self {
+            notifier,
+            status,
+            peers,
+            sniffer,
+            journal,
+            store,
+        })
+    }
+
+    /// Describes the state of the state machine.
+    ///
+    /// # Returns
+    /// The state, the role in the election, the LED color override, the power
+    /// mode and the number of deep sleeps.
+    fn state(&self) -> String {
+        let role = self
+            .status
+            .role()
+            .map_or_else(|| "none".into(), |role| role.to_string());
+        let color = self
+            .status
+            .color()
+            .map_or_else(|| "auto".into(), |color| color.to_string());
+        let mode = power::mode()
+            .map_or_else(|| "unmanaged".into(), |mode| format!("{mode:?}"));
+
+        format!(
+            "state: {}\nrole: {}\nled: {}\npower: {}\nsleeps: {}\n",
+            self.status.state(),
+            role,
+            color,
+            mode,
+            hibernate::sleeps()
+        )
+    }
+
+    /// Lists the devices seen by the scanner.
+    ///
+    /// # Returns
+    /// One line per device, least recently seen first.
+    fn peers(&self) -> String {
+        let mut out = String::new();
+        for peer in self.peers.list() {
+            let _ = writeln!(
+                out,
+                "{} {:?} {}s ago",
+                peer.addr,
+                peer.trigger,
+                peer.last_seen.elapsed().as_secs()
+            );
+        }
+
+        out
+    }
+
+    /// Shows the crashes kept across restarts.
+    ///
+    /// # Returns
+    /// One line per crash record, oldest first, then the number of
+    /// consecutive crashes if any.
+    fn crashes() -> String {
+        let mut out = String::new();
+        for record in crash::records() {
+            let _ = writeln!(out, "{record}");
+        }
+        let consecutive = crash::consecutive();
+        if consecutive > 0 {
+            let _ = writeln!(
+                out,
+                "{consecutive} consecutive crash(es) before this boot"
+            );
+        }
+
+        out
+    }
+
+    /// Shows the configuration.
+    ///
+    /// # Arguments
+    /// * `key` - The setting to show, or `None` for all of them.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration cannot be loaded or if the
+    /// setting does not exist.
+    fn config_get(&self, key: Option<&str>) -> Result<String> {
+        let config = self.store.load()?;
+        let mut out = String::new();
+
+        for key in key.map_or(Config::KEYS.to_vec(), |key| vec![key]) {
+            let _ = writeln!(out, "{}: {}", key, config.get(key)?);
+        }
+
+        Ok(out)
+    }
+
+    /// Changes a setting, applied at the next restart.
+    ///
+    /// # Arguments
+    /// * `key` - The setting to change.
+    /// * `value` - The new value.
+    ///
+    /// # Errors
+    /// Returns an error if the setting is invalid or cannot be saved.
+    fn config_set(&mut self, key: &str, value: &str) -> Result<String> {
+        let mut config = self.store.load()?;
+        config.set(key, value)?;
+        self.store.save(&config)?;
+
+        Ok("saved, reboot to apply\n".into())
+    }
+
+    /// Starts streaming the advertisements heard, muting the logs meanwhile.
+    ///
+    /// # Arguments
+    /// * `format` - The stream format.
+    ///
+    /// # Errors
+    /// Returns an error if the logger is not initialized or the logs cannot
+    /// be muted.
+    fn sniff(&self, format: sniff::Format) -> Result<String> {
+        logger::get()?.set_muted(true)?;
+        self.sniffer.start(format);
+
+        Ok(String::new())
+    }
+
+    /// Returns whether the advertisements heard are being streamed.
+    ///
+    /// # Returns
+    /// `true` from a `sniff` command until `stop_sniffing`.
+    #[must_use]
+    pub fn sniffing(&self) -> bool {
+        self.sniffer.active()
+    }
+
+    /// Takes the advertisements waiting to be streamed.
+    ///
+    /// # Returns
+    /// The reports, in the format of the `sniff` command.
+    #[must_use]
+    pub fn sniffed(&self) -> Vec<u8> {
+        self.sniffer.take()
+    }
+
+    /// Stops streaming the advertisements heard and unmutes the logs.
+    ///
+    /// # Errors
+    /// Returns an error if the logger is not initialized or the log levels
+    /// cannot be restored.
+    pub fn stop_sniffing(&self) -> Result<()> {
+        self.sniffer.stop();
+        logger::get()?.set_muted(false)
+    }
+
+    /// Restarts the device.
+    fn reboot() -> ! {
+        warn!("Rebooting on request");
+        // Leave some time for the log line to be printed.
+        sleep(100);
+        restart()
+    }
+
+    /// Executes a command.
+    ///
+    /// # Arguments
+    /// * `command` - The command to execute.
+    ///
+    /// # Returns
+    /// The output of the command, made of lines ending with `\n`.
+    ///
+    /// # Errors
+    /// Returns an error if the command fails.
+    pub fn execute(&mut self, command: Command) -> Result<String> {
+        let out = match command {
+            Command::Help => HELP.into(),
+            Command::State => self.state(),
+            Command::Trigger(name) => {
+                let trigger = name.parse()?;
+                // The internal triggers would confuse the state machine.
+                if !Trigger::external().contains(trigger) {
+                    return Err(anyhow!("Internal trigger: {}", name));
+                }
+                self.notifier.notify(trigger)?;
+                "ok\n".into()
+            }
+            Command::Led(color) => {
+                self.status.set_color(color);
+                "ok\n".into()
+            }
+            Command::ScanNow => {
+                self.notifier.notify(Trigger::ScanRequested)?;
+                "ok\n".into()
+            }
+            Command::Peers => self.peers(),
+            Command::Crashes => Self::crashes(),
+            Command::ConfigGet(key) => self.config_get(key.as_deref())?,
+            Command::ConfigSet(key, value) => self.config_set(&key, &value)?,
+            Command::Reboot => Self::reboot(),
+            Command::LogLevel(module, level) => {
+                let logger = logger::get()?;
+                match module {
+                    Some(module) => logger.set_level(&module, level)?,
+                    None => logger.set_default_level(level)?,
+                }
+                "ok\n".into()
+            }
+            Command::LogRecent => logger::get()?
+                .recent()
+                .iter()
+                .map(|line| format!("{line}\n"))
+                .collect(),
+            Command::Journal { json, from, to } => {
+                let format = if json { Format::Json } else { Format::Csv };
+                let entries = self.journal.range(
+                    from.unwrap_or(Timestamp::MIN),
+                    to.unwrap_or(Timestamp::MAX),
+                );
+                let mut out = journal::export(&entries, format);
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out
+            }
+            Command::Metrics { json } => {
+                if json {
+                    metrics::json() + "\n"
+                } else {
+                    metrics::prometheus()
+                }
+            }
+            Command::Sniff { pcap } => self.sniff(if pcap {
+                sniff::Format::Pcap
+            } else {
+                sniff::Format::Json
+            })?,
+        };
+
+        Ok(out)
+    }
+}
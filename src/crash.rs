@@ -0,0 +1,409 @@
+use esp_idf_hal::sys::{
+    esp_backtrace_frame_t, esp_backtrace_get_next_frame, esp_backtrace_get_start,
+    esp_reset_reason, esp_reset_reason_t, esp_reset_reason_t_ESP_RST_INT_WDT,
+    esp_reset_reason_t_ESP_RST_PANIC, esp_reset_reason_t_ESP_RST_POWERON,
+    esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_WDT,
+    esp_timer_get_time,
+};
+use log::{error, info};
+use std::{
+    ffi::CStr,
+    fmt, panic,
+    ptr::addr_of_mut,
+    sync::{Mutex, OnceLock, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use crate::watchdog;
+
+/// Maximum number of crash records kept across resets.
+const MAX_RECORDS: usize = 4;
+
+/// Maximum length of a thread name in a crash record.
+const THREAD_LEN: usize = 16;
+
+/// Maximum length of an error message in a crash record.
+const MESSAGE_LEN: usize = 96;
+
+/// Number of program counters kept in a crash record.
+const BACKTRACE_LEN: usize = 4;
+
+/// Number of consecutive failed boots after which the device starts in safe
+/// mode.
+const MAX_CONSECUTIVE: u32 = 3;
+
+/// Uptime after which a boot is considered successful, i.e. the window in
+/// which a crash counts as a failed boot.
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// Marks a valid `Log` in RTC memory.
+const MAGIC: u32 = 0x4352_5348;
+
+/// Represents a crash record as stored in RTC memory.
+///
+/// # Fields
+/// * `uptime_ms` - Uptime of the crash, in milliseconds.
+/// * `thread` - Null-terminated name of the thread that crashed.
+/// * `backtrace` - Program counters of the innermost frames, zero-padded.
+/// * `message` - Null-terminated error chain or panic message.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Entry {
+    uptime_ms: u32,
+    thread: [u8; THREAD_LEN],
+    backtrace: [u32; BACKTRACE_LEN],
+    message: [u8; MESSAGE_LEN],
+}
+
+/// Represents the crash log as stored in RTC memory.
+///
+/// # Fields
+/// * `magic` - Equals `MAGIC` if the log is valid.
+/// * `pending` - Non-zero if a crash was recorded since the last boot.
+/// * `consecutive` - Number of consecutive boots that ended in a crash.
+/// * `count` - Total number of crashes recorded, used as ring buffer index.
+/// * `entries` - The last crash records.
+#[repr(C)]
+struct Log {
+    magic: u32,
+    pending: u32,
+    consecutive: u32,
+    count: u32,
+    entries: [Entry; MAX_RECORDS],
+}
+
+/// Crash log. Lives in RTC memory that is not cleared on reset, so it has to
+/// be validated with `MAGIC` before use.
+#[link_section = ".rtc_noinit"]
+static mut LOG: Log = Log {
+    magic: 0,
+    pending: 0,
+    consecutive: 0,
+    count: 0,
+    entries: [Entry {
+        uptime_ms: 0,
+        thread: [0; THREAD_LEN],
+        backtrace: [0; BACKTRACE_LEN],
+        message: [0; MESSAGE_LEN],
+    }; MAX_RECORDS],
+};
+
+/// Serializes accesses to `LOG`.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Function called on the crash path, set by `on_crash`.
+static HOOK: OnceLock<Box<dyn Fn() + Send + Sync>> = OnceLock::new();
+
+/// Represents a crash that happened before the last reset.
+///
+/// # Fields
+/// * `uptime` - Uptime of the crash.
+/// * `thread` - Name of the thread that crashed.
+/// * `backtrace` - Program counters of the innermost frames.
+/// * `message` - Error chain or panic message.
+pub struct Record {
+    pub uptime: Duration,
+    pub thread: String,
+    pub backtrace: Vec<u32>,
+    pub message: String,
+}
+
+impl From<&Entry> for Record {
+    /// Converts a raw `Entry` into a `Record`.
+    fn from(entry: &Entry) -> Self {
+        Self {
+            uptime: Duration::from_millis(entry.uptime_ms.into()),
+            thread: read_str(&entry.thread),
+            backtrace: entry
+                .backtrace
+                .iter()
+                .copied()
+                .take_while(|pc| *pc != 0)
+                .collect(),
+            message: read_str(&entry.message),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    /// Formats the record on a single line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:.3}s] {}: {} (backtrace:",
+            self.uptime.as_secs_f32(),
+            self.thread,
+            self.message
+        )?;
+        for pc in &self.backtrace {
+            write!(f, " {pc:#010x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Copies a string into a fixed-size, null-terminated buffer, truncating it if
+/// needed.
+///
+/// # Arguments
+/// * `dst` - The buffer to copy to.
+/// * `src` - The string to copy.
+fn write_str(dst: &mut [u8], src: &str) {
+    let len = src.len().min(dst.len() - 1);
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+    dst[len..].fill(0);
+}
+
+/// Reads a string from a fixed-size, null-terminated buffer.
+///
+/// # Arguments
+/// * `src` - The buffer to read from.
+///
+/// # Returns
+/// The string, with invalid UTF-8 sequences replaced.
+fn read_str(src: &[u8]) -> String {
+    CStr::from_bytes_until_nul(src)
+        .map_or_else(|_| String::new(), |s| s.to_string_lossy().into_owned())
+}
+
+/// Returns the uptime.
+///
+/// # Returns
+/// The time elapsed since boot.
+#[allow(clippy::cast_sign_loss)]
+fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
+/// Converts a return address read from the stack into the address of the call
+/// instruction, as the Xtensa windowed ABI stores the window size in the upper
+/// bits of return addresses.
+///
+/// # Arguments
+/// * `pc` - The return address.
+///
+/// # Returns
+/// The address of the call instruction.
+fn process_stack_pc(pc: u32) -> u32 {
+    let pc = if pc & 0x8000_0000 == 0 {
+        pc
+    } else {
+        (pc & 0x3fff_ffff) | 0x4000_0000
+    };
+
+    pc.wrapping_sub(3)
+}
+
+/// Captures the program counters of the caller's innermost frames.
+///
+/// # Returns
+/// The program counters, zero-padded.
+fn backtrace() -> [u32; BACKTRACE_LEN] {
+    let mut pcs = [0; BACKTRACE_LEN];
+    let mut frame = esp_backtrace_frame_t::default();
+
+    unsafe {
+        esp_backtrace_get_start(&mut frame.pc, &mut frame.sp, &mut frame.next_pc);
+    }
+    // Skip the frame of this function.
+    for pc in &mut pcs {
+        if !unsafe { esp_backtrace_get_next_frame(&mut frame) } {
+            break;
+        }
+        *pc = process_stack_pc(frame.pc);
+    }
+
+    pcs
+}
+
+/// Stores a crash record in RTC memory.
+///
+/// # Arguments
+/// * `thread` - Name of the thread that crashed.
+/// * `message` - Error chain or panic message.
+/// * `backtrace` - Program counters of the innermost frames.
+fn store(thread: &str, message: &str, backtrace: [u32; BACKTRACE_LEN]) {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let log = unsafe { &mut *addr_of_mut!(LOG) };
+
+    let entry = &mut log.entries[log.count as usize % MAX_RECORDS];
+    entry.uptime_ms = u32::try_from(uptime().as_millis()).unwrap_or(u32::MAX);
+    write_str(&mut entry.thread, thread);
+    entry.backtrace = backtrace;
+    write_str(&mut entry.message, message);
+
+    log.count = log.count.wrapping_add(1);
+    log.pending = 1;
+}
+
+/// Returns the name of the current thread.
+///
+/// # Returns
+/// The name of the thread, or `"<unnamed>"`.
+fn current_thread() -> String {
+    thread::current().name().unwrap_or("<unnamed>").to_owned()
+}
+
+/// Returns whether a reset reason denotes a crash not recorded by `record`.
+///
+/// # Arguments
+/// * `reason` - The reset reason.
+///
+/// # Returns
+/// A description of the crash, if the reset was caused by one.
+fn crash_reason(reason: esp_reset_reason_t) -> Option<&'static str> {
+    match reason {
+        esp_reset_reason_t_ESP_RST_PANIC => Some("panic"),
+        esp_reset_reason_t_ESP_RST_INT_WDT => Some("interrupt watchdog"),
+        esp_reset_reason_t_ESP_RST_TASK_WDT => Some("task watchdog"),
+        esp_reset_reason_t_ESP_RST_WDT => Some("watchdog"),
+        _ => None,
+    }
+}
+
+/// Initializes crash diagnostics.
+///
+/// This function must be called once at boot. It accounts for the crash that
+/// caused the last reset, if any, logs the last crash records and installs a
+/// panic hook recording panics.
+pub fn init() {
+    let reason = unsafe { esp_reset_reason() };
+
+    {
+        let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let log = unsafe { &mut *addr_of_mut!(LOG) };
+
+        if log.magic != MAGIC || reason == esp_reset_reason_t_ESP_RST_POWERON {
+            *log = Log {
+                magic: MAGIC,
+                pending: 0,
+                consecutive: 0,
+                count: 0,
+                entries: log.entries,
+            };
+        }
+    }
+
+    let pending = unsafe { (*addr_of_mut!(LOG)).pending } != 0;
+    if !pending {
+        if let Some(description) = crash_reason(reason) {
+            let thread = watchdog::starved().unwrap_or_else(|| "<unknown>".into());
+            store(&thread, description, [0; BACKTRACE_LEN]);
+        }
+    }
+
+    {
+        let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let log = unsafe { &mut *addr_of_mut!(LOG) };
+
+        if log.pending == 0 {
+            log.consecutive = 0;
+        } else {
+            log.consecutive += 1;
+            log.pending = 0;
+        }
+    }
+
+    for record in records() {
+        info!("Crash: {}", record);
+    }
+    if consecutive() > 0 {
+        error!("{} consecutive crash(es) before this boot", consecutive());
+    }
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        store(&current_thread(), &info.to_string(), backtrace());
+        rescue();
+        default_hook(info);
+    }));
+}
+
+/// Sets a function called on the crash path, once the crash is recorded, e.g.
+/// to save the journal before the device restarts.
+///
+/// Only the first function set is kept.
+///
+/// # Arguments
+/// * `hook` - The function, which must not block.
+pub fn on_crash(hook: impl Fn() + Send + Sync + 'static) {
+    let _ = HOOK.set(Box::new(hook));
+}
+
+/// Calls the function set by `on_crash`, if any.
+fn rescue() {
+    if let Some(hook) = HOOK.get() {
+        hook();
+    }
+}
+
+/// Records an error that is about to restart the device.
+///
+/// # Arguments
+/// * `error` - The error, whose whole chain is recorded.
+pub fn record(error: &anyhow::Error) {
+    error!("{} failed: {:#}", current_thread(), error);
+    store(&current_thread(), &format!("{error:#}"), backtrace());
+    rescue();
+}
+
+/// Returns the crash records kept across resets, oldest first.
+///
+/// # Returns
+/// The last crash records.
+#[must_use]
+pub fn records() -> Vec<Record> {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let log = unsafe { &*addr_of_mut!(LOG) };
+
+    let len = (log.count as usize).min(MAX_RECORDS);
+    (log.count as usize - len..log.count as usize)
+        .map(|i| Record::from(&log.entries[i % MAX_RECORDS]))
+        .collect()
+}
+
+/// Returns the number of consecutive boots that ended in a crash.
+///
+/// # Returns
+/// The number of consecutive crashes.
+#[must_use]
+pub fn consecutive() -> u32 {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    unsafe { (*addr_of_mut!(LOG)).consecutive }
+}
+
+/// Returns whether the device crashed too many times in a row to start
+/// normally.
+///
+/// # Returns
+/// `true` if the device should start in safe mode, `false` otherwise.
+#[must_use]
+pub fn safe_mode() -> bool {
+    consecutive() >= MAX_CONSECUTIVE
+}
+
+/// Resets the consecutive crash counter.
+pub fn clear() {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let log = unsafe { &mut *addr_of_mut!(LOG) };
+
+    log.consecutive = 0;
+    log.pending = 0;
+}
+
+/// Resets the consecutive crash counter once the device has been up for long
+/// enough.
+///
+/// This function is cheap and meant to be called periodically.
+pub fn settle() {
+    if uptime() < STABLE_UPTIME {
+        return;
+    }
+
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let log = unsafe { &mut *addr_of_mut!(LOG) };
+    if log.pending == 0 {
+        log.consecutive = 0;
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Result;
+use esp_idf_hal::sys::{
+    esp, esp_deep_sleep_start, esp_reset_reason,
+    esp_reset_reason_t_ESP_RST_DEEPSLEEP, esp_sleep_enable_ext0_wakeup,
+    esp_sleep_enable_timer_wakeup, esp_sleep_get_wakeup_cause,
+    esp_sleep_wakeup_cause_t_ESP_SLEEP_WAKEUP_EXT0,
+    esp_sleep_wakeup_cause_t_ESP_SLEEP_WAKEUP_TIMER,
+};
+use log::info;
+use std::{
+    ptr::addr_of_mut,
+    time::{Duration, Instant},
+};
+
+use crate::{logic::State, message::Trigger};
+
+/// Marks a valid `Retained` record in RTC memory.
+const MAGIC: u32 = 0x534c_4550;
+
+/// Represents the data kept in RTC memory during deep sleep.
+///
+/// # Fields
+/// * `magic` - Equals `MAGIC` if the record is valid.
+/// * `state` - The state of the state machine when it went to sleep.
+/// * `sleeps` - Number of deep sleeps since power on.
+#[repr(C)]
+struct Retained {
+    magic: u32,
+    state: u8,
+    sleeps: u32,
+}
+
+/// Data kept during deep sleep. Lives in RTC memory that is cleared on power
+/// on only, so it has to be validated with `MAGIC` before use.
+#[link_section = ".rtc.data"]
+static mut RETAINED: Retained = Retained {
+    magic: 0,
+    state: 0,
+    sleeps: 0,
+};
+
+/// Represents the deep sleep policy of the system.
+///
+/// Once the system has been off for long enough, the device deep sleeps until
+/// the button is pressed or, optionally, until a timer expires. The state of
+/// the state machine is retained meanwhile, and restored on wakeup.
+pub struct Hibernate {
+    after: Duration,
+    wake_every: Option<Duration>,
+    button: i32,
+    deadline: Option<Instant>,
+}
+
+impl Hibernate {
+    /// Duration the device stays awake after a timer wakeup, e.g. to scan.
+    pub const GRACE: Duration = Duration::from_secs(10);
+
+    /// Creates a new `Hibernate` instance.
+    ///
+    /// # Arguments
+    /// * `after` - Duration the system has to be off for before deep sleeping.
+    /// * `wake_every` - Duration after which a deep sleep ends, if any.
+    /// * `button` - The GPIO number of the button, active low. It must be an
+    ///   RTC GPIO.
+    ///
+    /// # Errors
+    /// Returns an error if the policy cannot be initialized.
+    pub fn new(
+        after: Duration,
+        wake_every: Option<Duration>,
+        button: i32,
+    ) -> Result<Self> {
+        Ok(Self {
+            after,
+            wake_every,
+            button,
+            deadline: None,
+        })
+    }
+
+    /// Tracks the state of the state machine to schedule the deep sleep.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the state machine.
+    pub fn update(&mut self, state: State) {
+        self.deadline = match (state, self.deadline) {
+            (State::Off, Some(deadline)) => Some(deadline),
+            (State::Off, None) => Some(Instant::now() + self.after),
+            _ => None,
+        };
+    }
+
+    /// Reschedules the deep sleep, e.g. shortly after a timer wakeup.
+    ///
+    /// # Arguments
+    /// * `after` - Duration from now after which the device deep sleeps, if the
+    ///   system is still off.
+    pub fn snooze(&mut self, after: Duration) {
+        self.deadline = Some(Instant::now() + after);
+    }
+
+    /// Returns whether it is time to deep sleep.
+    ///
+    /// # Returns
+    /// `true` if the system has been off until the scheduled deadline.
+    #[must_use]
+    pub fn due(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Persists the state, counts the deep sleep, arms the wakeup sources and
+    /// deep sleeps.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the state machine, restored on wakeup.
+    ///
+    /// # Errors
+    /// Returns an error if a wakeup source cannot be armed.
+    pub fn enter(&self, state: State) -> Result<!> {
+        esp!(unsafe { esp_sleep_enable_ext0_wakeup(self.button, 0) })?;
+        if let Some(wake_every) = self.wake_every {
+            let us = u64::try_from(wake_every.as_micros()).unwrap_or(u64::MAX);
+            esp!(unsafe { esp_sleep_enable_timer_wakeup(us) })?;
+        }
+
+        let retained = unsafe { &mut *addr_of_mut!(RETAINED) };
+        if retained.magic != MAGIC {
+            retained.sleeps = 0;
+        }
+        retained.magic = MAGIC;
+        retained.state = state.into();
+        retained.sleeps = retained.sleeps.wrapping_add(1);
+
+        info!(wake_every:? = self.wake_every; "entering deep sleep");
+        log::logger().flush();
+
+        unsafe { esp_deep_sleep_start() }
+    }
+}
+
+/// Returns the state the state machine was in before the last deep sleep.
+///
+/// # Returns
+/// The retained state, if the device just woke up from deep sleep.
+#[must_use]
+pub fn restore() -> Option<State> {
+    let retained = unsafe { &*addr_of_mut!(RETAINED) };
+
+    (unsafe { esp_reset_reason() } == esp_reset_reason_t_ESP_RST_DEEPSLEEP
+        && retained.magic == MAGIC)
+        .then(|| State::try_from(retained.state).ok())
+        .flatten()
+}
+
+/// Returns the trigger corresponding to the cause of the last wakeup.
+///
+/// # Returns
+/// `ButtonPressed` if the button woke the device up, `SleepTimerExpired` if
+/// the timer did, `None` if the device did not wake up from deep sleep.
+#[must_use]
+pub fn wake_trigger() -> Option<Trigger> {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_sleep_get_wakeup_cause() } {
+        esp_sleep_wakeup_cause_t_ESP_SLEEP_WAKEUP_EXT0 => {
+            Some(Trigger::ButtonPressed)
+        }
+        esp_sleep_wakeup_cause_t_ESP_SLEEP_WAKEUP_TIMER => {
+            Some(Trigger::SleepTimerExpired)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the number of deep sleeps since power on.
+///
+/// # Returns
+/// The number of deep sleeps, zero after a power on.
+#[must_use]
+pub fn sleeps() -> u32 {
+    let retained = unsafe { &*addr_of_mut!(RETAINED) };
+
+    if retained.magic == MAGIC {
+        retained.sleeps
+    } else {
+        0
+    }
+}
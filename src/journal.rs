@@ -0,0 +1,394 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    logic::State,
+    message::{Trigger, TriggerSet},
+    metrics::lock,
+    timestamp::Timestamp,
+};
+
+/// Size of an encoded `Entry`, in bytes.
+const ENTRY_SIZE: usize = 12;
+
+/// Represents a journal entry.
+///
+/// # Fields
+/// * `timestamp` - When the triggers were handled.
+/// * `from` - The state before handling the triggers.
+/// * `to` - The state after handling the triggers.
+/// * `triggers` - The handled triggers.
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub timestamp: Timestamp,
+    pub from: State,
+    pub to: State,
+    pub triggers: TriggerSet,
+}
+
+impl Entry {
+    /// Encodes the entry for storage.
+    ///
+    /// # Returns
+    /// The little-endian encoding of the entry.
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut buf = [0; ENTRY_SIZE];
+        let uptime =
+            u32::try_from(self.timestamp.uptime.as_millis()).unwrap_or(u32::MAX);
+
+        buf[0..2].copy_from_slice(&self.timestamp.boot.to_le_bytes());
+        buf[2..6].copy_from_slice(&uptime.to_le_bytes());
+        buf[6] = self.from.into();
+        buf[7] = self.to.into();
+        buf[8..12].copy_from_slice(&self.triggers.bits().to_le_bytes());
+
+        buf
+    }
+
+    /// Decodes an entry from storage.
+    ///
+    /// # Arguments
+    /// * `buf` - The encoded entry.
+    ///
+    /// # Errors
+    /// Returns an error if the entry is corrupted.
+    fn decode(buf: &[u8; ENTRY_SIZE]) -> Result<Self> {
+        let boot = u16::from_le_bytes([buf[0], buf[1]]);
+        let uptime = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+        let triggers = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+
+        Ok(Self {
+            timestamp: Timestamp {
+                boot,
+                uptime: Duration::from_millis(uptime.into()),
+            },
+            from: State::try_from(buf[6])
+                .map_err(|e| anyhow!("Corrupted journal entry: {}", e))?,
+            to: State::try_from(buf[7])
+                .map_err(|e| anyhow!("Corrupted journal entry: {}", e))?,
+            triggers: TriggerSet::from_bits(triggers),
+        })
+    }
+}
+
+/// Represents an export format of the journal.
+///
+/// # Variants
+/// * `Json` - A JSON array of objects.
+/// * `Csv` - CSV with a header line.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+/// Represents the copy of the journal kept in NVS.
+pub struct Mirror {
+    nvs: EspNvs<NvsDefault>,
+    saved: Option<Instant>,
+}
+
+impl Mirror {
+    /// NVS namespace of the mirror.
+    const NAMESPACE: &'static str = "journal";
+    /// NVS key of the entries.
+    const ENTRIES: &'static str = "entries";
+    /// Minimum duration between two writes, to limit flash wear.
+    const PERIOD: Duration = Duration::from_secs(10);
+
+    /// Creates a new `Mirror` instance.
+    ///
+    /// # Arguments
+    /// * `partition` - The default NVS partition.
+    ///
+    /// # Errors
+    /// Returns an error if the NVS namespace cannot be opened.
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, Self::NAMESPACE, true)?,
+            saved: None,
+        })
+    }
+
+    /// Loads the mirrored entries.
+    ///
+    /// Unreadable entries, e.g. corrupted or written with a larger capacity,
+    /// are erased so that the journal starts empty rather than failing every
+    /// boot, safe mode included.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum number of entries to load.
+    ///
+    /// # Errors
+    /// Returns an error if the unreadable entries cannot be erased.
+    fn load(&self, capacity: usize) -> Result<Vec<Entry>> {
+        let mut buf = vec![0; capacity * ENTRY_SIZE];
+        let entries = match self.nvs.get_blob(Self::ENTRIES, &mut buf) {
+            Ok(data) => data
+                .unwrap_or(&[])
+                .chunks_exact(ENTRY_SIZE)
+                .map(|chunk| {
+                    Entry::decode(chunk.try_into().map_err(|_| anyhow!("Bad size"))?)
+                })
+                .collect(),
+            Err(err) => Err(err.into()),
+        };
+
+        entries.or_else(|err| {
+            warn!(err:% = err; "Erasing the unreadable journal");
+            self.nvs.remove(Self::ENTRIES)?;
+            Ok(Vec::new())
+        })
+    }
+
+    /// Saves the entries, unless the last write is too recent.
+    ///
+    /// # Arguments
+    /// * `entries` - The entries to save.
+    ///
+    /// # Errors
+    /// Returns an error if NVS cannot be written.
+    fn save<'a>(&mut self, entries: impl Iterator<Item = &'a Entry>) -> Result<()> {
+        if self
+            .saved
+            .is_some_and(|saved| saved.elapsed() < Self::PERIOD)
+        {
+            return Ok(());
+        }
+
+        self.write(entries)
+    }
+
+    /// Saves the entries regardless of the last write.
+    ///
+    /// # Arguments
+    /// * `entries` - The entries to save.
+    ///
+    /// # Errors
+    /// Returns an error if NVS cannot be written.
+    fn write<'a>(&mut self, entries: impl Iterator<Item = &'a Entry>) -> Result<()> {
+        let data: Vec<u8> = entries.flat_map(Entry::encode).collect();
+        self.nvs.set_blob(Self::ENTRIES, &data)?;
+        self.saved = Some(Instant::now());
+
+        Ok(())
+    }
+}
+
+/// Represents a bounded journal of the state machine activity.
+///
+/// The oldest entries are dropped when the journal is full.
+pub struct Journal {
+    boot: u16,
+    start: Instant,
+    capacity: usize,
+    entries: Mutex<VecDeque<Entry>>,
+    mirror: Mutex<Option<Mirror>>,
+}
+
+impl Journal {
+    /// Creates a new `Journal` instance.
+    ///
+    /// If a mirror is given, the entries of the previous boots are restored
+    /// from it and new entries are mirrored to it.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum number of entries.
+    /// * `mirror` - An optional copy of the journal in NVS.
+    ///
+    /// # Errors
+    /// Returns an error if the mirrored entries cannot be restored.
+    pub fn new(capacity: usize, mirror: Option<Mirror>) -> Result<Self> {
+        let restored = mirror
+            .as_ref()
+            .map_or_else(|| Ok(Vec::new()), |mirror| mirror.load(capacity))?;
+        let boot = restored
+            .last()
+            .map_or(0, |entry| entry.timestamp.boot.wrapping_add(1));
+
+        let mut entries = VecDeque::with_capacity(capacity);
+        entries.extend(restored);
+
+        Ok(Self {
+            boot,
+            start: Instant::now(),
+            capacity,
+            entries: Mutex::new(entries),
+            mirror: Mutex::new(mirror),
+        })
+    }
+
+    /// Locks the entries.
+    ///
+    /// # Returns
+    /// The guard of the entries.
+    fn entries(&self) -> MutexGuard<'_, VecDeque<Entry>> {
+        lock(&self.entries)
+    }
+
+    /// Returns the current timestamp.
+    ///
+    /// # Returns
+    /// The current boot index and uptime.
+    #[must_use]
+    pub fn now(&self) -> Timestamp {
+        Timestamp {
+            boot: self.boot,
+            uptime: self.start.elapsed(),
+        }
+    }
+
+    /// Records the handling of a set of triggers.
+    ///
+    /// # Arguments
+    /// * `from` - The state before handling the triggers.
+    /// * `to` - The state after handling the triggers.
+    /// * `triggers` - The handled triggers.
+    ///
+    /// # Errors
+    /// Returns an error if the journal cannot be mirrored.
+    pub fn record(
+        &self,
+        from: State,
+        to: State,
+        triggers: TriggerSet,
+    ) -> Result<()> {
+        let mut entries = self.entries();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            timestamp: self.now(),
+            from,
+            to,
+            triggers,
+        });
+
+        if from != to {
+            if let Some(mirror) = lock(&self.mirror).as_mut() {
+                mirror.save(entries.iter())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the entries to the mirror now, e.g. before the device sleeps.
+    ///
+    /// # Errors
+    /// Returns an error if the journal cannot be mirrored.
+    pub fn flush(&self) -> Result<()> {
+        let entries = self.entries();
+        if let Some(mirror) = lock(&self.mirror).as_mut() {
+            mirror.write(entries.iter())?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the entries to the mirror on the crash path.
+    ///
+    /// Unlike `flush`, nothing is saved if the journal is locked, e.g. by the
+    /// crashing thread, rather than deadlocking. Failures are ignored, as the
+    /// device is restarting anyway.
+    pub fn rescue(&self) {
+        let (Ok(entries), Ok(mut mirror)) =
+            (self.entries.try_lock(), self.mirror.try_lock())
+        else {
+            return;
+        };
+        if let Some(mirror) = mirror.as_mut() {
+            let _ = mirror.write(entries.iter());
+        }
+    }
+
+    /// Returns the entries within a time range, oldest first.
+    ///
+    /// # Arguments
+    /// * `start` - The beginning of the range, inclusive.
+    /// * `end` - The end of the range, exclusive.
+    ///
+    /// # Returns
+    /// The entries whose timestamp is in the range.
+    #[must_use]
+    pub fn range(&self, start: Timestamp, end: Timestamp) -> Vec<Entry> {
+        self.entries()
+            .iter()
+            .filter(|entry| (start..end).contains(&entry.timestamp))
+            .copied()
+            .collect()
+    }
+
+    /// Returns all the entries, oldest first.
+    ///
+    /// # Returns
+    /// All the entries of the journal.
+    #[must_use]
+    pub fn all(&self) -> Vec<Entry> {
+        self.entries().iter().copied().collect()
+    }
+}
+
+/// Exports entries in a given format.
+///
+/// # Arguments
+/// * `entries` - The entries to export.
+/// * `format` - The export format.
+///
+/// # Returns
+/// The exported entries.
+#[must_use]
+pub fn export(entries: &[Entry], format: Format) -> String {
+    let mut out = String::new();
+    let triggers = |entry: &Entry, separator: &str, quote: &str| {
+        entry
+            .triggers
+            .iter()
+            .map(|trigger: Trigger| format!("{quote}{trigger:?}{quote}"))
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+
+    // Writing to a `String` cannot fail.
+    match format {
+        Format::Json => {
+            out.push('[');
+            for (i, entry) in entries.iter().enumerate() {
+                let _ = write!(
+                    out,
+                    "{}{{\"boot\":{},\"uptime_ms\":{},\"from\":\"{}\",\"to\":\"{}\",\"triggers\":[{}]}}",
+                    if i == 0 { "" } else { "," },
+                    entry.timestamp.boot,
+                    entry.timestamp.uptime.as_millis(),
+                    entry.from,
+                    entry.to,
+                    triggers(entry, ",", "\""),
+                );
+            }
+            out.push(']');
+        }
+        Format::Csv => {
+            out.push_str("boot,uptime_ms,from,to,triggers\n");
+            for entry in entries {
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    entry.timestamp.boot,
+                    entry.timestamp.uptime.as_millis(),
+                    entry.from,
+                    entry.to,
+                    triggers(entry, "|", ""),
+                );
+            }
+        }
+    }
+
+    out
+}
@@ -3,25 +3,68 @@
 /// The main library module for the project.
 ///
 /// This module re-exports all submodules, providing a central entry point for the library.
+/// The hardware independent ones are defined in `esp_layground_core`, to be
+/// tested on the host.
 ///
 /// # Modules
+/// * `auth` - Authentication of the BLE advertisements.
+/// * `battery` - Battery voltage monitoring.
+/// * `beacon` - Standard iBeacon and Eddystone frames.
 /// * `ble` - Bluetooth Low Energy (BLE) functionality.
 /// * `button` - Button handling and state management.
+/// * `charge` - State of charge and charge levels of the battery.
 /// * `clock` - Timer and clock-related functionality.
 /// * `color` - RGB color utilities.
+/// * `config` - Persistent configuration.
+/// * `console` - Interactive shell on the serial port.
+/// * `control` - Command layer shared by the control surfaces.
+/// * `crash` - Crash diagnostics kept across restarts.
+/// * `election` - Election of a coordinator among the devices.
+/// * `flags` - Allocation-free sets of enum flags.
+/// * `group` - Groups of devices that react to each other.
+/// * `hibernate` - Deep sleep with state retention.
 /// * `infra` - Infrastructure traits and utilities.
+/// * `journal` - History of the state machine transitions.
 /// * `light` - LED light control.
+/// * `logger` - Logging with per-module levels and recent lines.
 /// * `logic` - Application logic and state machine.
+/// * `lru` - Bounded maps forgetting their least recently used entry.
+/// * `mesh` - Flooding of commands across the devices out of range.
 /// * `message` - Messaging and notification system.
+/// * `metrics` - Runtime metrics and their exposition.
+/// * `power` - Power modes with light sleep and frequency scaling.
+/// * `safe` - Safe mode after repeated crashes.
+/// * `schedule` - Adaptive scheduling of the BLE scans.
+/// * `shell` - Command parsing and line editing.
+/// * `sniff` - Streaming of the advertisements heard, as JSON lines or pcap.
+/// * `sync` - Synchronisation of the blinking across devices.
 /// * `thread` - Threading utilities.
 /// * `time` - Time-related utilities.
+/// * `timestamp` - Points in time across restarts.
+/// * `watchdog` - Task watchdog integration.
+pub mod battery;
 pub mod ble;
 pub mod button;
 pub mod clock;
-pub mod color;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod crash;
+pub mod hibernate;
 pub mod infra;
+pub mod journal;
 pub mod light;
+pub mod logger;
 pub mod logic;
 pub mod message;
+pub mod metrics;
+pub mod power;
+pub mod safe;
 pub mod thread;
 pub mod time;
+pub mod watchdog;
+
+pub use esp_layground_core::{
+    auth, beacon, charge, color, election, flags, group, lru, mesh, schedule, shell,
+    sniff, sync, timestamp,
+};
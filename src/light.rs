@@ -5,6 +5,7 @@ use std::time::Duration;
 use crate::{
     color::{Rgb, BLACK},
     infra::Switch,
+    metrics,
 };
 
 pub const BLINK_FREQ: u64 = 3;
@@ -65,6 +66,7 @@ fn neopixel(rgb: &Rgb, tx: &mut TxRmtDriver) -> Result<()> {
         signal.set(23 - i as usize, &(high_pulse, low_pulse))?;
     }
     tx.start_blocking(&signal)?;
+    metrics::LED_FRAMES.inc();
     Ok(())
 }
 
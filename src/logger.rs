@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Result};
+use esp_idf_hal::sys::{esp_log_level_set, esp_log_level_t_ESP_LOG_NONE};
+use esp_idf_svc::log::EspLogger;
+use log::{
+    kv::{self, Key, Source, Value, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use crate::metrics::lock;
+
+/// Number of log lines kept in the ring buffer.
+const RECENT_LINES: usize = 64;
+
+/// The global logger, set by `init`.
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Formats the key/value pairs of a record as ` key=value` pairs.
+struct Fields<'a>(&'a dyn Source);
+
+impl fmt::Display for Fields<'_> {
+    /// Formats the key/value pairs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Writes the pairs to a formatter.
+        struct Visitor<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl<'kvs> VisitSource<'kvs> for Visitor<'_, '_> {
+            fn visit_pair(
+                &mut self,
+                key: Key<'kvs>,
+                value: Value<'kvs>,
+            ) -> Result<(), kv::Error> {
+                write!(self.0, " {key}={value}")?;
+                Ok(())
+            }
+        }
+
+        self.0.visit(&mut Visitor(f)).map_err(|_| fmt::Error)
+    }
+}
+
+/// Represents a logger with per-module levels and a ring buffer of recent
+/// lines, printing to the console through `EspLogger`.
+pub struct Logger {
+    console: EspLogger,
+    default_level: Mutex<LevelFilter>,
+    levels: Mutex<Vec<(String, LevelFilter)>>,
+    recent: Mutex<VecDeque<String>>,
+    muted: AtomicBool,
+}
+
+impl Logger {
+    /// Creates a new `Logger` instance.
+    ///
+    /// # Returns
+    /// A logger logging at `Info` level for every module.
+    fn new() -> Self {
+        Self {
+            console: EspLogger::new(),
+            default_level: Mutex::new(LevelFilter::Info),
+            levels: Mutex::new(Vec::new()),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_LINES)),
+            muted: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the level of a module.
+    ///
+    /// The level of a module is the one of its longest configured prefix, or
+    /// the default level.
+    ///
+    /// # Arguments
+    /// * `module` - The module path, e.g. `esp_layground::ble`.
+    ///
+    /// # Returns
+    /// The level of the module.
+    #[must_use]
+    pub fn level(&self, module: &str) -> LevelFilter {
+        lock(&self.levels)
+            .iter()
+            .filter(|(prefix, _)| module.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(|| *lock(&self.default_level), |(_, level)| *level)
+    }
+
+    /// Sets the level of a module and its submodules.
+    ///
+    /// # Arguments
+    /// * `module` - The module path, e.g. `esp_layground::ble`.
+    /// * `level` - The new level.
+    ///
+    /// # Errors
+    /// Returns an error if the console level cannot be updated.
+    pub fn set_level(&self, module: &str, level: LevelFilter) -> Result<()> {
+        self.console.set_target_level(module, level)?;
+
+        let mut levels = lock(&self.levels);
+        levels.retain(|(prefix, _)| prefix != module);
+        levels.push((module.into(), level));
+
+        Ok(())
+    }
+
+    /// Sets the level of the modules without a specific level.
+    ///
+    /// # Arguments
+    /// * `level` - The new default level.
+    ///
+    /// # Errors
+    /// Returns an error if the console level cannot be updated.
+    pub fn set_default_level(&self, level: LevelFilter) -> Result<()> {
+        self.console.set_target_level("*", level)?;
+        *lock(&self.default_level) = level;
+
+        Ok(())
+    }
+
+    /// Returns the most recent log lines, oldest first.
+    ///
+    /// # Returns
+    /// Up to `RECENT_LINES` lines.
+    #[must_use]
+    pub fn recent(&self) -> Vec<String> {
+        lock(&self.recent).iter().cloned().collect()
+    }
+
+    /// Stops or resumes printing to the console, e.g. while it streams data.
+    ///
+    /// The lines are still kept in the ring buffer. The logs of ESP-IDF itself are silenced meanwhile, and their
+    /// default and per-module levels restored on resume.
+    ///
+    /// # Arguments
+    /// * `muted` - `true` to stop printing, `false` to resume.
+    ///
+    /// # Errors
+    /// Returns an error if the levels cannot be restored.
+    pub fn set_muted(&self, muted: bool) -> Result<()> {
+        self.muted.store(muted, Ordering::Relaxed);
+
+        if muted {
+            // Setting the wildcard level also drops the per-module levels.
+            unsafe {
+                esp_log_level_set(c"*".as_ptr(), esp_log_level_t_ESP_LOG_NONE)
+            };
+            return Ok(());
+        }
+
+        let default_level = *lock(&self.default_level);
+        self.console.set_target_level("*", default_level)?;
+        for (module, level) in lock(&self.levels).iter() {
+            self.console.set_target_level(module, *level)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    /// Checks the level of the record against the level of its module.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    /// Logs a record to the console, unless muted, and to the ring buffer.
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let fields = Fields(record.key_values());
+        if !self.muted.load(Ordering::Relaxed) {
+            self.console.log(
+                &Record::builder()
+                    .args(format_args!("{}{}", record.args(), fields))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            );
+        }
+
+        let mut line = String::new();
+        let _ = write!(
+            line,
+            "{} {}: {}{}",
+            record.level(),
+            record.target(),
+            record.args(),
+            fields
+        );
+
+        let mut recent = lock(&self.recent);
+        if recent.len() == RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+/// Installs the logger as the global logger.
+///
+/// # Errors
+/// Returns an error if a global logger is already installed.
+pub fn init() -> Result<()> {
+    let logger = LOGGER.get_or_init(Logger::new);
+
+    log::set_logger(logger).map_err(|e| anyhow!("Cannot set logger: {}", e))?;
+    log::set_max_level(LevelFilter::Trace);
+
+    Ok(())
+}
+
+/// Returns the global logger.
+///
+/// # Errors
+/// Returns an error if `init` was not called.
+pub fn get() -> Result<&'static Logger> {
+    LOGGER
+        .get()
+        .ok_or_else(|| anyhow!("Logger not initialized"))
+}
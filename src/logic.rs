@@ -1,31 +1,35 @@
 use anyhow::{anyhow, Result};
-use log::info;
-use std::{collections::HashSet, fmt};
+use log::{debug, info, trace};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
-    ble::Advertiser,
+    battery::Charge,
+    ble::{Advertiser, Leadership, Rhythm},
+    charge::Level,
     clock::Timer,
-    color::{Rgb, GREEN, RED},
+    color::{Rgb, AMBER, GREEN, RED},
+    config::Store,
+    crash,
+    election::Role,
+    hibernate::{self, Hibernate},
     infra::Switch,
+    journal::Journal,
     light::Led,
-    message::{Dispatcher, Trigger},
+    mesh::Command,
+    message::{Dispatcher, Notifier, Trigger, TriggerSet},
+    metrics::lock,
+    power::{self, Mode},
+    watchdog,
 };
 
-macro_rules! func {
-    () => {{
-        fn f() {}
-        fn type_name_of<T>(_: T) -> &'static str {
-            std::any::type_name::<T>()
-        }
-        let name = type_name_of(f);
-
-        match &name[..name.len() - 3].rfind(':') {
-            Some(pos) => &name[pos + 1..name.len() - 3],
-            None => &name[..name.len() - 3],
-        }
-    }};
-}
-
 /// Represents the state of the application.
 ///
 /// # Variants
@@ -33,8 +37,9 @@ macro_rules! func {
 /// * `Off` - The application is inactive.
 /// * `ActiveDeviceNearby` - An active device is detected nearby.
 /// * `InactiveDeviceNearby` - An inactive device is detected nearby.
-#[derive(PartialEq)]
-enum State {
+#[derive(Clone, Copy, Debug, Eq, IntoPrimitive, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum State {
     On,
     Off,
     ActiveDeviceNearby,
@@ -69,6 +74,79 @@ impl From<&State> for Rgb {
     }
 }
 
+/// Represents the status of the state machine, shared with the control
+/// surfaces.
+pub struct Status {
+    state: AtomicU8,
+    role: Mutex<Option<Role>>,
+    color: Mutex<Option<Rgb>>,
+}
+
+impl Status {
+    /// Creates a new `Status` instance.
+    ///
+    /// # Errors
+    /// Returns an error if the status cannot be initialized.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            state: AtomicU8::new(State::Off.into()),
+            role: Mutex::new(None),
+            color: Mutex::new(None),
+        })
+    }
+
+    /// Returns the state of the state machine.
+    ///
+    /// # Returns
+    /// The state after the last handled triggers.
+    #[must_use]
+    pub fn state(&self) -> State {
+        State::try_from(self.state.load(Ordering::Relaxed)).unwrap_or(State::Off)
+    }
+
+    /// Publishes the state of the state machine.
+    ///
+    /// # Arguments
+    /// * `state` - The new state.
+    fn set_state(&self, state: State) {
+        self.state.store(state.into(), Ordering::Relaxed);
+    }
+
+    /// Returns the role of the device in the election of the coordinator.
+    ///
+    /// # Returns
+    /// The role, `None` if the device takes no part in the election.
+    #[must_use]
+    pub fn role(&self) -> Option<Role> {
+        *lock(&self.role)
+    }
+
+    /// Publishes the role of the device in the election of the coordinator.
+    ///
+    /// # Arguments
+    /// * `role` - The new role.
+    fn set_role(&self, role: Role) {
+        *lock(&self.role) = Some(role);
+    }
+
+    /// Returns the color overriding the one of the state, if any.
+    ///
+    /// # Returns
+    /// The LED color override.
+    #[must_use]
+    pub fn color(&self) -> Option<Rgb> {
+        *lock(&self.color)
+    }
+
+    /// Overrides the LED color. The state machine applies it within a second.
+    ///
+    /// # Arguments
+    /// * `color` - The color to use, or `None` to follow the state.
+    pub fn set_color(&self, color: Option<Rgb>) {
+        *lock(&self.color) = color;
+    }
+}
+
 /// Represents the state machine for the application.
 ///
 /// # Type Parameters
@@ -78,10 +156,36 @@ pub struct StateMachine<'a> {
     led: Led<'a>,
     timer: Timer<'a>,
     dispatcher: Dispatcher,
+    notifier: Notifier,
+    journal: Arc<Journal>,
+    status: Arc<Status>,
+    hibernate: Option<Hibernate>,
+    battery: Option<Arc<Charge>>,
+    store: Option<Store>,
+    rhythm: Option<Arc<Rhythm>>,
+    leadership: Option<Arc<Leadership>>,
     state: State,
+    role: Option<Role>,
+    group_shown: Option<Instant>,
+    probing: bool,
+    started: Instant,
+    last_activity: Instant,
 }
 
 impl<'a> StateMachine<'a> {
+    /// Maximum duration to wait for a trigger before reporting `Trigger::Idle`.
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+    /// Duration without activity after which the system is switched off.
+    const AUTO_OFF: Duration = Duration::from_secs(10 * 60);
+    /// Period of the low battery warning, during the first two seconds of
+    /// which the LED is amber.
+    const BATTERY_WARNING: u64 = 10;
+    /// Duration the LED shows the color of the group after it changes.
+    const GROUP_DISPLAY: Duration = Duration::from_secs(3);
+    /// Period of the leader signal, during the first second of which the LED
+    /// shows the color of the group.
+    const LEADER_SIGNAL: u64 = 5;
+
     /// Creates a new `StateMachine` instance.
     ///
     /// # Arguments
@@ -89,6 +193,9 @@ impl<'a> StateMachine<'a> {
     /// * `led` - An LED controller.
     /// * `timer` - A timer for periodic tasks.
     /// * `dispatcher` - A dispatcher for handling triggers.
+    /// * `notifier` - A notifier to publish system on/off triggers.
+    /// * `journal` - The journal the transitions are recorded in.
+    /// * `status` - The status shared with the control surfaces.
     ///
     /// # Errors
     /// Returns an error if the state machine cannot be initialized.
@@ -97,6 +204,9 @@ impl<'a> StateMachine<'a> {
         led: Led<'a>,
         timer: Timer<'a>,
         dispatcher: Dispatcher,
+        notifier: Notifier,
+        journal: Arc<Journal>,
+        status: Arc<Status>,
     ) -> Result<Self> {
         let state = State::Off;
 
@@ -109,53 +219,248 @@ impl<'a> StateMachine<'a> {
             led,
             timer,
             dispatcher,
+            notifier,
+            journal,
+            status,
+            hibernate: None,
+            battery: None,
+            store: None,
+            rhythm: None,
+            leadership: None,
             state,
+            role: None,
+            group_shown: None,
+            probing: false,
+            started: Instant::now(),
+            last_activity: Instant::now(),
         })
     }
 
-    /// Handles the button pressed trigger.
+    /// Lets the device deep sleep once the system has been off for long enough.
     ///
-    /// # Errors
-    /// Returns an error if the advertiser state cannot be toggled.
-    fn handle_button_pressed(&mut self) -> Result<()> {
-        info!("{}", func!());
+    /// # Arguments
+    /// * `hibernate` - The deep sleep policy.
+    ///
+    /// # Returns
+    /// The state machine, with deep sleep enabled.
+    #[must_use]
+    pub fn with_hibernate(mut self, hibernate: Hibernate) -> Self {
+        self.hibernate = Some(hibernate);
+        self
+    }
+
+    /// Lets the state machine warn about and advertise the battery level.
+    ///
+    /// # Arguments
+    /// * `battery` - The charge published by the battery monitor.
+    ///
+    /// # Returns
+    /// The state machine, with battery monitoring enabled.
+    #[must_use]
+    pub fn with_battery(mut self, battery: Arc<Charge>) -> Self {
+        self.battery = Some(battery);
+        self
+    }
+
+    /// Lets the state machine save the settings changed with the button, e.g.
+    /// the group.
+    ///
+    /// # Arguments
+    /// * `store` - The configuration store.
+    ///
+    /// # Returns
+    /// The state machine, with the settings kept across restarts.
+    #[must_use]
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Lets the state machine blink in unison with the devices of the group.
+    ///
+    /// # Arguments
+    /// * `rhythm` - The blinking rhythm of the device, shared with the scanner.
+    ///
+    /// # Returns
+    /// The state machine, with the blinking synchronised.
+    #[must_use]
+    pub fn with_rhythm(mut self, rhythm: Arc<Rhythm>) -> Self {
+        self.rhythm = Some(rhythm);
+        self
+    }
+
+    /// Lets the state machine act on the election of the coordinator of the
+    /// group.
+    ///
+    /// # Arguments
+    /// * `leadership` - The election, shared with the scanner.
+    ///
+    /// # Returns
+    /// The state machine, taking part in the election.
+    #[must_use]
+    pub fn with_leadership(mut self, leadership: Arc<Leadership>) -> Self {
+        self.leadership = Some(leadership);
+        self
+    }
 
+    /// Switches the system on if it is off, and off otherwise.
+    ///
+    /// # Errors
+    /// Returns an error if the advertiser state cannot be toggled or if the
+    /// new system state cannot be published.
+    fn toggle_system(&mut self) -> Result<()> {
+        // A pending scan after a timer wakeup no longer decides anything.
+        self.probing = false;
         self.state = match self.state {
             State::Off => State::On,
             _ => State::Off,
         };
 
+        self.notifier.notify(match self.state {
+            State::Off => Trigger::SystemOff,
+            _ => Trigger::SystemOn,
+        })?;
+
         self.advertiser.toggle()
     }
 
+    /// Handles the button pressed trigger.
+    ///
+    /// The devices of the group are asked to follow through the mesh, if the
+    /// device takes part in it.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be toggled or if the command
+    /// cannot be sent.
+    fn handle_button_pressed(&mut self) -> Result<()> {
+        debug!("button pressed");
+
+        self.toggle_system()?;
+        self.advertiser.broadcast(match self.state {
+            State::Off => Command::Off,
+            _ => Command::On,
+        })
+    }
+
+    /// Handles the mesh on and off triggers.
+    ///
+    /// # Arguments
+    /// * `command` - What the mesh asks for.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be toggled.
+    fn handle_mesh_command(&mut self, command: Command) -> Result<()> {
+        debug!(command:? = command; "mesh command received");
+
+        if (command == Command::On) == (self.state == State::Off) {
+            self.toggle_system()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles the button held trigger.
+    ///
+    /// The device moves to the next group, whose color the LED shows for a
+    /// while.
+    ///
+    /// # Errors
+    /// Returns an error if the group cannot be advertised or saved.
+    fn handle_button_held(&mut self) -> Result<()> {
+        let group = self.advertiser.group().next();
+        info!(group:% = group; "switching group");
+
+        self.advertiser.set_group(group)?;
+        self.group_shown = Some(Instant::now());
+        if let Some(store) = self.store.as_mut() {
+            let mut config = store.load()?;
+            config.group = group;
+            store.save(&config)?;
+        }
+
+        Ok(())
+    }
+
     /// Handles the timer ticked trigger.
     ///
+    /// If the blinking is synchronised, the LED follows the rhythm rather than
+    /// toggling, and the timer is aligned with its next edge. Otherwise, the
+    /// timer ticks at its own period again.
+    ///
     /// # Errors
-    /// Returns an error if the LED state cannot be toggled.
+    /// Returns an error if the LED state cannot be toggled, if the timer cannot
+    /// be aligned or resumed or if the beat cannot be advertised.
     fn handle_timer_ticked(&mut self) -> Result<()> {
-        info!("{}", func!());
+        trace!("timer ticked");
+        self.timer.resume()?;
 
-        match self.state {
-            State::ActiveDeviceNearby | State::InactiveDeviceNearby => {
-                self.led.toggle()
-            } // Blinking
-            _ => Ok(()),
+        if !matches!(
+            self.state,
+            State::ActiveDeviceNearby | State::InactiveDeviceNearby
+        ) {
+            return Ok(());
+        }
+        // Blinking
+        let Some(rhythm) = self.rhythm.as_ref() else {
+            return self.led.toggle();
+        };
+
+        let (on, next) = rhythm.edge();
+        self.timer.align(next)?;
+        if on {
+            self.led.on()?;
+            self.advertiser.beat()
+        } else {
+            self.led.off()
         }
     }
 
+    /// Ends the scan requested after a timer wakeup, if it is pending.
+    ///
+    /// The system is switched on if an active device is nearby, so that the
+    /// device joins it, and the device deep sleeps again right away otherwise.
+    ///
+    /// # Arguments
+    /// * `active` - Whether the scan found an active device.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched on.
+    fn end_probe(&mut self, active: bool) -> Result<()> {
+        if !std::mem::take(&mut self.probing) {
+            return Ok(());
+        }
+
+        if active {
+            info!("active device nearby after timer wakeup, switching on");
+            return self.toggle_system();
+        }
+        if let Some(hibernate) = self.hibernate.as_mut() {
+            hibernate.snooze(Duration::ZERO);
+        }
+
+        Ok(())
+    }
+
     /// Handles the device found active trigger.
-    fn handle_device_found_active(&mut self) {
-        info!("{}", func!());
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched on after a timer
+    /// wakeup.
+    fn handle_device_found_active(&mut self) -> Result<()> {
+        debug!("active device found");
 
-        self.state = match self.state {
-            State::Off => State::Off,
-            _ => State::ActiveDeviceNearby,
-        };
+        match self.state {
+            State::Off => self.end_probe(true),
+            _ => {
+                self.state = State::ActiveDeviceNearby;
+                Ok(())
+            }
+        }
     }
 
     /// Handles the device found inactive trigger.
     fn handle_device_found_inactive(&mut self) {
-        info!("{}", func!());
+        debug!("inactive device found");
 
         self.state = match self.state {
             State::Off => State::Off,
@@ -163,9 +468,28 @@ impl<'a> StateMachine<'a> {
         };
     }
 
+    /// Handles the beacon found trigger.
+    ///
+    /// A beacon of the deployment counts as an active device.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched on after a timer
+    /// wakeup.
+    fn handle_beacon_found(&mut self) -> Result<()> {
+        debug!("beacon found");
+
+        match self.state {
+            State::Off => self.end_probe(true),
+            _ => {
+                self.state = State::ActiveDeviceNearby;
+                Ok(())
+            }
+        }
+    }
+
     /// Handles the device not found trigger.
     fn handle_device_not_found(&mut self) {
-        info!("{}", func!());
+        trace!("no device found");
 
         self.state = match self.state {
             State::Off => State::Off,
@@ -173,6 +497,50 @@ impl<'a> StateMachine<'a> {
         };
     }
 
+    /// Handles the sleep timer expired trigger.
+    ///
+    /// The device woke up on its own: it scans once, and the result of the
+    /// scan decides whether the system switches on or the device sleeps again.
+    ///
+    /// # Errors
+    /// Returns an error if the scan cannot be requested.
+    fn handle_sleep_timer_expired(&mut self) -> Result<()> {
+        debug!("woke up from deep sleep on timer");
+
+        // Sleep again if the scan does not end in time.
+        if let Some(hibernate) = self.hibernate.as_mut() {
+            hibernate.snooze(Hibernate::GRACE);
+        }
+        self.probing = self.state == State::Off;
+
+        self.notifier.notify(Trigger::ScanRequested)
+    }
+
+    /// Handles the battery low trigger.
+    fn handle_battery_low() {
+        debug!("battery low");
+    }
+
+    /// Handles the battery critical trigger.
+    ///
+    /// The system is switched off to save the remaining charge, and the device
+    /// deep sleeps right away if it can.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched off.
+    fn handle_battery_critical(&mut self) -> Result<()> {
+        debug!("battery critical");
+
+        if self.state != State::Off {
+            self.toggle_system()?;
+        }
+        if let Some(hibernate) = self.hibernate.as_mut() {
+            hibernate.snooze(Duration::ZERO);
+        }
+
+        Ok(())
+    }
+
     /// Handles a set of triggers.
     ///
     /// # Arguments
@@ -180,47 +548,240 @@ impl<'a> StateMachine<'a> {
     ///
     /// # Errors
     /// Returns an error if any trigger handling fails.
-    fn handle_triggers(&mut self, triggers: &HashSet<Trigger>) -> Result<()> {
-        info!(
-            "{}: triggers: {:?}, state: {}",
-            func!(),
-            triggers,
-            self.state
-        );
-
-        if triggers.contains(&Trigger::ButtonPressed) {
+    fn handle_triggers(&mut self, triggers: &TriggerSet) -> Result<()> {
+        trace!(triggers:? = triggers, state:% = self.state; "handling triggers");
+
+        // A low battery is only reported, it must not hide the other triggers.
+        if triggers.contains(Trigger::BatteryLow) {
+            Self::handle_battery_low();
+        }
+
+        // A critical battery is rare and takes precedence.
+        if triggers.contains(Trigger::BatteryCritical) {
+            self.handle_battery_critical()?;
+        } else if triggers.contains(Trigger::ButtonHeld) {
+            self.handle_button_held()?;
+        } else if triggers.contains(Trigger::ButtonPressed) {
             self.handle_button_pressed()?;
-        } else if triggers.contains(&Trigger::DeviceFoundActive) {
-            self.handle_device_found_active();
-        } else if triggers.contains(&Trigger::DeviceFoundInactive) {
+        } else if triggers.contains(Trigger::MeshOn) {
+            self.handle_mesh_command(Command::On)?;
+        } else if triggers.contains(Trigger::MeshOff) {
+            self.handle_mesh_command(Command::Off)?;
+        } else if triggers.contains(Trigger::DeviceFoundActive) {
+            self.handle_device_found_active()?;
+        } else if triggers.contains(Trigger::DeviceFoundInactive) {
             self.handle_device_found_inactive();
-        } else if triggers.contains(&Trigger::DeviceNotFound) {
+            self.end_probe(false)?;
+        } else if triggers.contains(Trigger::BeaconFound) {
+            self.handle_beacon_found()?;
+        } else if triggers.contains(Trigger::DeviceNotFound) {
             self.handle_device_not_found();
-        } else if triggers.contains(&Trigger::TimerTicked) {
+            self.end_probe(false)?;
+        } else if triggers.contains(Trigger::TimerTicked) {
             self.handle_timer_ticked()?;
-        } else {
+        } else if triggers.contains(Trigger::SleepTimerExpired) {
+            self.handle_sleep_timer_expired()?;
+        } else if triggers.contains(Trigger::Idle) {
+            trace!("idle");
+        } else if !triggers.contains(Trigger::BatteryLow) {
             Err(anyhow!("Unknown triggers: {:?}", triggers))?;
         }
 
         Ok(())
     }
 
+    /// Switches the system off if nothing happened for `AUTO_OFF`.
+    ///
+    /// Only the button and nearby devices count as activity, as the timer and
+    /// the scanner keep notifying as long as the system is on.
+    ///
+    /// # Arguments
+    /// * `triggers` - The set of triggers that were just handled.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched off.
+    fn handle_inactivity(&mut self, triggers: &TriggerSet) -> Result<()> {
+        let activity = Trigger::ButtonPressed
+            | Trigger::ButtonHeld
+            | Trigger::MeshOn
+            | Trigger::MeshOff
+            | Trigger::DeviceFoundActive
+            | Trigger::DeviceFoundInactive
+            | Trigger::BeaconFound;
+        if triggers.intersects(activity) {
+            self.last_activity = Instant::now();
+        }
+
+        if self.state != State::Off && self.last_activity.elapsed() >= Self::AUTO_OFF
+        {
+            info!(after:? = Self::AUTO_OFF; "switching off after inactivity");
+            self.toggle_system()?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the role of the device from the election, if it takes part in
+    /// it.
+    fn update_role(&mut self) {
+        let Some(leadership) = self.leadership.as_ref() else {
+            return;
+        };
+
+        let role = leadership.role();
+        if self.role != Some(role) {
+            info!(
+                role:% = role,
+                leader:? = leadership.leader().map(|leader| leader.to_string());
+                "role changed"
+            );
+            self.role = Some(role);
+            self.status.set_role(role);
+        }
+    }
+
+    /// Deep sleeps if the system has been off for long enough.
+    ///
+    /// # Errors
+    /// Returns an error if the journal cannot be saved or if the device cannot
+    /// deep sleep. Does not return otherwise if the device deep sleeps.
+    fn handle_hibernate(&mut self) -> Result<()> {
+        let Some(hibernate) = self.hibernate.as_mut() else {
+            return Ok(());
+        };
+
+        hibernate.update(self.state);
+        if hibernate.due() {
+            self.journal.flush()?;
+            self.led.off()?;
+            hibernate.enter(self.state)?;
+        }
+
+        Ok(())
+    }
+
+    /// Restores the state the system was in before the last deep sleep.
+    ///
+    /// # Errors
+    /// Returns an error if the system cannot be switched on.
+    fn restore(&mut self) -> Result<()> {
+        if let Some(state) = hibernate::restore() {
+            info!(state:% = state; "restoring state after deep sleep");
+            if state != State::Off {
+                self.toggle_system()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the color of the group, if it just changed.
+    ///
+    /// # Returns
+    /// The color of the group for `GROUP_DISPLAY` after it changed.
+    fn group_color(&self) -> Option<Rgb> {
+        self.group_shown
+            .filter(|shown| shown.elapsed() < Self::GROUP_DISPLAY)
+            .map(|_| self.advertiser.group().color())
+    }
+
+    /// Returns the color warning about the battery, if any.
+    ///
+    /// # Returns
+    /// Amber when the battery is critical, or periodically when it is low.
+    fn battery_warning(&self) -> Option<Rgb> {
+        let flash = self.started.elapsed().as_secs() % Self::BATTERY_WARNING < 2;
+
+        match self.battery.as_ref()?.level() {
+            Level::Normal => None,
+            Level::Low => flash.then_some(AMBER),
+            Level::Critical => Some(AMBER),
+        }
+    }
+
+    /// Returns the signal of the leader, if the device leads.
+    ///
+    /// # Returns
+    /// The color of the group during the first second of every
+    /// `LEADER_SIGNAL` seconds, while the system is on and the device leads.
+    fn leader_signal(&self) -> Option<Rgb> {
+        let flash = self.started.elapsed().as_secs() % Self::LEADER_SIGNAL == 0;
+
+        (flash && self.state != State::Off && self.role == Some(Role::Leader))
+            .then(|| self.advertiser.group().color())
+    }
+
+    /// Returns whether the state machine is idle, i.e. whether it only waits
+    /// for triggers and does not need the CPU at full speed.
+    ///
+    /// # Returns
+    /// `true` unless the LED is blinking for a nearby device.
+    fn idle(&self) -> bool {
+        matches!(self.state, State::On | State::Off)
+    }
+
     /// Runs the state machine.
     ///
     /// # Errors
     /// Returns an error if the state machine encounters an issue during execution.
     pub fn run(&mut self) -> Result<()> {
+        self.restore()?;
+
         loop {
-            let triggers = self.dispatcher.collect()?;
+            watchdog::feed()?;
+            crash::settle();
+
+            let triggers = self.dispatcher.collect_timeout(Self::IDLE_TIMEOUT)?;
+            let previous = self.state;
             self.handle_triggers(&triggers)?;
+            self.handle_inactivity(&triggers)?;
+            self.update_role();
 
-            self.led.set_color((&self.state).into())?;
+            if self.state != previous {
+                info!(
+                    from:% = previous,
+                    to:% = self.state,
+                    triggers:? = triggers;
+                    "state transition"
+                );
+            }
+            // The timer and the scanner notify continuously, keep the journal
+            // for what matters: the devices found only count when they change
+            // the state.
+            let notable = Trigger::ButtonPressed
+                | Trigger::ButtonHeld
+                | Trigger::MeshOn
+                | Trigger::MeshOff
+                | Trigger::BatteryLow
+                | Trigger::BatteryCritical;
+            if self.state != previous || triggers.intersects(notable) {
+                self.journal.record(previous, self.state, triggers)?;
+            }
+
+            self.status.set_state(self.state);
+            let color = self
+                .status
+                .color()
+                .or_else(|| self.group_color())
+                .or_else(|| self.battery_warning())
+                .or_else(|| self.leader_signal())
+                .unwrap_or_else(|| (&self.state).into());
+            self.led.set_color(color)?;
             if self.state == State::On || self.state == State::Off {
                 self.timer.off()?;
                 self.led.on()?;
             } else {
                 self.timer.on()?;
             }
+
+            if let Some(battery) = self.battery.as_ref() {
+                self.advertiser.set_battery(battery.percent())?;
+                self.advertiser.set_voltage(Some(battery.millivolts()));
+            }
+            self.advertiser.refresh()?;
+
+            power::set_mode(Mode::select(self.state != State::Off, self.idle()))?;
+            self.handle_hibernate()?;
         }
     }
 }
@@ -1,24 +1,112 @@
 use anyhow::{anyhow, Result};
-use esp_idf_hal::{delay::BLOCK, task::notification};
+use esp_idf_hal::{
+    delay::{TickType, BLOCK, NON_BLOCK},
+    task::notification,
+};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::{collections::HashSet, convert::TryFrom, num::NonZeroU32, sync::Arc};
+use std::{
+    convert::TryFrom,
+    num::NonZeroU32,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
 
-/// Represents various triggers that can occur in the system.
-///
-/// # Variants
-/// * `ButtonPressed` - Triggered when a button is pressed.
-/// * `TimerTicked` - Triggered when a timer ticks.
-/// * `DeviceFoundActive` - Triggered when an active device is found.
-/// * `DeviceFoundInactive` - Triggered when an inactive device is found.
-/// * `DeviceNotFound` - Triggered when no device is found.
-#[derive(Debug, Eq, Hash, IntoPrimitive, PartialEq, TryFromPrimitive)]
-#[repr(u32)]
-pub enum Trigger {
-    ButtonPressed = 1 << 0,
-    TimerTicked = 1 << 1,
-    DeviceFoundActive = 1 << 2,
-    DeviceFoundInactive = 1 << 3,
-    DeviceNotFound = 1 << 4,
+use crate::{
+    flags::{flags, Flag, FlagSet},
+    metrics,
+};
+
+/// Maximum number of subscribers a `Bus` can hold.
+const MAX_SUBSCRIBERS: usize = 8;
+
+flags! {
+    /// Represents various triggers that can occur in the system.
+    ///
+    /// # Variants
+    /// * `ButtonPressed` - Triggered when a button is pressed.
+    /// * `TimerTicked` - Triggered when a timer ticks.
+    /// * `DeviceFoundActive` - Triggered when an active device is found.
+    /// * `DeviceFoundInactive` - Triggered when an inactive device is found.
+    /// * `DeviceNotFound` - Triggered when no device is found.
+    /// * `SystemOn` - Triggered when the system is switched on.
+    /// * `SystemOff` - Triggered when the system is switched off.
+    /// * `Idle` - Never published, reported by `Dispatcher::collect_timeout` when
+    ///   no trigger was received in time.
+    /// * `ScanRequested` - Triggered when an immediate BLE scan is requested.
+    /// * `SleepTimerExpired` - Triggered when the device wakes up from deep sleep
+    ///   on its timer.
+    /// * `BatteryLow` - Triggered when the battery becomes low.
+    /// * `BatteryCritical` - Triggered when the battery becomes critical.
+    /// * `ButtonHeld` - Triggered when the button is held down, instead of
+    ///   `ButtonPressed`.
+    /// * `MeshOn` - Triggered when the mesh asks the system to switch on.
+    /// * `MeshOff` - Triggered when the mesh asks the system to switch off.
+    /// * `BeaconFound` - Triggered when a beacon of the deployment is found and
+    ///   no device is.
+    #[derive(
+        Clone, Copy, Debug, Eq, Hash, IntoPrimitive, PartialEq, TryFromPrimitive,
+    )]
+    #[repr(u32)]
+    pub enum Trigger {
+        ButtonPressed,
+        TimerTicked,
+        DeviceFoundActive,
+        DeviceFoundInactive,
+        DeviceNotFound,
+        SystemOn,
+        SystemOff,
+        Idle,
+        ScanRequested,
+        SleepTimerExpired,
+        BatteryLow,
+        BatteryCritical,
+        ButtonHeld,
+        MeshOn,
+        MeshOff,
+        BeaconFound,
+    }
+}
+
+/// Represents a set of triggers.
+pub type TriggerSet = FlagSet<Trigger, { Trigger::COUNT }>;
+
+impl Trigger {
+    /// Returns the triggers caused by the outside world, i.e. by the user or
+    /// by the nearby devices, as opposed to the ones the firmware publishes for
+    /// itself.
+    ///
+    /// # Returns
+    /// The external triggers.
+    #[must_use]
+    pub fn external() -> TriggerSet {
+        Self::ButtonPressed
+            | Self::ButtonHeld
+            | Self::DeviceFoundActive
+            | Self::DeviceFoundInactive
+            | Self::DeviceNotFound
+            | Self::MeshOn
+            | Self::MeshOff
+            | Self::BeaconFound
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = anyhow::Error;
+
+    /// Parses a trigger from its name, ignoring case.
+    ///
+    /// # Errors
+    /// Returns an error if no trigger has this name.
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|trigger| format!("{trigger:?}").eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow!("Unknown trigger: {}", s))
+    }
 }
 
 impl TryFrom<Trigger> for NonZeroU32 {
@@ -29,29 +117,111 @@ impl TryFrom<Trigger> for NonZeroU32 {
     type Error = anyhow::Error;
 
     fn try_from(trigger: Trigger) -> Result<Self, Self::Error> {
-        NonZeroU32::new(trigger.into())
+        NonZeroU32::new(trigger.bit())
             .ok_or_else(|| anyhow!("Invalid value for NonZeroU32"))
     }
 }
 
-/// Represents a notifier for sending notifications.
-pub struct Notifier {
+/// Represents a subscription to a set of triggers.
+///
+/// # Fields
+/// * `interest` - The triggers the subscriber listens to.
+/// * `notifier` - The notifier of the subscriber's task.
+struct Subscription {
+    interest: TriggerSet,
     notifier: Arc<notification::Notifier>,
 }
 
+/// Represents the shared part of a `Bus`.
+///
+/// Subscriptions are stored in write-once slots so that publishing never needs
+/// to take a lock, which keeps `Notifier::notify` usable from interrupts.
+struct Subscriptions {
+    slots: [OnceLock<Subscription>; MAX_SUBSCRIBERS],
+    len: AtomicUsize,
+}
+
+/// Represents a publish/subscribe bus for triggers.
+///
+/// Any component can publish triggers through a `Notifier`, and any task can
+/// subscribe to a subset of the triggers through its own `Dispatcher`.
+#[derive(Clone)]
+pub struct Bus {
+    subscriptions: Arc<Subscriptions>,
+}
+
+impl Bus {
+    /// Creates a new `Bus` instance.
+    ///
+    /// # Errors
+    /// Returns an error if the bus cannot be initialized.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            subscriptions: Arc::new(Subscriptions {
+                slots: [const { OnceLock::new() }; MAX_SUBSCRIBERS],
+                len: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    /// Returns a `Notifier` publishing on the bus.
+    ///
+    /// # Errors
+    /// Returns an error if the notifier cannot be created.
+    pub fn notifier(&self) -> Result<Notifier> {
+        Notifier::new(self.clone())
+    }
+
+    /// Subscribes the current task to a set of triggers.
+    ///
+    /// The returned `Dispatcher` is bound to the calling task, so this must be
+    /// called from the thread that will collect the triggers.
+    ///
+    /// # Arguments
+    /// * `triggers` - The triggers to subscribe to.
+    ///
+    /// # Errors
+    /// Returns an error if the maximum number of subscribers is reached.
+    pub fn subscribe(&self, triggers: TriggerSet) -> Result<Dispatcher> {
+        let index = self
+            .subscriptions
+            .len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < MAX_SUBSCRIBERS).then_some(len + 1)
+            })
+            .map_err(|_| anyhow!("Too many subscribers"))?;
+
+        let dispatcher = Dispatcher::new();
+
+        self.subscriptions.slots[index]
+            .set(Subscription {
+                interest: triggers,
+                notifier: dispatcher.notification.notifier(),
+            })
+            .map_err(|_| anyhow!("Subscription slot already taken"))?;
+
+        Ok(dispatcher)
+    }
+}
+
+/// Represents a notifier for publishing triggers on a `Bus`.
+pub struct Notifier {
+    bus: Bus,
+}
+
 impl Notifier {
     /// Creates a new `Notifier` instance.
     ///
     /// # Arguments
-    /// * `notifier` - An `Arc` of a `notification::Notifier`.
+    /// * `bus` - The bus to publish on.
     ///
     /// # Errors
     /// Returns an error if the notifier cannot be initialized.
-    pub fn new(notifier: Arc<notification::Notifier>) -> Result<Self> {
-        Ok(Self { notifier })
+    pub fn new(bus: Bus) -> Result<Self> {
+        Ok(Self { bus })
     }
 
-    /// Sends a notification for a given trigger.
+    /// Sends a notification for a given trigger to every interested subscriber.
     ///
     /// # Arguments
     /// * `trigger` - The trigger to notify.
@@ -59,68 +229,97 @@ impl Notifier {
     /// # Errors
     /// Returns an error if the notification fails.
     pub fn notify(&self, trigger: Trigger) -> Result<()> {
-        unsafe {
-            self.notifier.notify_and_yield(trigger.try_into()?);
+        let bits = NonZeroU32::try_from(trigger)?;
+        metrics::TRIGGERS.inc(trigger);
+
+        for subscription in self
+            .bus
+            .subscriptions
+            .slots
+            .iter()
+            .filter_map(OnceLock::get)
+        {
+            if subscription.interest.contains(trigger) {
+                unsafe {
+                    subscription.notifier.notify_and_yield(bits);
+                }
+            }
         }
 
         Ok(())
     }
 }
 
-/// Represents a dispatcher for collecting triggers.
+/// Represents a dispatcher for collecting the triggers a task subscribed to.
 pub struct Dispatcher {
     notification: notification::Notification,
 }
 
 impl Dispatcher {
-    /// Creates a new `Dispatcher` instance.
+    /// Creates a new `Dispatcher` instance bound to the current task.
+    fn new() -> Self {
+        Self {
+            notification: notification::Notification::new(),
+        }
+    }
+
+    /// Waits for triggers from the notification system.
+    ///
+    /// # Arguments
+    /// * `timeout` - The number of ticks to wait for.
+    ///
+    /// # Returns
+    /// A `TriggerSet` of collected triggers.
+    fn wait(&self, timeout: u32) -> TriggerSet {
+        self.notification
+            .wait(timeout)
+            .map_or_else(TriggerSet::empty, |notification| {
+                TriggerSet::from_bits(notification.get())
+            })
+    }
+
+    /// Collects triggers from the notification system, blocking until at least
+    /// one is received.
+    ///
+    /// # Returns
+    /// A `TriggerSet` of collected triggers.
     ///
     /// # Errors
-    /// Returns an error if the dispatcher cannot be initialized.
-    pub fn new() -> Result<Self> {
-        Ok(Self {
-            notification: notification::Notification::new(),
-        })
+    /// Returns an error if the collection fails.
+    pub fn collect(&self) -> Result<TriggerSet> {
+        Ok(self.wait(BLOCK))
     }
 
-    /// Returns a `Notifier` associated with the dispatcher.
+    /// Collects the pending triggers without blocking.
+    ///
+    /// # Returns
+    /// A `TriggerSet` of collected triggers, empty if none is pending.
     ///
     /// # Errors
-    /// Returns an error if the notifier cannot be created.
-    pub fn notifier(&self) -> Result<Notifier> {
-        Notifier::new(self.notification.notifier())
+    /// Returns an error if the collection fails.
+    pub fn try_collect(&self) -> Result<TriggerSet> {
+        Ok(self.wait(NON_BLOCK))
     }
 
-    /// Collects triggers from the notification system.
+    /// Collects triggers from the notification system, blocking for at most
+    /// `timeout`.
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum duration to wait for.
     ///
     /// # Returns
-    /// A `HashSet` of collected triggers.
+    /// A `TriggerSet` of collected triggers, containing only `Trigger::Idle` if
+    /// none was received before the timeout.
     ///
     /// # Errors
     /// Returns an error if the collection fails.
-    pub fn collect(&self) -> Result<HashSet<Trigger>> {
-        let mut set = HashSet::new();
-
-        let notification = self.notification.wait(BLOCK);
-        if let Some(notification) = notification {
-            let notification = notification.get();
-            if notification & u32::from(Trigger::ButtonPressed) != 0 {
-                set.insert(Trigger::ButtonPressed);
-            }
-            if notification & u32::from(Trigger::TimerTicked) != 0 {
-                set.insert(Trigger::TimerTicked);
-            }
-            if notification & u32::from(Trigger::DeviceFoundActive) != 0 {
-                set.insert(Trigger::DeviceFoundActive);
-            }
-            if notification & u32::from(Trigger::DeviceFoundInactive) != 0 {
-                set.insert(Trigger::DeviceFoundInactive);
-            }
-            if notification & u32::from(Trigger::DeviceNotFound) != 0 {
-                set.insert(Trigger::DeviceNotFound);
-            }
-        }
+    pub fn collect_timeout(&self, timeout: Duration) -> Result<TriggerSet> {
+        let triggers = self.wait(TickType::from(timeout).ticks());
 
-        Ok(set)
+        Ok(if triggers.is_empty() {
+            Trigger::Idle.into()
+        } else {
+            triggers
+        })
     }
 }
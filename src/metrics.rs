@@ -0,0 +1,534 @@
+use esp_idf_hal::sys::{esp_get_free_heap_size, esp_get_minimum_free_heap_size};
+use std::{
+    fmt::{self, Write},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+};
+
+use crate::{flags::Flag, message::Trigger, watchdog};
+
+/// Triggers published on the bus, per kind.
+pub static TRIGGERS: PerFlag<Trigger, { Trigger::COUNT }> =
+    PerFlag::new("triggers_total", "Triggers published on the bus.", "kind");
+
+/// BLE scans performed.
+pub static SCANS: Counter = Counter::new("ble_scans_total", "BLE scans performed.");
+
+/// BLE scans that found a peer.
+pub static SCAN_HITS: Counter =
+    Counter::new("ble_scan_hits_total", "BLE scans that found a peer.");
+
+/// Percentage of the BLE scans that found a peer, updated by `sample`.
+pub static SCAN_HIT_RATE: Gauge = Gauge::new(
+    "ble_scan_hit_percent",
+    "Percentage of the BLE scans that found a peer.",
+);
+
+/// Duration of the BLE scans.
+pub static SCAN_DURATION: Histogram<5> = Histogram::new(
+    "ble_scan_duration_ms",
+    "Duration of the BLE scans, in milliseconds.",
+    [250, 500, 1000, 2000, 5000],
+);
+
+/// Authenticated advertisements rejected as forged or replayed.
+pub static SCAN_REJECTS: Counter = Counter::new(
+    "ble_scan_rejects_total",
+    "Advertisements rejected as forged or replayed.",
+);
+
+/// Mesh messages relayed to the devices out of range of their origin.
+pub static MESH_RELAYS: Counter =
+    Counter::new("ble_mesh_relays_total", "Mesh messages relayed.");
+
+/// Frames sent to the LED.
+pub static LED_FRAMES: Counter =
+    Counter::new("led_frames_total", "Frames sent to the LED.");
+
+/// Button presses.
+pub static BUTTON_PRESSES: Counter =
+    Counter::new("button_presses_total", "Button presses.");
+
+/// Interval between the BLE scans, as adapted by the schedule.
+pub static SCAN_INTERVAL: Gauge = Gauge::new(
+    "ble_scan_interval_ms",
+    "Interval between the BLE scans, in milliseconds.",
+);
+
+/// Mutexes found locked by another thread, see `lock`.
+pub static CONTENTIONS: Counter = Counter::new(
+    "mutex_contentions_total",
+    "Mutexes found locked by another thread.",
+);
+
+/// Free heap, updated by `sample`.
+pub static HEAP_FREE: Gauge = Gauge::new("heap_free_bytes", "Free heap.");
+
+/// Lowest free heap since boot, updated by `sample`.
+pub static HEAP_LOW_WATER: Gauge =
+    Gauge::new("heap_low_water_bytes", "Lowest free heap since boot.");
+
+/// Filtered battery voltage, 0 without a battery monitor.
+pub static BATTERY_VOLTAGE: Gauge =
+    Gauge::new("battery_millivolts", "Filtered battery voltage.");
+
+/// Estimated state of charge of the battery, 0 without a battery monitor.
+pub static BATTERY_CHARGE: Gauge = Gauge::new(
+    "battery_charge_percent",
+    "Estimated state of charge of the battery.",
+);
+
+/// Lowest free stack of the watched tasks, read at exposition time.
+static STACKS: Stacks = Stacks {
+    name: "task_stack_high_water_bytes",
+    help: "Lowest free stack of the watched tasks since their start.",
+};
+
+/// Every metric, in exposition order.
+static METRICS: [&dyn Metric; 16] = [
+    &TRIGGERS,
+    &SCANS,
+    &SCAN_HITS,
+    &SCAN_HIT_RATE,
+    &SCAN_DURATION,
+    &SCAN_INTERVAL,
+    &SCAN_REJECTS,
+    &MESH_RELAYS,
+    &LED_FRAMES,
+    &BUTTON_PRESSES,
+    &CONTENTIONS,
+    &HEAP_FREE,
+    &HEAP_LOW_WATER,
+    &BATTERY_VOLTAGE,
+    &BATTERY_CHARGE,
+    &STACKS,
+];
+
+/// A trait representing a metric that can be exposed.
+trait Metric: Sync {
+    /// Writes the metric in the Prometheus text format.
+    ///
+    /// # Arguments
+    /// * `out` - The string to write to.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    fn prometheus(&self, out: &mut String) -> fmt::Result;
+
+    /// Writes the metric as a JSON object member.
+    ///
+    /// # Arguments
+    /// * `out` - The string to write to.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    fn json(&self, out: &mut String) -> fmt::Result;
+}
+
+/// Writes the `HELP` and `TYPE` lines of a metric.
+///
+/// # Arguments
+/// * `out` - The string to write to.
+/// * `name` - The name of the metric.
+/// * `help` - The description of the metric.
+/// * `kind` - The Prometheus type of the metric.
+///
+/// # Errors
+/// Returns an error if writing fails.
+fn header(out: &mut String, name: &str, help: &str, kind: &str) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {kind}")
+}
+
+/// Represents a monotonic counter.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU32,
+}
+
+impl Counter {
+    /// Creates a new `Counter` instance.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the counter.
+    /// * `help` - The description of the counter.
+    #[must_use]
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU32::new(0),
+        }
+    }
+
+    /// Increments the counter.
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the value of the counter.
+    ///
+    /// # Returns
+    /// The number of increments, wrapping at `u32::MAX`.
+    #[must_use]
+    pub fn get(&self) -> u32 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Counter {
+    /// Writes the counter as a single sample.
+    fn prometheus(&self, out: &mut String) -> fmt::Result {
+        header(out, self.name, self.help, "counter")?;
+        writeln!(out, "{} {}", self.name, self.get())
+    }
+
+    /// Writes the counter as a number.
+    fn json(&self, out: &mut String) -> fmt::Result {
+        write!(out, "\"{}\":{}", self.name, self.get())
+    }
+}
+
+/// Represents a value that can go up and down.
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU32,
+}
+
+impl Gauge {
+    /// Creates a new `Gauge` instance.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the gauge.
+    /// * `help` - The description of the gauge.
+    #[must_use]
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU32::new(0),
+        }
+    }
+
+    /// Sets the value of the gauge.
+    ///
+    /// # Arguments
+    /// * `value` - The new value.
+    pub fn set(&self, value: u32) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    /// Returns the value of the gauge.
+    ///
+    /// # Returns
+    /// The last value set.
+    #[must_use]
+    pub fn get(&self) -> u32 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Gauge {
+    /// Writes the gauge as a single sample.
+    fn prometheus(&self, out: &mut String) -> fmt::Result {
+        header(out, self.name, self.help, "gauge")?;
+        writeln!(out, "{} {}", self.name, self.get())
+    }
+
+    /// Writes the gauge as a number.
+    fn json(&self, out: &mut String) -> fmt::Result {
+        write!(out, "\"{}\":{}", self.name, self.get())
+    }
+}
+
+/// Represents a distribution of observed values.
+///
+/// # Type Parameters
+/// * `N` - Number of buckets, excluding the implicit `+Inf` one.
+pub struct Histogram<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    bounds: [u32; N],
+    buckets: [AtomicU32; N],
+    count: AtomicU32,
+    sum: AtomicU32,
+}
+
+impl<const N: usize> Histogram<N> {
+    /// Creates a new `Histogram` instance.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the histogram.
+    /// * `help` - The description of the histogram.
+    /// * `bounds` - The inclusive upper bounds of the buckets, ascending.
+    #[must_use]
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        bounds: [u32; N],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            bounds,
+            buckets: [const { AtomicU32::new(0) }; N],
+            count: AtomicU32::new(0),
+            sum: AtomicU32::new(0),
+        }
+    }
+
+    /// Records a value.
+    ///
+    /// # Arguments
+    /// * `value` - The observed value.
+    pub fn observe(&self, value: u32) {
+        if let Some(i) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Returns the cumulative bucket counts, as exposed by Prometheus.
+    ///
+    /// # Returns
+    /// For each bound, the number of observed values lower or equal to it.
+    fn cumulative(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.bounds
+            .iter()
+            .zip(&self.buckets)
+            .scan(0, |total, (bound, n)| {
+                *total += n.load(Ordering::Relaxed);
+                Some((*bound, *total))
+            })
+    }
+}
+
+impl<const N: usize> Metric for Histogram<N> {
+    /// Writes the cumulative buckets, the sum and the count.
+    fn prometheus(&self, out: &mut String) -> fmt::Result {
+        let count = self.count.load(Ordering::Relaxed);
+
+        header(out, self.name, self.help, "histogram")?;
+        for (bound, total) in self.cumulative() {
+            writeln!(out, "{}_bucket{{le=\"{bound}\"}} {total}", self.name)?;
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {count}", self.name)?;
+        writeln!(
+            out,
+            "{}_sum {}",
+            self.name,
+            self.sum.load(Ordering::Relaxed)
+        )?;
+        writeln!(out, "{}_count {count}", self.name)
+    }
+
+    /// Writes the cumulative buckets, the sum and the count as an object.
+    fn json(&self, out: &mut String) -> fmt::Result {
+        write!(out, "\"{}\":{{\"buckets\":{{", self.name)?;
+        for (bound, total) in self.cumulative() {
+            write!(out, "\"{bound}\":{total},")?;
+        }
+        write!(
+            out,
+            "\"+Inf\":{}}},\"sum\":{},\"count\":{}}}",
+            self.count.load(Ordering::Relaxed),
+            self.sum.load(Ordering::Relaxed),
+            self.count.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Represents a counter per variant of a flag enum, exposed with a label.
+///
+/// # Type Parameters
+/// * `T` - Type of the flags.
+/// * `N` - Number of variants of `T`.
+pub struct PerFlag<T, const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    counters: [AtomicU32; N],
+    marker: PhantomData<fn(T)>,
+}
+
+impl<T, const N: usize> PerFlag<T, N>
+where
+    T: Flag<N> + fmt::Debug,
+{
+    /// Creates a new `PerFlag` instance.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the counters.
+    /// * `help` - The description of the counters.
+    /// * `label` - The name of the label holding the variant.
+    #[must_use]
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            counters: [const { AtomicU32::new(0) }; N],
+            marker: PhantomData,
+        }
+    }
+
+    /// Increments the counter of a flag.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag whose counter is incremented.
+    pub fn inc(&self, flag: T) {
+        self.counters[flag.into() as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counter of a flag.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag whose counter is returned.
+    ///
+    /// # Returns
+    /// The number of increments, wrapping at `u32::MAX`.
+    #[must_use]
+    pub fn get(&self, flag: T) -> u32 {
+        self.counters[flag.into() as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl<T, const N: usize> Metric for PerFlag<T, N>
+where
+    T: Flag<N> + fmt::Debug,
+{
+    /// Writes one sample per flag, labelled with the flag.
+    fn prometheus(&self, out: &mut String) -> fmt::Result {
+        header(out, self.name, self.help, "counter")?;
+        for flag in T::ALL {
+            writeln!(
+                out,
+                "{}{{{}=\"{:?}\"}} {}",
+                self.name,
+                self.label,
+                flag,
+                self.get(flag)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the counters as an object keyed by flag.
+    fn json(&self, out: &mut String) -> fmt::Result {
+        write!(out, "\"{}\":{{", self.name)?;
+        for (i, flag) in T::ALL.into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(out, "{separator}\"{:?}\":{}", flag, self.get(flag))?;
+        }
+        write!(out, "}}")
+    }
+}
+
+/// Represents the stack high-water marks of the watched tasks.
+struct Stacks {
+    name: &'static str,
+    help: &'static str,
+}
+
+impl Metric for Stacks {
+    /// Writes one sample per watched task, labelled with the task.
+    fn prometheus(&self, out: &mut String) -> fmt::Result {
+        header(out, self.name, self.help, "gauge")?;
+        for (task, free) in watchdog::stack_high_water_marks() {
+            writeln!(out, "{}{{task=\"{task}\"}} {free}", self.name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the high-water marks as an object keyed by task.
+    fn json(&self, out: &mut String) -> fmt::Result {
+        write!(out, "\"{}\":{{", self.name)?;
+        for (i, (task, free)) in
+            watchdog::stack_high_water_marks().into_iter().enumerate()
+        {
+            let separator = if i == 0 { "" } else { "," };
+            write!(out, "{separator}\"{task}\":{free}")?;
+        }
+        write!(out, "}}")
+    }
+}
+
+/// Locks a mutex, counting contentions and ignoring poisoning.
+///
+/// # Arguments
+/// * `mutex` - The mutex to lock.
+///
+/// # Returns
+/// The guard of the mutex.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            CONTENTIONS.inc();
+            mutex.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+/// Updates the gauges that are not maintained by their subsystem.
+pub fn sample() {
+    HEAP_FREE.set(unsafe { esp_get_free_heap_size() });
+    HEAP_LOW_WATER.set(unsafe { esp_get_minimum_free_heap_size() });
+
+    let scans = u64::from(SCANS.get());
+    if scans > 0 {
+        let rate = u64::from(SCAN_HITS.get()) * 100 / scans;
+        SCAN_HIT_RATE.set(u32::try_from(rate).unwrap_or(100));
+    }
+}
+
+/// Exposes every metric in the Prometheus text format.
+///
+/// # Returns
+/// The metrics, one sample per line.
+#[must_use]
+pub fn prometheus() -> String {
+    sample();
+
+    let mut out = String::new();
+    for metric in METRICS {
+        // Writing to a `String` cannot fail.
+        let _ = metric.prometheus(&mut out);
+    }
+
+    out
+}
+
+/// Exposes every metric as a JSON object.
+///
+/// # Returns
+/// The metrics, keyed by name.
+#[must_use]
+pub fn json() -> String {
+    sample();
+
+    let mut out = String::from("{");
+    for (i, metric) in METRICS.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        // Writing to a `String` cannot fail.
+        let _ = metric.json(&mut out);
+    }
+    out.push('}');
+
+    out
+}
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use esp_idf_hal::sys::{
+    esp, esp_pm_config_t, esp_pm_configure, esp_sleep_enable_gpio_wakeup,
+    esp_sleep_enable_uart_wakeup, gpio_int_type_t_GPIO_INTR_LOW_LEVEL,
+    gpio_wakeup_enable, uart_port_t, uart_set_wakeup_threshold,
+};
+use log::debug;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Value of `MODE` before the first mode is applied.
+const UNSET: u8 = u8::MAX;
+
+/// Number of RX edges that wake the chip up from light sleep, the minimum
+/// ESP-IDF accepts.
+const UART_WAKEUP_EDGES: i32 = 3;
+
+/// Current power mode, `UNSET` until `init` is called.
+static MODE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Represents the CPU settings of a power mode.
+///
+/// # Fields
+/// * `max_freq_mhz` - Frequency of the CPU when a task is running.
+/// * `min_freq_mhz` - Frequency the CPU is scaled down to when idle.
+/// * `light_sleep` - Whether the chip light sleeps when all tasks are blocked.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Settings {
+    pub max_freq_mhz: i32,
+    pub min_freq_mhz: i32,
+    pub light_sleep: bool,
+}
+
+/// Represents a power mode.
+///
+/// The current figures are typical values of the ESP32 chip alone, quoted
+/// from Espressif's datasheet, not measurements of this board: the radio adds
+/// about 100 mA while it receives, and the board's LED and USB bridge draw
+/// extra.
+///
+/// The light sleep figures also need BLE modem sleep clocked by a 32 kHz
+/// crystal, as configured in `sdkconfig.defaults`. Without the crystal, e.g.
+/// on the Atom Lite, the BLE controller keeps the chip awake and `Idle` and
+/// `Standby` only save the frequency scaling (20 to 31 mA at 80 MHz).
+///
+/// # Variants
+/// * `Active` - Something is happening nearby: the CPU runs at full speed
+///   (30 to 68 mA).
+/// * `Idle` - The system is on but nothing is happening: the CPU is scaled
+///   down when idle (20 to 31 mA at 80 MHz) and light sleeps between events
+///   (0.8 mA).
+/// * `Standby` - The system is off: the CPU runs slowly and light sleeps
+///   between button polls (0.8 mA).
+#[derive(Clone, Copy, Debug, Eq, IntoPrimitive, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Mode {
+    Active,
+    Idle,
+    Standby,
+}
+
+impl Mode {
+    /// Selects the power mode for a system state.
+    ///
+    /// # Arguments
+    /// * `system_on` - Whether the system is switched on.
+    /// * `idle` - Whether the state machine declared itself idle.
+    ///
+    /// # Returns
+    /// The lowest power mode compatible with the system state.
+    #[must_use]
+    pub const fn select(system_on: bool, idle: bool) -> Self {
+        match (system_on, idle) {
+            (false, _) => Self::Standby,
+            (true, true) => Self::Idle,
+            (true, false) => Self::Active,
+        }
+    }
+
+    /// Returns the CPU settings of the mode.
+    ///
+    /// # Returns
+    /// The frequencies and light sleep setting to apply.
+    #[must_use]
+    pub const fn settings(self) -> Settings {
+        match self {
+            Self::Active => Settings {
+                max_freq_mhz: 240,
+                min_freq_mhz: 240,
+                light_sleep: false,
+            },
+            Self::Idle => Settings {
+                max_freq_mhz: 240,
+                min_freq_mhz: 80,
+                light_sleep: true,
+            },
+            Self::Standby => Settings {
+                max_freq_mhz: 80,
+                min_freq_mhz: 40,
+                light_sleep: true,
+            },
+        }
+    }
+}
+
+/// Initializes power management.
+///
+/// The button pin and the console UART are armed as light sleep wakeup
+/// sources, so that a press or a keystroke wakes the chip up, and the `Active`
+/// mode is applied. The character that wakes the chip up is lost.
+///
+/// # Arguments
+/// * `button` - The GPIO number of the button, active low.
+/// * `uart` - The port of the console UART.
+///
+/// # Errors
+/// Returns an error if a wakeup source or the mode cannot be configured.
+pub fn init(button: i32, uart: uart_port_t) -> Result<()> {
+    esp!(unsafe {
+        gpio_wakeup_enable(button, gpio_int_type_t_GPIO_INTR_LOW_LEVEL)
+    })?;
+    esp!(unsafe { esp_sleep_enable_gpio_wakeup() })?;
+    esp!(unsafe { uart_set_wakeup_threshold(uart, UART_WAKEUP_EDGES) })?;
+    esp!(unsafe { esp_sleep_enable_uart_wakeup(uart) })?;
+
+    set_mode(Mode::Active)
+}
+
+/// Applies a power mode, if it is not already applied.
+///
+/// # Arguments
+/// * `mode` - The power mode.
+///
+/// # Errors
+/// Returns an error if ESP-IDF rejects the settings of the mode.
+pub fn set_mode(mode: Mode) -> Result<()> {
+    if MODE.load(Ordering::Acquire) == u8::from(mode) {
+        return Ok(());
+    }
+
+    let settings = mode.settings();
+    let config = esp_pm_config_t {
+        max_freq_mhz: settings.max_freq_mhz,
+        min_freq_mhz: settings.min_freq_mhz,
+        light_sleep_enable: settings.light_sleep,
+    };
+    esp!(unsafe { esp_pm_configure(std::ptr::from_ref(&config).cast()) })?;
+    MODE.store(mode.into(), Ordering::Release);
+    debug!(mode:? = mode, settings:? = settings; "power mode applied");
+
+    Ok(())
+}
+
+/// Returns the current power mode.
+///
+/// # Returns
+/// The last applied mode, `None` before `init` is called.
+#[must_use]
+pub fn mode() -> Option<Mode> {
+    Mode::try_from(MODE.load(Ordering::Acquire)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [Mode; 3] = [Mode::Active, Mode::Idle, Mode::Standby];
+
+    #[test]
+    fn selects_lowest_mode() {
+        assert_eq!(Mode::select(false, false), Mode::Standby);
+        assert_eq!(Mode::select(false, true), Mode::Standby);
+        assert_eq!(Mode::select(true, true), Mode::Idle);
+        assert_eq!(Mode::select(true, false), Mode::Active);
+    }
+
+    #[test]
+    fn orders_settings() {
+        for mode in MODES {
+            let settings = mode.settings();
+            assert!(settings.min_freq_mhz <= settings.max_freq_mhz);
+        }
+        assert!(!Mode::Active.settings().light_sleep);
+        assert!(Mode::Idle.settings().light_sleep);
+        assert!(Mode::Standby.settings().light_sleep);
+        assert!(
+            Mode::Standby.settings().max_freq_mhz
+                < Mode::Idle.settings().max_freq_mhz
+        );
+    }
+
+    #[test]
+    fn encodes_modes() {
+        for mode in MODES {
+            assert_eq!(Mode::try_from(u8::from(mode)), Ok(mode));
+        }
+        assert!(Mode::try_from(UNSET).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use esp_idf_hal::{
+    gpio::{InputMode, InputPin, PinDriver},
+    reset::restart,
+};
+use log::{error, warn};
+use std::time::{Duration, Instant};
+
+use crate::{
+    color::BLUE, config::Store, crash, infra::Poller, light::Led, time::yield_now,
+    watchdog,
+};
+
+/// Represents the minimal firmware run after repeated crashes.
+///
+/// Only the button, the LED and the serial console are used: BLE is left off.
+/// The LED double flashes blue every second, and holding the button for
+/// `RESET_HOLD` resets the configuration to its defaults and restarts the
+/// device. The console, run separately, fixes the configuration without
+/// resetting it.
+///
+/// # Type Parameters
+/// * `'a` - Lifetime of the safe mode.
+/// * `T` - Type of the button's GPIO pin.
+/// * `MODE` - Input mode of the button's GPIO pin.
+pub struct SafeMode<'a, T, MODE>
+where
+    T: InputPin,
+    MODE: InputMode,
+{
+    led: Led<'a>,
+    pin: PinDriver<'a, T, MODE>,
+    store: Store,
+}
+
+impl<'a, T, MODE> SafeMode<'a, T, MODE>
+where
+    T: InputPin,
+    MODE: InputMode,
+{
+    /// Duration the button has to be held for to reset the configuration.
+    const RESET_HOLD: Duration = Duration::from_secs(5);
+    /// Period of the LED pattern.
+    const PATTERN_PERIOD: Duration = Duration::from_secs(1);
+    /// Duration of a flash of the LED pattern.
+    const FLASH: Duration = Duration::from_millis(100);
+
+    /// Creates a new `SafeMode` instance.
+    ///
+    /// # Arguments
+    /// * `led` - An LED controller.
+    /// * `pin` - The button's GPIO pin driver.
+    /// * `store` - The configuration store.
+    ///
+    /// # Errors
+    /// Returns an error if the LED cannot be set up.
+    pub fn new(
+        led: Led<'a>,
+        pin: PinDriver<'a, T, MODE>,
+        store: Store,
+    ) -> Result<Self> {
+        let mut led = led;
+        led.set_color(BLUE)?;
+
+        Ok(Self { led, pin, store })
+    }
+
+    /// Applies the LED pattern for a given time.
+    ///
+    /// The pattern is two flashes at the beginning of each period.
+    ///
+    /// # Arguments
+    /// * `elapsed` - Time elapsed since the start of the safe mode.
+    ///
+    /// # Errors
+    /// Returns an error if the LED cannot be updated.
+    fn blink(&mut self, elapsed: Duration) -> Result<()> {
+        let phase = elapsed.as_millis() % Self::PATTERN_PERIOD.as_millis();
+        let flash = Self::FLASH.as_millis();
+
+        if phase < flash || (2 * flash..3 * flash).contains(&phase) {
+            self.led.on()
+        } else {
+            self.led.off()
+        }
+    }
+
+    /// Resets the configuration and the crash counter.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration cannot be reset.
+    fn reset(&mut self) -> Result<()> {
+        warn!("Resetting configuration to defaults");
+
+        self.led.on()?;
+        self.store.reset()?;
+        crash::clear();
+
+        Ok(())
+    }
+}
+
+impl<T, MODE> Poller for SafeMode<'_, T, MODE>
+where
+    T: InputPin,
+    MODE: InputMode,
+{
+    /// Blinks the LED and watches for a long button press.
+    ///
+    /// # Errors
+    /// Returns an error if the LED, the configuration or the watchdog fails.
+    fn poll(&mut self) -> Result<!> {
+        error!("Too many consecutive crashes, starting in safe mode");
+
+        let start = Instant::now();
+        let mut pressed_since = None;
+
+        loop {
+            watchdog::feed()?;
+            self.blink(start.elapsed())?;
+
+            pressed_since = if self.pin.is_low() {
+                pressed_since.or_else(|| Some(Instant::now()))
+            } else {
+                None
+            };
+            if pressed_since.is_some_and(|since| since.elapsed() >= Self::RESET_HOLD)
+            {
+                self.reset()?;
+                restart();
+            }
+
+            yield_now();
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use esp_idf_hal::reset::restart;
-use std::thread;
+use anyhow::Result;
+use esp_idf_hal::{
+    cpu::Core, reset::restart, task::thread::ThreadSpawnConfiguration,
+};
+use std::{ffi::CStr, thread};
 
-use crate::time::sleep;
+use crate::{crash, time::sleep, watchdog};
 
 /// Handles program failure by restarting the device.
 ///
@@ -26,9 +29,127 @@ impl Drop for ExitGuard {
     }
 }
 
-/// Spawns a new thread with a failure guard.
+/// A builder for threads running as FreeRTOS tasks.
+///
+/// Threads are spawned with a failure guard and registered with the task
+/// watchdog, so the closure is expected to call `watchdog::feed` periodically.
+/// Errors returned by the closure are recorded as crashes.
+pub struct Builder {
+    name: &'static CStr,
+    stack_size: usize,
+    priority: u8,
+    core: Option<Core>,
+}
+
+impl Builder {
+    /// Default stack size of a thread, in bytes.
+    const DEFAULT_STACK_SIZE: usize = 4096;
+    /// Default FreeRTOS priority of a thread.
+    const DEFAULT_PRIORITY: u8 = 5;
+
+    /// Creates a new `Builder` instance.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the thread, also used as FreeRTOS task name.
+    ///
+    /// # Returns
+    /// A builder for an unpinned thread with default stack size and priority.
+    #[must_use]
+    pub fn new(name: &'static CStr) -> Self {
+        Self {
+            name,
+            stack_size: Self::DEFAULT_STACK_SIZE,
+            priority: Self::DEFAULT_PRIORITY,
+            core: None,
+        }
+    }
+
+    /// Sets the stack size of the thread.
+    ///
+    /// # Arguments
+    /// * `stack_size` - The stack size, in bytes.
+    #[must_use]
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Sets the FreeRTOS priority of the thread.
+    ///
+    /// # Arguments
+    /// * `priority` - The priority, higher values preempt lower ones.
+    #[must_use]
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Pins the thread to a CPU core.
+    ///
+    /// # Arguments
+    /// * `core` - The core to run the thread on.
+    #[must_use]
+    pub fn core(mut self, core: Core) -> Self {
+        self.core = Some(core);
+        self
+    }
+
+    /// Spawns the thread.
+    ///
+    /// The spawn configuration of ESP-IDF's pthread layer is local to the
+    /// calling thread, and applies to the threads it spawns next. It is reset
+    /// to the defaults afterwards, so that the other threads spawned from the
+    /// calling thread do not inherit it.
+    ///
+    /// # Arguments
+    /// * `f` - A closure to execute in the new thread.
+    ///
+    /// # Returns
+    /// A `JoinHandle` for the spawned thread.
+    ///
+    /// # Type Parameters
+    /// * `F` - The type of the closure.
+    /// * `T` - The return type of the closure.
+    ///
+    /// # Errors
+    /// Returns an error if the thread cannot be configured or spawned.
+    pub fn spawn<F, T>(self, f: F) -> Result<thread::JoinHandle<Result<T>>>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let name = self.name;
+
+        ThreadSpawnConfiguration {
+            name: Some(name.to_bytes_with_nul()),
+            stack_size: self.stack_size,
+            priority: self.priority,
+            pin_to_core: self.core,
+            ..Default::default()
+        }
+        .set()?;
+
+        let handle = thread::Builder::new()
+            .name(name.to_string_lossy().into_owned())
+            .stack_size(self.stack_size)
+            .spawn(move || {
+                let _guard = ExitGuard;
+                watchdog::watch(name).unwrap_or_else(|_| failure());
+                f().inspect_err(crash::record)
+            });
+
+        ThreadSpawnConfiguration::default().set()?;
+
+        Ok(handle?)
+    }
+}
+
+/// Spawns a new thread with the default configuration.
+///
+/// See `Builder` for details.
 ///
 /// # Arguments
+/// * `name` - The name of the thread.
 /// * `f` - A closure to execute in the new thread.
 ///
 /// # Returns
@@ -37,13 +158,16 @@ impl Drop for ExitGuard {
 /// # Type Parameters
 /// * `F` - The type of the closure.
 /// * `T` - The return type of the closure.
-pub fn spawn<F, T>(f: F) -> thread::JoinHandle<T>
+///
+/// # Errors
+/// Returns an error if the thread cannot be spawned.
+pub fn spawn<F, T>(
+    name: &'static CStr,
+    f: F,
+) -> Result<thread::JoinHandle<Result<T>>>
 where
-    F: FnOnce() -> T + Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
     T: Send + 'static,
 {
-    thread::spawn(move || {
-        let _guard = ExitGuard;
-        f()
-    })
+    Builder::new(name).spawn(f)
 }
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+use esp_idf_hal::sys::{
+    esp, esp_reset_reason, esp_reset_reason_t_ESP_RST_TASK_WDT,
+    esp_task_wdt_add_user, esp_task_wdt_delete_user, esp_task_wdt_reset_user,
+    esp_task_wdt_user_handle_t, esp_timer_get_time, uxTaskGetStackHighWaterMark,
+    xTaskGetCurrentTaskHandle,
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr},
+    ptr::{self, addr_of_mut},
+    sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+};
+
+/// Maximum number of tasks the watchdog can keep track of.
+const MAX_WATCHED: usize = 8;
+
+/// Maximum length of a task name kept across resets.
+const NAME_LEN: usize = 16;
+
+/// Marks a valid `Starved` record in RTC memory.
+const MAGIC: u32 = 0x5744_5447;
+
+/// Represents the last heartbeat of a watched task.
+///
+/// # Fields
+/// * `name` - Name of the task, null if the slot is free.
+/// * `last_beat` - Uptime of the last heartbeat, in milliseconds.
+/// * `task` - Handle of the FreeRTOS task.
+struct Slot {
+    name: AtomicPtr<c_char>,
+    last_beat: AtomicU32,
+    task: AtomicPtr<c_void>,
+}
+
+/// Heartbeats of the watched tasks, readable from the watchdog interrupt.
+static SLOTS: [Slot; MAX_WATCHED] = [const {
+    Slot {
+        name: AtomicPtr::new(ptr::null_mut()),
+        last_beat: AtomicU32::new(0),
+        task: AtomicPtr::new(ptr::null_mut()),
+    }
+}; MAX_WATCHED];
+
+/// Number of slots handed out so far.
+static LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Represents the task that starved the watchdog, kept across resets.
+///
+/// # Fields
+/// * `magic` - Equals `MAGIC` if the record is valid.
+/// * `name` - Null-terminated name of the task.
+#[repr(C)]
+struct Starved {
+    magic: u32,
+    name: [u8; NAME_LEN],
+}
+
+/// Record of the last starved task. Lives in RTC memory that is not cleared on
+/// reset, so it has to be validated with `MAGIC` before use.
+#[link_section = ".rtc_noinit"]
+static mut STARVED: Starved = Starved {
+    magic: 0,
+    name: [0; NAME_LEN],
+};
+
+thread_local! {
+    /// Heartbeat of the current thread, if it is watched.
+    static HEARTBEAT: RefCell<Option<Heartbeat>> = const { RefCell::new(None) };
+}
+
+/// Returns the uptime in milliseconds, wrapping after about 49 days.
+///
+/// # Returns
+/// The truncated uptime in milliseconds.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn uptime_ms() -> u32 {
+    (unsafe { esp_timer_get_time() } / 1000) as u32
+}
+
+/// Represents a task registered with the ESP-IDF task watchdog.
+///
+/// The task has to call `beat` more often than the watchdog timeout, otherwise
+/// the device panics and restarts.
+pub struct Heartbeat {
+    handle: esp_task_wdt_user_handle_t,
+    slot: usize,
+}
+
+impl Heartbeat {
+    /// Registers a new task with the watchdog.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the task, reported if it starves the watchdog.
+    ///
+    /// # Errors
+    /// Returns an error if too many tasks are watched or if the task cannot be
+    /// registered.
+    pub fn new(name: &'static CStr) -> Result<Self> {
+        let slot = LEN
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < MAX_WATCHED).then_some(len + 1)
+            })
+            .map_err(|_| anyhow!("Too many watched tasks"))?;
+
+        let mut handle = ptr::null_mut();
+        esp!(unsafe { esp_task_wdt_add_user(name.as_ptr(), &mut handle) })?;
+
+        SLOTS[slot].last_beat.store(uptime_ms(), Ordering::Relaxed);
+        SLOTS[slot].task.store(
+            unsafe { xTaskGetCurrentTaskHandle() }.cast(),
+            Ordering::Relaxed,
+        );
+        SLOTS[slot]
+            .name
+            .store(name.as_ptr().cast_mut(), Ordering::Release);
+
+        Ok(Self { handle, slot })
+    }
+
+    /// Feeds the watchdog on behalf of the task.
+    ///
+    /// # Errors
+    /// Returns an error if the watchdog cannot be fed.
+    pub fn beat(&self) -> Result<()> {
+        esp!(unsafe { esp_task_wdt_reset_user(self.handle) })?;
+        SLOTS[self.slot]
+            .last_beat
+            .store(uptime_ms(), Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl Drop for Heartbeat {
+    /// Unregisters the task from the watchdog.
+    fn drop(&mut self) {
+        SLOTS[self.slot]
+            .name
+            .store(ptr::null_mut(), Ordering::Release);
+        unsafe {
+            esp_task_wdt_delete_user(self.handle);
+        }
+    }
+}
+
+/// Registers the current thread with the watchdog.
+///
+/// # Arguments
+/// * `name` - Name of the thread, reported if it starves the watchdog.
+///
+/// # Errors
+/// Returns an error if the thread cannot be registered.
+pub fn watch(name: &'static CStr) -> Result<()> {
+    let heartbeat = Heartbeat::new(name)?;
+    HEARTBEAT.with(|cell| cell.replace(Some(heartbeat)));
+
+    Ok(())
+}
+
+/// Feeds the watchdog on behalf of the current thread.
+///
+/// This is a no-op if the current thread is not watched.
+///
+/// # Errors
+/// Returns an error if the watchdog cannot be fed.
+pub fn feed() -> Result<()> {
+    HEARTBEAT.with(|cell| cell.borrow().as_ref().map_or(Ok(()), Heartbeat::beat))
+}
+
+/// Returns the task that starved the watchdog, if it caused the last reset.
+///
+/// The record is cleared, so subsequent calls return `None`.
+///
+/// # Returns
+/// The name of the task that starved the watchdog, if any.
+#[must_use]
+pub fn starved() -> Option<String> {
+    let reason = unsafe { esp_reset_reason() };
+    let starved = unsafe { &mut *addr_of_mut!(STARVED) };
+
+    let name = (reason == esp_reset_reason_t_ESP_RST_TASK_WDT
+        && starved.magic == MAGIC)
+        .then(|| {
+            CStr::from_bytes_until_nul(&starved.name).map_or_else(
+                |_| "<invalid>".into(),
+                |name| name.to_string_lossy().into_owned(),
+            )
+        });
+    starved.magic = 0;
+
+    name
+}
+
+/// Returns the stack high-water marks of the watched tasks.
+///
+/// # Returns
+/// The name of each watched task with the lowest amount of free stack it had
+/// since it started, in bytes.
+#[must_use]
+pub fn stack_high_water_marks() -> Vec<(String, u32)> {
+    SLOTS
+        .iter()
+        .filter_map(|slot| {
+            let name = slot.name.load(Ordering::Acquire);
+            let task = slot.task.load(Ordering::Relaxed);
+            (!name.is_null()).then(|| {
+                (
+                    unsafe { CStr::from_ptr(name) }
+                        .to_string_lossy()
+                        .into_owned(),
+                    unsafe { uxTaskGetStackHighWaterMark(task.cast()) },
+                )
+            })
+        })
+        .collect()
+}
+
+/// Records the task with the oldest heartbeat before the watchdog panics.
+///
+/// This overrides the weak handler ESP-IDF calls from the watchdog interrupt,
+/// so it must neither allocate nor lock.
+#[no_mangle]
+extern "C" fn esp_task_wdt_isr_user_handler() {
+    let now = uptime_ms();
+    let oldest = SLOTS
+        .iter()
+        .filter_map(|slot| {
+            let name = slot.name.load(Ordering::Acquire);
+            let age = now.wrapping_sub(slot.last_beat.load(Ordering::Relaxed));
+            (!name.is_null()).then_some((name, age))
+        })
+        .max_by_key(|(_, age)| *age);
+
+    if let Some((name, _)) = oldest {
+        let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+        let len = name.len().min(NAME_LEN - 1);
+        let starved = unsafe { &mut *addr_of_mut!(STARVED) };
+
+        starved.name[..len].copy_from_slice(&name[..len]);
+        starved.name[len] = 0;
+        starved.magic = MAGIC;
+    }
+}